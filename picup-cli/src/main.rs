@@ -1,46 +1,824 @@
-use clap::{arg, command, ArgAction};
-use picup_lib::{picup, Result, UploadImgParam};
+use std::{
+    env,
+    fs::read_to_string,
+    io::{self, Write},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-fn main() -> Result<()> {
+use clap::{arg, command, ArgAction, ArgMatches, Command};
+use picup_lib::{
+    asset_meta, encode_metadata, list_categories, picup_with_options, server_version, Error,
+    Metadata, OverridePolicy, PicupError, PicupOptions, ResponseCode, Result, UploadImgParam,
+    UploadReport, UploadedImage, METADATA_HEADER,
+};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::{json, Value};
+
+const DEFAULT_API_URL: &str = "http://127.0.0.1:19190";
+
+const TOKEN_ENV: &str = "PICUP_TOKEN";
+
+/// Exit codes besides 0 for success and 1 for anything else, 2 is also used by clap itself.
+const EXIT_USAGE: u8 = 2;
+const EXIT_AUTH: u8 = 3;
+const EXIT_NOT_FOUND: u8 = 4;
+const EXIT_NETWORK: u8 = 5;
+const EXIT_PARTIAL: u8 = 6;
+
+/// Errors of the cli itself, on top of those of [`picup_lib`].
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    PartialFailure { failed: usize, total: usize },
+    Unreachable(String),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}", msg),
+            CliError::PartialFailure { failed, total } => {
+                write!(f, "{} of {} images failed to upload", failed, total)
+            }
+            CliError::Unreachable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+fn exit_code(e: &Error) -> u8 {
+    if let Some(e) = e.downcast_ref::<CliError>() {
+        return match e {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::PartialFailure { .. } => EXIT_PARTIAL,
+            CliError::Unreachable(_) => EXIT_NETWORK,
+        };
+    }
+
+    if let Some(PicupError::Response { code, .. }) = e.downcast_ref::<PicupError>() {
+        return match *code {
+            ResponseCode::INVALID_TOKEN | ResponseCode::FORBIDDEN => EXIT_AUTH,
+            ResponseCode::INVALID_CATEGORY
+            | ResponseCode::FILE_NOT_FOUND
+            | ResponseCode::NOT_FOUND => EXIT_NOT_FOUND,
+            _ => 1,
+        };
+    }
+
+    if e.downcast_ref::<reqwest::Error>().is_some() {
+        return EXIT_NETWORK;
+    }
+
+    match e.downcast_ref::<io::Error>() {
+        // an image to upload that doesn't exist
+        Some(e) if e.kind() == io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        _ => 1,
+    }
+}
+
+fn run() -> Result<()> {
     let mut matches = command!()
         .args(&[
-            arg!(-o --"override"            "Override existing images in the server.")
+            arg!(-o --"override"            "Override existing images in the server, same as --overwrite-policy always.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("overwrite-policy"),
+            arg!(--"overwrite-policy" <policy> "What to do with images existing in the server: reject the upload (never), replace them (always), replace them unless they are the same (if-different), or upload under a free name (rename). Default: never")
+                .value_parser(["never", "always", "if-different", "rename"]),
+            arg!(--cache                    "Skip re-hosting remote images that haven't changed since the last upload.")
+                .action(ArgAction::SetTrue),
+            arg!(--"continue-on-error"      "Upload the images one by one, keep going when some of them fail and report them at the end.")
                 .action(ArgAction::SetTrue),
             arg!(-c --category <category>   "Category uploading the images to.")
                 .required(true),
-            arg!(-t --token <token>         "Token for access to uploading images to the server.")
-                .required(true),
+            arg!(-t --token <token>         "Token for access to uploading images to the server. Overrides PICUP_TOKEN and --token-file."),
+            arg!(--"token-file" <path>      "File containing the token, used if neither --token nor PICUP_TOKEN is given."),
             arg!(-u --"api-url" <url>       "\"/upload\" api url prefix for PicUp server. Default: http://127.0.0.1:19190"),
+            arg!(-q --quiet                 "Don't print the upload summary to stderr.")
+                .action(ArgAction::SetTrue),
+            arg!(--first                    "Print the url of the only image given without a newline, for command substitution. Fails if more images are given.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("format"),
+            arg!(--format <format>          "Output format, \"json\" prints the urls and what the server did with each file along with the summary as one object.")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+            arg!(--map <mapping>            "Upload an image to another category than --category, as PATH=CATEGORY, or PATH=CATEGORY:override to also override it. Can be repeated, and the image needn't be listed again.")
+                .action(ArgAction::Append),
+            arg!(--meta <pair>              "Metadata of the images such as their alt text, as KEY=VALUE like alt='A cat'. Keys may contain letters, digits, \"-\", \"_\" and \".\". Can be repeated.")
+                .action(ArgAction::Append),
             arg!([images]                   "File paths for images to be uploaded.")
-                .required(true)
+                .required_unless_present("map")
                 .num_args(0..),
         ])
+        .subcommand(
+            Command::new("categories")
+                .about("List categories available in the server and their constraints.")
+                .args(&[
+                    arg!(-t --token <token>     "Token for access to the server. Overrides PICUP_TOKEN and --token-file."),
+                    arg!(--"token-file" <path>  "File containing the token, used if neither --token nor PICUP_TOKEN is given."),
+                    arg!(-u --"api-url" <url>   "Api url prefix for PicUp server. Default: http://127.0.0.1:19190")
+                        .visible_alias("url"),
+                ]),
+        )
+        .subcommand(
+            Command::new("ping")
+                .about("Check that the server is reachable and the token is valid before uploading.")
+                .args(&[
+                    arg!(-t --token <token>     "Token for access to the server. Overrides PICUP_TOKEN and --token-file."),
+                    arg!(--"token-file" <path>  "File containing the token, used if neither --token nor PICUP_TOKEN is given."),
+                    arg!(-u --"api-url" <url>   "Api url prefix for PicUp server. Default: http://127.0.0.1:19190")
+                        .visible_alias("url"),
+                ]),
+        )
+        .subcommand(
+            Command::new("meta")
+                .about("Print the url, size and metadata of an uploaded image.")
+                .args(&[
+                    arg!(-c --category <category> "Category of the image.")
+                        .required(true),
+                    arg!(-u --"api-url" <url>   "Api url prefix for PicUp server. Default: http://127.0.0.1:19190")
+                        .visible_alias("url"),
+                    arg!(<name>                 "Name the image is stored under."),
+                ]),
+        )
+        .subcommand(
+            Command::new("version")
+                .about("Print versions of this client and the server.")
+                .arg(
+                    arg!(-u --"api-url" <url>   "Api url prefix for PicUp server. Default: http://127.0.0.1:19190")
+                        .visible_alias("url"),
+                ),
+        )
+        .after_help(
+            "Exit codes: 0 success, 1 other errors, 2 usage errors, 3 invalid token, \
+             4 image or category not found, 5 network errors, 6 some images of a \
+             --continue-on-error batch failed.",
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_precedence_over_arg(true)
+        .subcommand_negates_reqs(true)
         .get_matches();
 
+    if let Some((name, sub_matches)) = matches.remove_subcommand() {
+        return match name.as_str() {
+            "categories" => categories(sub_matches),
+            "ping" => ping(sub_matches),
+            "meta" => meta(sub_matches),
+            "version" => version(sub_matches),
+            _ => unreachable!(),
+        };
+    }
+
     let category = matches.remove_one::<String>("category").unwrap();
 
-    let token = matches.remove_one::<String>("token").unwrap();
+    let token = token(&mut matches)?;
 
-    let api_url = matches
-        .remove_one::<String>("api-url")
-        .unwrap_or("http://127.0.0.1:19190".to_string());
+    let api_url = api_url(&mut matches);
 
     let paths = matches
         .remove_many::<String>("images")
-        .unwrap()
-        .collect::<Vec<String>>();
+        .map(|paths| paths.collect::<Vec<String>>())
+        .unwrap_or_default();
+
+    let r#override = match matches
+        .get_one::<String>("overwrite-policy")
+        .map(String::as_str)
+    {
+        Some("always") => OverridePolicy::Always,
+        Some("if-different") => OverridePolicy::IfDifferent,
+        Some("rename") => OverridePolicy::Rename,
+        _ => matches.get_flag("override").into(),
+    };
 
-    let r#override = matches.get_flag("override");
+    let mappings = matches
+        .remove_many::<String>("map")
+        .map(|mappings| mappings.collect::<Vec<String>>())
+        .unwrap_or_default();
 
-    let urls = picup(
-        &api_url,
-        &paths,
-        &UploadImgParam::new(&token, 0, &category, r#override),
+    let targets = targets(&token, &paths, &category, r#override, &mappings)?;
+
+    let metadata = metadata(
+        &matches
+            .remove_many::<String>("meta")
+            .map(|pairs| pairs.collect::<Vec<String>>())
+            .unwrap_or_default(),
     )?;
 
-    for url in urls {
-        println!("{}", url);
+    let mut part_headers = HeaderMap::new();
+
+    if !metadata.is_empty() {
+        part_headers.insert(
+            METADATA_HEADER,
+            HeaderValue::try_from(encode_metadata(&metadata))?,
+        );
+    }
+
+    let options = PicupOptions {
+        remote_cache: matches.get_flag("cache"),
+        part_headers,
+    };
+
+    let output = Output {
+        quiet: matches.get_flag("quiet"),
+        json: matches.get_one::<String>("format").unwrap() == "json",
+        first: matches.get_flag("first"),
+    };
+
+    if output.first && targets.len() != 1 {
+        return Err(CliError::Usage(format!(
+            "--first takes exactly one image, {} given",
+            targets.len()
+        ))
+        .into());
     }
 
+    if !mappings.is_empty() && !output.quiet && !output.json {
+        for (path, param) in &targets {
+            eprintln!("{} -> {}", path, param.category());
+        }
+    }
+
+    if matches.get_flag("continue-on-error") {
+        return upload_each(&api_url, &targets, &options, &output);
+    }
+
+    let mut images = vec![None; targets.len()];
+    let mut summary = Summary::default();
+
+    // one request per category, in the order they first appear
+    for i in 0..targets.len() {
+        if images[i].is_some() {
+            continue;
+        }
+
+        let param = &targets[i].1;
+
+        let group = (i..targets.len())
+            .filter(|j| {
+                let other = &targets[*j].1;
+                other.category() == param.category() && other.r#override() == param.r#override()
+            })
+            .collect::<Vec<usize>>();
+
+        let paths = group
+            .iter()
+            .map(|j| &targets[*j].0)
+            .collect::<Vec<&String>>();
+
+        let report = picup_with_options(&api_url, &paths, param, &options)?;
+        summary.add(&report);
+
+        for (j, image) in group.into_iter().zip(report.images()) {
+            images[j] = Some(image.clone());
+        }
+    }
+
+    let images = images.into_iter().flatten().collect::<Vec<UploadedImage>>();
+
+    output.print(&images, &summary, &[]);
+
     Ok(())
 }
+
+/// Paths along with what they are uploaded with, which is `category` and `override` unless
+/// mapped otherwise by `--map`. Mapped paths not among `paths` are appended.
+fn targets(
+    token: &str,
+    paths: &[String],
+    category: &str,
+    r#override: OverridePolicy,
+    mappings: &[String],
+) -> Result<Vec<(String, UploadImgParam)>> {
+    let mut targets = paths
+        .iter()
+        .map(|path| {
+            (
+                path.to_string(),
+                UploadImgParam::new(token, 0, category, r#override),
+            )
+        })
+        .collect::<Vec<(String, UploadImgParam)>>();
+
+    for mapping in mappings {
+        // the path may contain "=" itself
+        let (path, target) = mapping.rsplit_once('=').ok_or_else(|| {
+            CliError::Usage(format!(
+                "invalid --map [{}], PATH=CATEGORY expected",
+                mapping
+            ))
+        })?;
+
+        let (category, r#override) = match target.split_once(':') {
+            Some((category, "override")) => (category, OverridePolicy::Always),
+            Some(_) => {
+                return Err(CliError::Usage(format!(
+                    "invalid --map [{}], only \":override\" may follow the category",
+                    mapping
+                ))
+                .into())
+            }
+            None => (target, r#override),
+        };
+
+        if category.is_empty() {
+            return Err(CliError::Usage(format!("no category in --map [{}]", mapping)).into());
+        }
+
+        let param = UploadImgParam::new(token, 0, category, r#override);
+
+        match targets.iter_mut().find(|(p, _)| p == path) {
+            Some(target) => target.1 = param,
+            None => targets.push((path.to_string(), param)),
+        }
+    }
+
+    Ok(targets)
+}
+
+/// How the results of an upload are printed.
+struct Output {
+    quiet: bool,
+    json: bool,
+
+    /// print the only url without a newline
+    first: bool,
+}
+
+impl Output {
+    fn print(&self, images: &[UploadedImage], summary: &Summary, failures: &[(&String, Error)]) {
+        let urls = images.iter().map(UploadedImage::url).collect::<Vec<_>>();
+
+        let action_names = images
+            .iter()
+            .map(|image| image.action().map(|action| action.name()))
+            .collect::<Vec<_>>();
+
+        if self.json {
+            let mut out = json!({
+                "urls": urls,
+                "actions": action_names,
+                "files": images
+                    .iter()
+                    .zip(&action_names)
+                    .map(|(image, action)| json!({
+                        "submitted_name": image.submitted_name(),
+                        "stored_name": image.stored_name(),
+                        "url": image.url(),
+                        "action": action,
+                        "job": image.job(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "summary": summary.to_json(),
+            });
+
+            if !failures.is_empty() {
+                out["failures"] = failures
+                    .iter()
+                    .map(|(path, e)| json!({ "path": path, "error": e.to_string() }))
+                    .collect();
+            }
+
+            println!("{}", out);
+            return;
+        }
+
+        if self.first {
+            if let Some(url) = urls.first() {
+                print!("{}", url);
+                let _ = io::stdout().flush();
+            }
+        } else {
+            for url in urls {
+                println!("{}", url);
+            }
+        }
+
+        if !self.quiet {
+            // unknown for those reused from the cache and with older servers
+            for (image, action) in images.iter().zip(&action_names) {
+                match (action, image.submitted_name()) {
+                    (Some(action), Some(name)) => {
+                        eprintln!("{} {} -> {}", action, name, image.url())
+                    }
+                    (Some(action), None) => eprintln!("{} {}", action, image.url()),
+                    _ => {}
+                }
+            }
+
+            eprintln!("{}", summary);
+        }
+    }
+}
+
+/// Totals of what has been sent to the server.
+#[derive(Default)]
+struct Summary {
+    files: usize,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Summary {
+    fn add(&mut self, report: &UploadReport) {
+        self.files += report.files();
+        self.bytes += report.bytes();
+        self.elapsed += report.elapsed();
+    }
+
+    /// Average throughput in MB/s.
+    fn speed(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+
+        if secs == 0.0 {
+            return 0.0;
+        }
+
+        self.bytes as f64 / 1_000_000.0 / secs
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "files": self.files,
+            "bytes": self.bytes,
+            "seconds": self.elapsed.as_secs_f64(),
+            "mb_per_sec": self.speed(),
+        })
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files, {:.2} MB in {:.2}s ({:.2} MB/s)",
+            self.files,
+            self.bytes as f64 / 1_000_000.0,
+            self.elapsed.as_secs_f64(),
+            self.speed()
+        )
+    }
+}
+
+fn upload_each(
+    api_url: &str,
+    targets: &[(String, UploadImgParam)],
+    options: &PicupOptions,
+    output: &Output,
+) -> Result<()> {
+    let mut images = vec![];
+    let mut summary = Summary::default();
+    let mut failures = vec![];
+
+    for (path, param) in targets {
+        match picup_with_options(api_url, &[path], param, options) {
+            Ok(report) => {
+                summary.add(&report);
+                images.extend(report.images().iter().cloned());
+            }
+            Err(e) => failures.push((path, e)),
+        }
+    }
+
+    output.print(&images, &summary, &failures);
+
+    if !output.json {
+        eprintln!(
+            "{} uploaded, {} failed.",
+            targets.len() - failures.len(),
+            failures.len()
+        );
+
+        for (path, e) in &failures {
+            eprintln!("  {}: {}", path, e);
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(CliError::PartialFailure {
+            failed: failures.len(),
+            total: targets.len(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Metadata of `--meta` pairs, which the server validates.
+fn metadata(pairs: &[String]) -> Result<Metadata> {
+    let mut metadata = Metadata::new();
+
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').ok_or_else(|| {
+            CliError::Usage(format!("invalid --meta [{}], KEY=VALUE expected", pair))
+        })?;
+
+        metadata.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(metadata)
+}
+
+fn categories(mut matches: ArgMatches) -> Result<()> {
+    let token = token(&mut matches)?;
+
+    let api_url = api_url(&mut matches);
+
+    for category in list_categories(&api_url, &token)? {
+        let max_size = match category.max_size() {
+            0 => "none".to_string(),
+            max_size => max_size.to_string(),
+        };
+
+        let extensions = category
+            .allowed_extensions()
+            .map_or("any".to_string(), |extensions| extensions.join(","));
+
+        println!(
+            "{}\tallow_all_files={}\tmax_size={}\textensions={}",
+            category.name(),
+            category.allow_all_files(),
+            max_size,
+            extensions
+        );
+    }
+
+    Ok(())
+}
+
+fn ping(mut matches: ArgMatches) -> Result<()> {
+    let token = token(&mut matches)?;
+
+    let api_url = api_url(&mut matches);
+
+    let started = Instant::now();
+
+    let server = server_version(&api_url).map_err(|e| unreachable(&api_url, e))?;
+
+    println!(
+        "reachable: {} in {} ms, server {} ({}, {})",
+        api_url,
+        started.elapsed().as_millis(),
+        server.version(),
+        server.profile(),
+        server.target()
+    );
+
+    let started = Instant::now();
+
+    let status =
+        token_status(list_categories(&api_url, &token)).map_err(|e| unreachable(&api_url, e))?;
+
+    println!("token: {} in {} ms", status, started.elapsed().as_millis());
+
+    Ok(())
+}
+
+/// What listing the categories tells of the token: a scoped token without the read scope is
+/// refused as forbidden, yet valid, unlike one the server doesn't know.
+fn token_status<T>(listed: Result<T>) -> Result<&'static str> {
+    match listed {
+        Ok(_) => Ok("valid"),
+        Err(e) => match e.downcast_ref::<PicupError>() {
+            Some(PicupError::Response {
+                code: ResponseCode::FORBIDDEN,
+                ..
+            }) => Ok("valid, but not scoped for reading"),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Tells connection failures and timeouts apart from other errors.
+fn unreachable(api_url: &str, e: Error) -> Error {
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(re) if re.is_timeout() => {
+            CliError::Unreachable(format!("timed out connecting to {}", api_url)).into()
+        }
+        Some(re) if re.is_connect() => {
+            CliError::Unreachable(format!("cannot connect to {}, is the server up?", api_url))
+                .into()
+        }
+        _ => e,
+    }
+}
+
+fn meta(mut matches: ArgMatches) -> Result<()> {
+    let api_url = api_url(&mut matches);
+
+    let category = matches.remove_one::<String>("category").unwrap();
+    let name = matches.remove_one::<String>("name").unwrap();
+
+    let asset = asset_meta(&api_url, &category, &name)?;
+
+    println!("{}\t{} bytes", asset.url(), asset.size());
+
+    for (key, value) in asset.metadata() {
+        println!("{}={}", key, value);
+    }
+
+    Ok(())
+}
+
+fn version(mut matches: ArgMatches) -> Result<()> {
+    let api_url = api_url(&mut matches);
+
+    let client_version = env!("CARGO_PKG_VERSION");
+
+    println!("client: {}", client_version);
+
+    let server = server_version(&api_url)?;
+
+    println!(
+        "server: {} ({}, {})",
+        server.version(),
+        server.profile(),
+        server.target()
+    );
+
+    if !server.is_compatible_with(client_version) {
+        eprintln!(
+            "warning: server version {} may be incompatible with client version {}",
+            server.version(),
+            client_version
+        );
+    }
+
+    Ok(())
+}
+
+/// Token from `--token`, `PICUP_TOKEN` or `--token-file`, in order of precedence.
+fn token(matches: &mut ArgMatches) -> Result<String> {
+    if let Some(token) = matches.remove_one::<String>("token") {
+        return Ok(token);
+    }
+
+    if let Ok(token) = env::var(TOKEN_ENV) {
+        return Ok(token);
+    }
+
+    if let Some(path) = matches.remove_one::<String>("token-file") {
+        let token = read_to_string(&path)
+            .map_err(|e| CliError::Usage(format!("failed to read token file [{}]: {}", path, e)))?;
+
+        return Ok(token.trim().to_string());
+    }
+
+    Err(CliError::Usage(format!(
+        "no token provided, use --token, {} or --token-file",
+        TOKEN_ENV
+    ))
+    .into())
+}
+
+fn api_url(matches: &mut ArgMatches) -> String {
+    matches
+        .remove_one::<String>("api-url")
+        .unwrap_or(DEFAULT_API_URL.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets_of(
+        paths: &[&str],
+        mappings: &[&str],
+    ) -> Result<Vec<(String, String, OverridePolicy)>> {
+        let paths = paths
+            .iter()
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>();
+        let mappings = mappings
+            .iter()
+            .map(|mapping| mapping.to_string())
+            .collect::<Vec<_>>();
+
+        Ok(
+            targets("baka", &paths, "pic", OverridePolicy::Never, &mappings)?
+                .into_iter()
+                .map(|(path, param)| (path, param.category().clone(), param.r#override()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_targets() {
+        assert_eq!(
+            targets_of(
+                &["a.png", "b.png"],
+                &["b.png=files", "c=d.png=other:override"]
+            )
+            .unwrap(),
+            [
+                (
+                    "a.png".to_string(),
+                    "pic".to_string(),
+                    OverridePolicy::Never
+                ),
+                (
+                    "b.png".to_string(),
+                    "files".to_string(),
+                    OverridePolicy::Never
+                ),
+                (
+                    "c=d.png".to_string(),
+                    "other".to_string(),
+                    OverridePolicy::Always
+                ),
+            ]
+        );
+
+        for mapping in ["a.png", "a.png=", "a.png=:override", "a.png=pic:replace"] {
+            let e = targets_of(&[], &[mapping]).unwrap_err();
+
+            assert!(
+                matches!(e.downcast_ref::<CliError>(), Some(CliError::Usage(_))),
+                "{}",
+                mapping
+            );
+            assert_eq!(exit_code(&e), EXIT_USAGE);
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let pairs = ["alt=a cat".to_string(), "caption=x=y".to_string()];
+
+        let parsed = metadata(&pairs).unwrap();
+        assert_eq!(parsed["alt"], "a cat");
+        assert_eq!(parsed["caption"], "x=y");
+
+        let e = metadata(&["alt".to_string()]).unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_USAGE);
+    }
+
+    #[test]
+    fn test_token_status() {
+        let response = |code| -> Result<()> {
+            Err(PicupError::Response {
+                code,
+                msg: String::new(),
+            }
+            .into())
+        };
+
+        assert_eq!(token_status(Ok(())).unwrap(), "valid");
+        assert_eq!(
+            token_status(response(ResponseCode::FORBIDDEN)).unwrap(),
+            "valid, but not scoped for reading"
+        );
+
+        let e = token_status(response(ResponseCode::INVALID_TOKEN)).unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_AUTH);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let response = |code| -> Error {
+            PicupError::Response {
+                code,
+                msg: String::new(),
+            }
+            .into()
+        };
+
+        for (e, expected) in [
+            (response(ResponseCode::INVALID_TOKEN), EXIT_AUTH),
+            (response(ResponseCode::FORBIDDEN), EXIT_AUTH),
+            (response(ResponseCode::INVALID_CATEGORY), EXIT_NOT_FOUND),
+            (response(ResponseCode::FILE_NOT_FOUND), EXIT_NOT_FOUND),
+            (response(ResponseCode::FILE_EXISTED), 1),
+            (
+                CliError::PartialFailure {
+                    failed: 1,
+                    total: 2,
+                }
+                .into(),
+                EXIT_PARTIAL,
+            ),
+            (CliError::Unreachable(String::new()).into(), EXIT_NETWORK),
+            (
+                io::Error::new(io::ErrorKind::NotFound, "a.png").into(),
+                EXIT_NOT_FOUND,
+            ),
+            (
+                io::Error::new(io::ErrorKind::PermissionDenied, "a.png").into(),
+                1,
+            ),
+        ] {
+            assert_eq!(exit_code(&e), expected, "{}", e);
+        }
+    }
+}
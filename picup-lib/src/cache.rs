@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read_to_string, write},
+    path::PathBuf,
+};
+
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Validators of a remote image seen before, and where it has been uploaded to.
+#[derive(Serialize, Deserialize, Default)]
+struct RemoteEntry {
+    etag: Option<String>,
+    last_modified: Option<String>,
+
+    /// uploaded urls keyed by [`RemoteCache::target`]
+    uploaded: HashMap<String, String>,
+}
+
+/// Small on-disk cache of remote urls `picup()` has re-hosted, stored in the user cache dir.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct RemoteCache {
+    entries: HashMap<String, RemoteEntry>,
+
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl RemoteCache {
+    fn path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("picup").join("remote.json"))
+    }
+
+    pub(crate) fn load() -> Self {
+        Self::path()
+            .and_then(|path| read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+
+        write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    /// same remote image uploaded to different servers or categories are different urls
+    pub(crate) fn target(base_url: &str, category: &str) -> String {
+        format!("{}|{}", base_url, category)
+    }
+
+    /// url it has been uploaded to previously, if any
+    pub(crate) fn uploaded(&self, url: &str, target: &str) -> Option<&String> {
+        self.entries.get(url)?.uploaded.get(target)
+    }
+
+    /// attaches conditional headers to the request if the url is seen before
+    pub(crate) fn conditional(&self, url: &str, req: RequestBuilder) -> RequestBuilder {
+        let entry = match self.entries.get(url) {
+            Some(entry) => entry,
+            None => return req,
+        };
+
+        let mut req = req;
+
+        if let Some(etag) = &entry.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(last_modified) = &entry.last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        req
+    }
+
+    /// remembers validators of a freshly downloaded remote image
+    pub(crate) fn downloaded(&mut self, url: &str, res: &Response) {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+
+        let entry = self.entries.entry(url.to_string()).or_default();
+
+        if entry.etag != etag || entry.last_modified != last_modified {
+            // the old uploads are stale now
+            entry.uploaded.clear();
+        }
+
+        entry.etag = etag;
+        entry.last_modified = last_modified;
+
+        self.dirty = true;
+    }
+
+    pub(crate) fn uploaded_to(&mut self, url: &str, target: &str, uploaded_url: &str) {
+        let entry = self.entries.entry(url.to_string()).or_default();
+
+        if entry.etag.is_none() && entry.last_modified.is_none() {
+            // nothing to validate against next time
+            return;
+        }
+
+        entry
+            .uploaded
+            .insert(target.to_string(), uploaded_url.to_string());
+
+        self.dirty = true;
+    }
+}
@@ -1,12 +1,27 @@
 use std::{
+    collections::BTreeMap,
     env::temp_dir,
-    fs::{remove_file, File},
-    io::Write,
+    fs::{metadata, remove_file, File},
+    io::{Read, Write},
     path::PathBuf,
+    time::{Duration, Instant},
 };
 
-use reqwest::blocking::{multipart::Form, Client};
-use serde::{Deserialize, Serialize};
+use cache::RemoteCache;
+use reqwest::{
+    blocking::{
+        multipart::{Form, Part},
+        Client, Response,
+    },
+    header::{HeaderMap, EXPECT, IF_NONE_MATCH},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+mod cache;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -20,7 +35,7 @@ macro_rules! api {
     };
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct ResponseCode(u16);
 
 impl ResponseCode {
@@ -39,12 +54,35 @@ macro_rules! response_codes {
         $(
             pub const $r#const: ResponseCode = ResponseCode($num);
         )+
+
+            /// Name of the constant, e.g. `"INVALID_TOKEN"`.
+            pub fn name(&self) -> &'static str {
+                match self.0 {
+                $(
+                    $num => stringify!($r#const),
+                )+
+                    _ => "UNKNOWN",
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<ResponseCode> {
+                match name {
+                $(
+                    stringify!($r#const) => Some(ResponseCode::$r#const),
+                )+
+                    _ => None,
+                }
+            }
         }
     }
 }
 
 response_codes! {
     (0, OK);
+    (990, BAD_REQUEST);
+    (991, NOT_FOUND);
+    (992, METHOD_NOT_ALLOWED);
+    (993, PAYLOAD_TOO_LARGE);
     (998, NOT_IMPLEMENTED);
     (999, INTERNAL_ERROR);
     (1001, INVALID_TOKEN);
@@ -53,6 +91,16 @@ response_codes! {
     (1004, FILE_EXISTED);
     (1005, BAD_FILE);
     (1006, INVALID_CATEGORY);
+    (1007, MAINTENANCE);
+    (1008, FILE_NOT_FOUND);
+    (1009, INVALID_PARAM);
+    (1010, TIMEOUT);
+    (1011, FILE_TOO_LARGE);
+    (1012, TOO_MANY_FILES);
+    (1013, HOTLINK_DENIED);
+    (1014, DISK_FULL);
+    (1015, FORBIDDEN);
+    (1016, URL_BLOCKED);
 }
 
 fn serde_default_false() -> bool {
@@ -63,15 +111,321 @@ fn serde_default_zero_u8() -> u8 {
     0
 }
 
+fn serde_default_zero_u32() -> u32 {
+    0
+}
+
+fn serde_default_zero_u64() -> u64 {
+    0
+}
+
+fn serde_default_presign_expires_in() -> u64 {
+    600
+}
+
 fn serde_default_empty_string() -> String {
     "".to_string()
 }
 
+fn serde_default_recent_limit() -> usize {
+    50
+}
+
+fn serde_default_list_page() -> usize {
+    1
+}
+
+fn serde_default_list_limit() -> usize {
+    100
+}
+
+fn serde_default_montage_columns() -> u32 {
+    4
+}
+
+fn serde_default_montage_cell() -> u32 {
+    128
+}
+
+/// What an upload does with a file of the same name already stored.
+///
+/// `never` and `always` are sent as `false` and `true`, which older servers know as well.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum OverridePolicy {
+    /// Reject the upload.
+    #[default]
+    #[serde(rename = "false", alias = "never")]
+    Never,
+
+    /// Replace the stored file.
+    #[serde(rename = "true", alias = "always")]
+    Always,
+
+    /// Replace the stored file unless it's from the very same upload, which is skipped then.
+    #[serde(rename = "if_different")]
+    IfDifferent,
+
+    /// Store the file under a free name, such as `a-1.png` for `a.png`.
+    #[serde(rename = "rename")]
+    Rename,
+}
+
+impl OverridePolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverridePolicy::Never => "never",
+            OverridePolicy::Always => "always",
+            OverridePolicy::IfDifferent => "if_different",
+            OverridePolicy::Rename => "rename",
+        }
+    }
+
+    /// Whether stored files may be replaced.
+    pub fn replaces(&self) -> bool {
+        matches!(self, OverridePolicy::Always | OverridePolicy::IfDifferent)
+    }
+}
+
+impl From<bool> for OverridePolicy {
+    fn from(r#override: bool) -> Self {
+        if r#override {
+            OverridePolicy::Always
+        } else {
+            OverridePolicy::Never
+        }
+    }
+}
+
+impl std::fmt::Display for OverridePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverridePolicy::Never => write!(f, "false"),
+            OverridePolicy::Always => write!(f, "true"),
+            other => write!(f, "{}", other.name()),
+        }
+    }
+}
+
+/// What the server did with each file of an upload, told by [`UploadedFile::action`] and
+/// listed in the [`ACTIONS_HEADER`] of the response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UploadAction {
+    Created,
+    Overwritten,
+
+    /// It was stored from the very same upload already, as the client told by its hash.
+    Skipped,
+
+    /// Stored under another name, which is in its url.
+    Renamed,
+
+    /// An asset of the same name and content is there already, which is kept.
+    Deduplicated,
+}
+
+impl UploadAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UploadAction::Created => "created",
+            UploadAction::Overwritten => "overwritten",
+            UploadAction::Skipped => "skipped",
+            UploadAction::Renamed => "renamed",
+            UploadAction::Deduplicated => "deduplicated",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<UploadAction> {
+        match name {
+            "created" => Some(UploadAction::Created),
+            "overwritten" => Some(UploadAction::Overwritten),
+            "skipped" => Some(UploadAction::Skipped),
+            "renamed" => Some(UploadAction::Renamed),
+            "deduplicated" => Some(UploadAction::Deduplicated),
+            _ => None,
+        }
+    }
+}
+
+/// Header of upload responses listing the [`UploadAction`] of each url, comma separated.
+pub const ACTIONS_HEADER: &str = "x-picup-actions";
+
+/// A file of an upload as the server responds it, in the order the files were stored.
+#[derive(Serialize, Deserialize)]
+pub struct UploadedFile {
+    /// name of the file part
+    submitted_name: String,
+
+    /// after templates, conversions and renaming, which is the last segment of `url`
+    stored_name: String,
+
+    url: String,
+
+    /// name of the [`UploadAction`], kept as is for those the client doesn't know
+    action: String,
+}
+
+impl UploadedFile {
+    pub fn new(submitted_name: &str, stored_name: &str, url: &str, action: UploadAction) -> Self {
+        UploadedFile {
+            submitted_name: submitted_name.to_string(),
+            stored_name: stored_name.to_string(),
+            url: url.to_string(),
+            action: action.name().to_string(),
+        }
+    }
+
+    pub fn submitted_name(&self) -> &String {
+        &self.submitted_name
+    }
+
+    pub fn stored_name(&self) -> &String {
+        &self.stored_name
+    }
+
+    pub fn url(&self) -> &String {
+        &self.url
+    }
+
+    /// `None` if the client doesn't know it.
+    pub fn action(&self) -> Option<UploadAction> {
+        UploadAction::from_name(&self.action)
+    }
+}
+
+/// Header of uploads a category processes in the background, which are responded with
+/// `202 Accepted` as soon as they are stored, naming the job that tells when it's done.
+pub const JOB_HEADER: &str = "x-picup-job";
+
+/// How far an upload processed in the background is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobStatus {
+    Processing,
+    Done,
+
+    /// the files are served as uploaded
+    Failed,
+}
+
+impl JobStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobStatus::Processing => "processing",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "processing" => Some(JobStatus::Processing),
+            "done" => Some(JobStatus::Done),
+            "failed" => Some(JobStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// An upload processed in the background, as `/job/:id` tells.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    id: String,
+    category: String,
+
+    /// name of the [`JobStatus`]
+    status: String,
+
+    /// of the files it processes
+    urls: Vec<String>,
+
+    /// how many of them it's done with
+    #[serde(default)]
+    done: usize,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl JobInfo {
+    pub fn new(id: &str, category: &str, urls: Vec<String>) -> Self {
+        JobInfo {
+            id: id.to_string(),
+            category: category.to_string(),
+            status: JobStatus::Processing.name().to_string(),
+            urls,
+            done: 0,
+            error: None,
+        }
+    }
+
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn category(&self) -> &String {
+        &self.category
+    }
+
+    /// `None` if the client doesn't know it.
+    pub fn status(&self) -> Option<JobStatus> {
+        JobStatus::from_name(&self.status)
+    }
+
+    pub fn set_status(&mut self, status: JobStatus, error: Option<&str>) {
+        self.status = status.name().to_string();
+        self.error = error.map(str::to_string);
+    }
+
+    pub fn urls(&self) -> &Vec<String> {
+        &self.urls
+    }
+
+    /// How many of the files it's done with, the progress is `done` of `urls().len()`.
+    pub fn done(&self) -> usize {
+        self.done
+    }
+
+    /// Counts one more file done.
+    pub fn advance(&mut self) {
+        self.done += 1;
+    }
+
+    /// Why it failed.
+    pub fn error(&self) -> Option<&String> {
+        self.error.as_ref()
+    }
+}
+
+/// Key/value metadata of an asset given at upload, such as its alt text or caption.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Header of file parts carrying their [`Metadata`], see [`encode_metadata`]. The server replaces
+/// what it has of a file with it, or drops that if the file is stored again without it.
+pub const METADATA_HEADER: &str = "x-picup-metadata";
+
+/// [`METADATA_HEADER`] of the metadata, url-encoded `key=value` pairs joined by `&`.
+pub fn encode_metadata(metadata: &Metadata) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                urlencoding::encode(key),
+                urlencoding::encode(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Header of upload responses listing the BlurHash of each url, url-encoded and comma separated,
+/// empty for files without one. Only sent if any has one.
+pub const BLURHASH_HEADER: &str = "x-picup-blurhash";
+
 // serde bug: https://github.com/serde-rs/serde/issues/1030
 #[derive(Serialize, Deserialize)]
 pub struct UploadImgParam {
-    #[serde(default = "serde_default_false")]
-    r#override: bool,
+    #[serde(default)]
+    r#override: OverridePolicy,
 
     #[serde(default = "serde_default_zero_u8")]
     compress: u8,
@@ -81,22 +435,42 @@ pub struct UploadImgParam {
 
     #[serde(default = "serde_default_empty_string")]
     access_token: String,
+
+    /// the rest come from a pre-signed url, see [`PresignParam`]
+    #[serde(default = "serde_default_zero_u64")]
+    max_size: u64,
+
+    #[serde(default = "serde_default_zero_u64")]
+    expires: u64,
+
+    #[serde(default = "serde_default_empty_string")]
+    signature: String,
 }
 
 impl UploadImgParam {
-    pub fn new(access_token: &str, compress: u8, category: &str, r#override: bool) -> Self {
+    pub fn new(
+        access_token: &str,
+        compress: u8,
+        category: &str,
+        r#override: impl Into<OverridePolicy>,
+    ) -> Self {
         UploadImgParam {
             access_token: access_token.to_string(),
             compress,
             category: category.to_string(),
-            r#override,
+            r#override: r#override.into(),
+            max_size: 0,
+            expires: 0,
+            signature: "".to_string(),
         }
     }
 
-    pub fn r#override(&self) -> bool {
+    pub fn r#override(&self) -> OverridePolicy {
         self.r#override
     }
 
+    /// Jpeg quality from 1 to 100 to re-encode jpeg images at, which the server may clamp, or 0
+    /// for none.
     pub fn compress(&self) -> u8 {
         self.compress
     }
@@ -108,165 +482,1672 @@ impl UploadImgParam {
     pub fn access_token(&self) -> &String {
         &self.access_token
     }
-}
 
-#[derive(Serialize, Deserialize)]
-pub struct GetImgParam {
-    #[serde(default = "serde_default_zero_u8")]
-    compress: u8,
-}
+    /// Limit of the total size of the files in bytes, 0 for none.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
 
-impl GetImgParam {
-    pub fn compress(&self) -> u8 {
-        self.compress
+    /// Unix time after which the signature is no longer accepted.
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    /// Hex HMAC of the constraints, accepted in lieu of the token.
+    pub fn signature(&self) -> &String {
+        &self.signature
     }
 }
 
+/// Constraints of a pre-signed upload url, which lets a client upload without the token.
 #[derive(Serialize, Deserialize)]
-pub struct RestResponse<TData> {
-    code: u16,
-    msg: String,
-    data: Option<TData>,
+pub struct PresignParam {
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
+
+    #[serde(default = "serde_default_empty_string")]
+    category: String,
+
+    #[serde(default = "serde_default_false")]
+    r#override: bool,
+
+    #[serde(default = "serde_default_zero_u64")]
+    max_size: u64,
+
+    #[serde(default = "serde_default_presign_expires_in")]
+    expires_in: u64,
 }
 
-impl<TData> RestResponse<TData> {
-    pub fn new(status: ResponseCode, msg: &str, data: TData) -> Self {
-        Self {
-            code: status.to_u16(),
-            msg: msg.to_string(),
-            data: Some(data),
+impl PresignParam {
+    pub fn new(
+        access_token: &str,
+        category: &str,
+        r#override: bool,
+        max_size: u64,
+        expires_in: u64,
+    ) -> Self {
+        PresignParam {
+            access_token: access_token.to_string(),
+            category: category.to_string(),
+            r#override,
+            max_size,
+            expires_in,
         }
     }
 
-    pub fn new_no_data(status: ResponseCode, msg: &str) -> Self {
-        Self {
-            code: status.to_u16(),
-            msg: msg.to_string(),
-            data: None,
-        }
+    pub fn access_token(&self) -> &String {
+        &self.access_token
     }
 
-    pub fn code(&self) -> ResponseCode {
-        ResponseCode(self.code)
+    /// The only category the url uploads to.
+    pub fn category(&self) -> &String {
+        &self.category
     }
 
-    pub fn msg(&self) -> &String {
-        &self.msg
+    /// Whether the url may override existing files.
+    pub fn r#override(&self) -> bool {
+        self.r#override
     }
 
-    pub fn data(&self) -> Option<&TData> {
-        self.data.as_ref()
+    /// Limit of the total size of the files uploaded per request in bytes, 0 for none.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Seconds the url stays valid.
+    pub fn expires_in(&self) -> u64 {
+        self.expires_in
     }
 }
 
-pub fn picup<TPath>(
-    base_url: &str,
-    file_paths: &[TPath],
-    param: &UploadImgParam,
-) -> Result<Vec<String>>
-where
-    TPath: AsRef<std::path::Path>,
-{
-    let client = Client::new();
+#[derive(Serialize, Deserialize)]
+pub struct PresignedUpload {
+    url: String,
+    expires: u64,
+}
 
-    let mut form = Form::new();
+impl PresignedUpload {
+    pub fn new(url: &str, expires: u64) -> Self {
+        PresignedUpload {
+            url: url.to_string(),
+            expires,
+        }
+    }
 
-    let mut temp_files = vec![];
+    /// Upload url, to be posted multipart files to as is.
+    pub fn url(&self) -> &String {
+        &self.url
+    }
 
-    for path in file_paths {
-        if !path.as_ref().to_str().unwrap().starts_with("http") {
-            // do nothing if it's actually a local file
-            form = form.file("file", path)?;
+    /// Unix time after which the url is no longer accepted.
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+}
 
-            continue;
-        }
+#[derive(Serialize, Deserialize)]
+pub struct GetImgParam {
+    #[serde(default = "serde_default_zero_u8")]
+    compress: u8,
 
-        // download it before we add it
-        let res = client
-            .get(path.as_ref().to_str().unwrap())
-            .send()?
-            .bytes()?;
+    #[serde(default = "serde_default_zero_u8")]
+    autorotate: u8,
 
-        let temp_file_path = [
-            temp_dir(),
-            PathBuf::from(
-                path.as_ref()
-                    .file_name()
-                    .expect("guessing file extension has not implemented yet"),
-            ),
-        ]
-        .iter()
-        .collect::<PathBuf>();
+    #[serde(default = "serde_default_zero_u8")]
+    original: u8,
 
-        let mut file = File::create(&temp_file_path)?;
+    #[serde(default = "serde_default_zero_u32")]
+    w: u32,
 
-        file.write_all(&res)?;
+    #[serde(default = "serde_default_zero_u32")]
+    h: u32,
 
-        form = form.file("file", &temp_file_path)?;
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
+}
 
-        temp_files.push(temp_file_path);
+impl GetImgParam {
+    pub fn compress(&self) -> u8 {
+        self.compress
     }
 
-    let mut res = client
-        .post(format!("{}{}", base_url, api!("/upload")))
-        .query(&[
-            ("access_token", param.access_token()),
-            ("compress", &param.compress().to_string()),
-            ("category", param.category()),
-            ("override", &param.r#override().to_string()),
-        ])
-        .multipart(form)
-        .send()?;
-
-    let mut body_buf = vec![];
-    res.copy_to(&mut body_buf)?;
-
-    let json_str = String::from_utf8(body_buf)?;
+    /// Whether to apply the EXIF orientation of the image before serving it.
+    pub fn autorotate(&self) -> bool {
+        self.autorotate != 0
+    }
 
-    let res = match serde_json::from_str::<RestResponse<Vec<String>>>(&json_str) {
-        Ok(parsed) => parsed,
-        Err(e) => {
-            eprintln!("json parse fail, should be an error: {}", e);
+    /// Whether to serve the upload as it was before processing, which requires the token.
+    pub fn original(&self) -> bool {
+        self.original != 0
+    }
 
-            return Err(Error::from(json_str));
-        }
-    };
+    /// Width to fit the image within, 0 if unbounded.
+    pub fn w(&self) -> u32 {
+        self.w
+    }
 
-    for file in temp_files {
-        let _ = remove_file(file);
+    /// Height to fit the image within, 0 if unbounded.
+    pub fn h(&self) -> u32 {
+        self.h
     }
 
-    if res.code() != ResponseCode::OK {
-        return Err(Error::from(res.msg().as_str()));
+    pub fn access_token(&self) -> &String {
+        &self.access_token
     }
+}
 
-    Ok(res.data().unwrap().to_vec())
+#[derive(Serialize, Deserialize)]
+pub struct TokenParam {
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
 }
 
-#[test]
-fn test_local_file() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    println!(
-        "{:#?}",
-        picup(
-            "https://skopzz.com",
-            &["D:/Download/demo.gif"],
-            &UploadImgParam::new("baka", 0, "pic", false),
-        )?
+impl TokenParam {
+    pub fn new(access_token: &str) -> Self {
+        TokenParam {
+            access_token: access_token.to_string(),
+        }
+    }
+
+    pub fn access_token(&self) -> &String {
+        &self.access_token
+    }
+}
+
+/// Body of `/upload/url`, whose token goes in the query as a [`TokenParam`].
+#[derive(Serialize, Deserialize)]
+pub struct UrlUploadParam {
+    category: String,
+    urls: Vec<String>,
+
+    #[serde(default)]
+    r#override: bool,
+}
+
+impl UrlUploadParam {
+    pub fn new(category: &str, urls: &[&str], r#override: bool) -> Self {
+        UrlUploadParam {
+            category: category.to_string(),
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            r#override,
+        }
+    }
+
+    pub fn category(&self) -> &String {
+        &self.category
+    }
+
+    /// Images the server fetches and stores.
+    pub fn urls(&self) -> &Vec<String> {
+        &self.urls
+    }
+
+    pub fn r#override(&self) -> bool {
+        self.r#override
+    }
+}
+
+/// Query of `/hash/:category/:sha256`, which takes those of [`GetImgParam`] as well. The sha256
+/// is that of the file as it was uploaded, an asset the server has processed is served by it
+/// rather than by that of its own bytes.
+#[derive(Serialize, Deserialize)]
+pub struct HashParam {
+    #[serde(default = "serde_default_zero_u8")]
+    any_category: u8,
+}
+
+impl HashParam {
+    pub fn new(any_category: bool) -> Self {
+        HashParam {
+            any_category: any_category as u8,
+        }
+    }
+
+    /// Whether to look in the other categories too if the given one has no such asset.
+    pub fn any_category(&self) -> bool {
+        self.any_category != 0
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListImgParam {
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
+
+    #[serde(default = "serde_default_list_page")]
+    page: usize,
+
+    #[serde(default = "serde_default_list_limit")]
+    limit: usize,
+}
+
+impl ListImgParam {
+    pub fn new(access_token: &str, page: usize, limit: usize) -> Self {
+        ListImgParam {
+            access_token: access_token.to_string(),
+            page,
+            limit,
+        }
+    }
+
+    pub fn access_token(&self) -> &String {
+        &self.access_token
+    }
+
+    /// Page of the assets sorted by name, from 1.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Urls listed per page.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveParam {
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
+
+    #[serde(default = "serde_default_empty_string")]
+    prefix: String,
+}
+
+impl ArchiveParam {
+    pub fn new(access_token: &str, prefix: &str) -> Self {
+        ArchiveParam {
+            access_token: access_token.to_string(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    pub fn access_token(&self) -> &String {
+        &self.access_token
+    }
+
+    /// Only files whose names start with it are archived.
+    pub fn prefix(&self) -> &String {
+        &self.prefix
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecentParam {
+    #[serde(default = "serde_default_empty_string")]
+    access_token: String,
+
+    #[serde(default = "serde_default_recent_limit")]
+    limit: usize,
+}
+
+impl RecentParam {
+    pub fn new(access_token: &str, limit: usize) -> Self {
+        RecentParam {
+            access_token: access_token.to_string(),
+            limit,
+        }
+    }
+
+    pub fn access_token(&self) -> &String {
+        &self.access_token
+    }
+
+    /// Most uploads listed.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchParam {
+    #[serde(default = "serde_default_empty_string")]
+    files: String,
+}
+
+impl BatchParam {
+    pub fn new(files: &[&str]) -> Self {
+        BatchParam {
+            files: files.join(","),
+        }
+    }
+
+    /// Names of the files, separated by commas.
+    pub fn files(&self) -> &String {
+        &self.files
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MontageParam {
+    #[serde(default = "serde_default_empty_string")]
+    files: String,
+
+    #[serde(default = "serde_default_montage_columns")]
+    columns: u32,
+
+    #[serde(default = "serde_default_montage_cell")]
+    cell: u32,
+}
+
+impl MontageParam {
+    pub fn new(files: &[&str], columns: u32, cell: u32) -> Self {
+        MontageParam {
+            files: files.join(","),
+            columns,
+            cell,
+        }
+    }
+
+    /// Names of the files, separated by commas.
+    pub fn files(&self) -> &String {
+        &self.files
+    }
+
+    /// Number of cells in a row.
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    /// Width and height of a cell in pixels, which the thumbnails are fit in.
+    pub fn cell(&self) -> u32 {
+        self.cell
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CategoryInfo {
+    name: String,
+    allow_all_files: bool,
+
+    /// of each file, 0 if the category has none of its own or the server doesn't tell
+    #[serde(default)]
+    max_size: u64,
+
+    #[serde(default)]
+    allowed_extensions: Option<Vec<String>>,
+}
+
+impl CategoryInfo {
+    pub fn new(
+        name: &str,
+        allow_all_files: bool,
+        max_size: u64,
+        allowed_extensions: Option<Vec<String>>,
+    ) -> Self {
+        CategoryInfo {
+            name: name.to_string(),
+            allow_all_files,
+            max_size,
+            allowed_extensions,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn allow_all_files(&self) -> bool {
+        self.allow_all_files
+    }
+
+    /// Bytes each file uploaded to the category may have at most, 0 if it has no limit of its own
+    /// or the server doesn't tell. Whole requests are limited by the server regardless.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Extensions of the images the category takes and processes, any file if `None`.
+    pub fn allowed_extensions(&self) -> Option<&Vec<String>> {
+        self.allowed_extensions.as_ref()
+    }
+}
+
+/// An asset among the recent uploads, or as `/meta/:category/:file_name` tells of it.
+#[derive(Serialize, Deserialize)]
+pub struct RecentUpload {
+    category: String,
+    name: String,
+    url: String,
+    size: u64,
+
+    /// unix seconds it was stored at
+    uploaded: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    metadata: Metadata,
+}
+
+impl RecentUpload {
+    pub fn new(
+        category: &str,
+        name: &str,
+        url: &str,
+        size: u64,
+        uploaded: u64,
+        blurhash: Option<&str>,
+        metadata: Metadata,
+    ) -> Self {
+        RecentUpload {
+            category: category.to_string(),
+            name: name.to_string(),
+            url: url.to_string(),
+            size,
+            uploaded,
+            blurhash: blurhash.map(str::to_string),
+            metadata,
+        }
+    }
+
+    pub fn category(&self) -> &String {
+        &self.category
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn url(&self) -> &String {
+        &self.url
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    /// Placeholder of the image, if its category computes them, see [`UploadedImage::blurhash`].
+    pub fn blurhash(&self) -> Option<&String> {
+        self.blurhash.as_ref()
+    }
+
+    /// Given along with the file when it was uploaded, see [`METADATA_HEADER`].
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VersionInfo {
+    version: String,
+    profile: String,
+    target: String,
+}
+
+impl VersionInfo {
+    pub fn new(version: &str, profile: &str, target: &str) -> Self {
+        VersionInfo {
+            version: version.to_string(),
+            profile: profile.to_string(),
+            target: target.to_string(),
+        }
+    }
+
+    /// Crate version, e.g. `"0.1.0"`.
+    pub fn version(&self) -> &String {
+        &self.version
+    }
+
+    /// Cargo profile it was built with, e.g. `"release"`.
+    pub fn profile(&self) -> &String {
+        &self.profile
+    }
+
+    /// Target triple it was built for.
+    pub fn target(&self) -> &String {
+        &self.target
+    }
+
+    /// Whether both sides should speak the same api. Pre-1.0 minor versions may break it.
+    pub fn is_compatible_with(&self, version: &str) -> bool {
+        let major_minor = |v: &str| {
+            let mut parts = v.split('.');
+            let major = parts.next().unwrap_or("").to_string();
+            let minor = parts.next().unwrap_or("").to_string();
+
+            if major == "0" {
+                (major, minor)
+            } else {
+                (major, String::new())
+            }
+        };
+
+        major_minor(&self.version) == major_minor(version)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RestResponse<TData> {
+    code: u16,
+    msg: String,
+    data: Option<TData>,
+}
+
+impl<TData> RestResponse<TData> {
+    pub fn new(status: ResponseCode, msg: &str, data: TData) -> Self {
+        Self {
+            code: status.to_u16(),
+            msg: msg.to_string(),
+            data: Some(data),
+        }
+    }
+
+    pub fn new_no_data(status: ResponseCode, msg: &str) -> Self {
+        Self {
+            code: status.to_u16(),
+            msg: msg.to_string(),
+            data: None,
+        }
+    }
+
+    pub fn code(&self) -> ResponseCode {
+        ResponseCode(self.code)
+    }
+
+    pub fn msg(&self) -> &String {
+        &self.msg
+    }
+
+    pub fn data(&self) -> Option<&TData> {
+        self.data.as_ref()
+    }
+}
+
+/// Errors of talking to a PicUp server, returned boxed in [`Error`] along with those of the
+/// transport (`reqwest::Error`) and of local files (`std::io::Error`).
+#[derive(Debug)]
+pub enum PicupError {
+    /// The server refused the request.
+    Response { code: ResponseCode, msg: String },
+
+    /// The response isn't one of a PicUp server.
+    BadResponse(String),
+}
+
+impl std::fmt::Display for PicupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PicupError::Response { msg, .. } => write!(f, "{}", msg),
+            PicupError::BadResponse(body) => write!(f, "unexpected response: {}", body),
+        }
+    }
+}
+
+impl std::error::Error for PicupError {}
+
+/// Client side options of [`picup_with_options`] which are not sent to the server.
+#[derive(Default)]
+pub struct PicupOptions {
+    /// Remember validators (`ETag`/`Last-Modified`) of re-hosted remote images in the user cache
+    /// dir, and skip downloading and uploading them again if they haven't changed since.
+    pub remote_cache: bool,
+
+    /// Extra headers sent with every file part, on top of the detected `Content-Type`.
+    pub part_headers: HeaderMap,
+}
+
+/// Detects the mime type of common image formats by their magic bytes.
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"II*\0", "image/tiff"),
+        (b"MM\0*", "image/tiff"),
+        (b"\0\0\x01\0", "image/x-icon"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(sig, _)| bytes.starts_with(sig)) {
+        return Some(mime);
+    }
+
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        match &bytes[8..12] {
+            b"avif" | b"avis" => return Some("image/avif"),
+            b"heic" | b"heix" | b"mif1" => return Some("image/heic"),
+            _ => {}
+        }
+    }
+
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]);
+    let text = text.trim_start();
+
+    if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+        return Some("image/svg+xml");
+    }
+
+    None
+}
+
+/// Total size of the files above which uploads are sent with `Expect: 100-continue`.
+const EXPECT_CONTINUE_THRESHOLD: u64 = 1024 * 1024;
+
+/// Header of file parts telling their position in the request, which the server lists in the
+/// order of the urls it responds.
+const INDEX_HEADER: &str = "x-picup-index";
+
+/// The url of an http or https file to download before uploading it, `None` for local files,
+/// including those whose paths aren't utf-8.
+fn remote_url(path: &std::path::Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Builds a part for the file with an explicit `Content-Type`, detected from its content and
+/// falling back to its extension, tagged with its position among the files of the request.
+fn file_part(path: &std::path::Path, options: &PicupOptions, index: usize) -> Result<Part> {
+    let mut head = vec![];
+    File::open(path)?.take(512).read_to_end(&mut head)?;
+
+    let mime = match sniff_mime(&head) {
+        Some(mime) => mime.to_string(),
+        None => mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string(),
+    };
+
+    let mut headers = options.part_headers.clone();
+    headers.insert(INDEX_HEADER, index.into());
+
+    Ok(Part::file(path)?.mime_str(&mime)?.headers(headers))
+}
+
+/// Puts the urls of an upload back into the order the files were attached in, by the indexes
+/// the server lists. Servers not listing them keep the order as is.
+fn in_attached_order<T: Clone>(urls: Vec<T>, indexes: Option<&str>) -> Vec<T> {
+    let indexes = match indexes.map(|indexes| {
+        indexes
+            .split(',')
+            .map(|index| index.trim().parse::<usize>())
+            .collect::<std::result::Result<Vec<usize>, _>>()
+    }) {
+        Some(Ok(indexes)) if indexes.len() == urls.len() => indexes,
+        _ => return urls,
+    };
+
+    let mut ordered = vec![None; urls.len()];
+
+    for (url, index) in urls.iter().zip(indexes) {
+        match ordered.get_mut(index) {
+            Some(slot @ None) => *slot = Some(url.clone()),
+            // not a permutation, better not trust it at all
+            _ => return urls,
+        }
+    }
+
+    ordered.into_iter().flatten().collect()
+}
+
+/// An image of an upload.
+#[derive(Clone)]
+pub struct UploadedImage {
+    url: String,
+    submitted_name: Option<String>,
+    stored_name: Option<String>,
+    action: Option<UploadAction>,
+    blurhash: Option<String>,
+    job: Option<String>,
+}
+
+impl UploadedImage {
+    pub fn url(&self) -> &String {
+        &self.url
+    }
+
+    /// Name the file was sent with, `None` if it's reused from the cache or the server doesn't
+    /// tell.
+    pub fn submitted_name(&self) -> Option<&String> {
+        self.submitted_name.as_ref()
+    }
+
+    /// Name the server stored the file under, which may differ from the one it was sent with,
+    /// `None` if it's reused from the cache or the server doesn't tell.
+    pub fn stored_name(&self) -> Option<&String> {
+        self.stored_name.as_ref()
+    }
+
+    /// What the server did with it, `None` if it's reused from the cache or the server doesn't
+    /// tell.
+    pub fn action(&self) -> Option<UploadAction> {
+        self.action
+    }
+
+    /// [BlurHash](https://blurha.sh) placeholder of the image, if its category computes them.
+    pub fn blurhash(&self) -> Option<&String> {
+        self.blurhash.as_ref()
+    }
+
+    /// Id of the job processing the image in the background, if its category does so, see
+    /// [`job_info`]. The url serves the image as uploaded until it's done.
+    pub fn job(&self) -> Option<&String> {
+        self.job.as_ref()
+    }
+}
+
+/// Result of [`picup_with_options`].
+pub struct UploadReport {
+    urls: Vec<String>,
+    images: Vec<UploadedImage>,
+    files: usize,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl UploadReport {
+    /// Urls of the images in the same order as the given paths.
+    pub fn urls(&self) -> &Vec<String> {
+        &self.urls
+    }
+
+    pub fn into_urls(self) -> Vec<String> {
+        self.urls
+    }
+
+    /// The images along with what the server tells about them, in the same order as the given
+    /// paths.
+    pub fn images(&self) -> &Vec<UploadedImage> {
+        &self.images
+    }
+
+    /// Number of files actually sent to the server, excluding those reused from the cache.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Total size of the files sent to the server.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Wall time of the whole upload, including downloading remote images.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+pub fn picup<TPath>(
+    base_url: &str,
+    file_paths: &[TPath],
+    param: &UploadImgParam,
+) -> Result<Vec<String>>
+where
+    TPath: AsRef<std::path::Path>,
+{
+    Ok(picup_with_options(base_url, file_paths, param, &PicupOptions::default())?.into_urls())
+}
+
+pub fn picup_with_options<TPath>(
+    base_url: &str,
+    file_paths: &[TPath],
+    param: &UploadImgParam,
+    options: &PicupOptions,
+) -> Result<UploadReport>
+where
+    TPath: AsRef<std::path::Path>,
+{
+    let started = Instant::now();
+
+    let client = Client::new();
+
+    let mut bytes = 0;
+
+    let mut form = Form::new();
+
+    let mut temp_files = vec![];
+
+    let mut cache = if options.remote_cache {
+        Some(RemoteCache::load())
+    } else {
+        None
+    };
+
+    let target = RemoteCache::target(base_url, param.category());
+
+    // images in the same order as `file_paths`, `None` for those waiting for the server
+    let mut images: Vec<Option<UploadedImage>> = vec![];
+
+    // remote url of each file attached to the form, `None` for local files
+    let mut attached: Vec<Option<String>> = vec![];
+
+    for path in file_paths {
+        let remote_url = match remote_url(path.as_ref()) {
+            Some(remote_url) => remote_url,
+            None => {
+                // a local file is attached as is
+                form = form.part("file", file_part(path.as_ref(), options, attached.len())?);
+                bytes += metadata(path)?.len();
+
+                images.push(None);
+                attached.push(None);
+
+                continue;
+            }
+        };
+
+        let uploaded = cache
+            .as_ref()
+            .and_then(|cache| cache.uploaded(remote_url, &target))
+            .cloned();
+
+        let mut req = client.get(remote_url);
+
+        if let (Some(cache), Some(_)) = (&cache, &uploaded) {
+            // only worth asking when there is something to reuse
+            req = cache.conditional(remote_url, req);
+        }
+
+        let res = req.send()?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            images.push(uploaded.map(|url| UploadedImage {
+                url,
+                submitted_name: None,
+                stored_name: None,
+                action: None,
+                blurhash: None,
+                job: None,
+            }));
+
+            continue;
+        }
+
+        if let Some(cache) = &mut cache {
+            cache.downloaded(remote_url, &res);
+        }
+
+        // download it before we add it
+        let res = res.bytes()?;
+
+        let temp_file_path = [
+            temp_dir(),
+            PathBuf::from(
+                path.as_ref()
+                    .file_name()
+                    .expect("guessing file extension has not implemented yet"),
+            ),
+        ]
+        .iter()
+        .collect::<PathBuf>();
+
+        let mut file = File::create(&temp_file_path)?;
+
+        file.write_all(&res)?;
+
+        form = form.part("file", file_part(&temp_file_path, options, attached.len())?);
+        bytes += res.len() as u64;
+
+        temp_files.push(temp_file_path);
+
+        images.push(None);
+        attached.push(Some(remote_url.to_string()));
+    }
+
+    if attached.is_empty() {
+        // everything is reused from the cache
+        let images: Vec<UploadedImage> = images.into_iter().flatten().collect();
+
+        return Ok(UploadReport {
+            urls: images.iter().map(|image| image.url.clone()).collect(),
+            images,
+            files: 0,
+            bytes: 0,
+            elapsed: started.elapsed(),
+        });
+    }
+
+    let mut req = client
+        .post(format!("{}{}", base_url, api!("/upload")))
+        .query(&upload_query(param));
+
+    // lets the server turn it down before it's sent
+    if bytes > EXPECT_CONTINUE_THRESHOLD {
+        req = req.header(EXPECT, "100-continue");
+    }
+
+    let res = req.multipart(form).send()?;
+
+    for file in temp_files {
+        let _ = remove_file(file);
+    }
+
+    let mut uploaded = uploaded_images(res)?.into_iter();
+
+    for (image, remote_url) in images
+        .iter_mut()
+        .filter(|image| image.is_none())
+        .zip(attached.iter())
+    {
+        *image = uploaded.next();
+
+        if let (Some(cache), Some(remote_url), Some(image)) = (&mut cache, remote_url, image) {
+            cache.uploaded_to(remote_url, &target, &image.url);
+        }
+    }
+
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
+
+    let images: Vec<UploadedImage> = images.into_iter().flatten().collect();
+
+    Ok(UploadReport {
+        urls: images.iter().map(|image| image.url.clone()).collect(),
+        images,
+        files: attached.len(),
+        bytes,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Actions listed in the [`ACTIONS_HEADER`], `None` for those the client doesn't know.
+fn upload_actions(headers: &HeaderMap) -> Option<Vec<Option<UploadAction>>> {
+    let actions = headers.get(ACTIONS_HEADER)?.to_str().ok()?;
+
+    Some(
+        actions
+            .split(',')
+            .map(|action| UploadAction::from_name(action.trim()))
+            .collect(),
+    )
+}
+
+/// BlurHashes listed in the [`BLURHASH_HEADER`].
+fn upload_blurhashes(headers: &HeaderMap) -> Option<Vec<Option<String>>> {
+    let blurhashes = headers.get(BLURHASH_HEADER)?.to_str().ok()?;
+
+    Some(
+        blurhashes
+            .split(',')
+            .map(|blurhash| {
+                urlencoding::decode(blurhash.trim())
+                    .ok()
+                    .map(|blurhash| blurhash.into_owned())
+                    .filter(|blurhash| !blurhash.is_empty())
+            })
+            .collect(),
+    )
+}
+
+/// A file of an upload response, which is only its url from servers older than
+/// [`UploadedFile`].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UploadedEntry {
+    File(UploadedFile),
+    Url(String),
+}
+
+/// Images of an upload response in the order the files were attached in, along with what its
+/// body and headers tell about them.
+fn uploaded_images(res: Response) -> Result<Vec<UploadedImage>> {
+    let indexes = res
+        .headers()
+        .get(INDEX_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+
+    let actions = upload_actions(res.headers());
+    let blurhashes = upload_blurhashes(res.headers());
+
+    let job = res
+        .headers()
+        .get(JOB_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+
+    let entries = parse_response::<Vec<UploadedEntry>>(res)?;
+
+    // those of servers not listing them are left out, they wouldn't line up otherwise
+    let actions = actions
+        .filter(|actions| actions.len() == entries.len())
+        .unwrap_or_else(|| vec![None; entries.len()]);
+
+    let blurhashes = blurhashes
+        .filter(|blurhashes| blurhashes.len() == entries.len())
+        .unwrap_or_else(|| vec![None; entries.len()]);
+
+    let images = entries
+        .into_iter()
+        .zip(actions)
+        .zip(blurhashes)
+        .map(|((entry, action), blurhash)| match entry {
+            UploadedEntry::File(file) => UploadedImage {
+                action: file.action().or(action),
+                // only the files it has written are processed
+                job: job.clone().filter(|_| {
+                    !matches!(
+                        file.action(),
+                        Some(UploadAction::Skipped | UploadAction::Deduplicated)
+                    )
+                }),
+                url: file.url,
+                submitted_name: Some(file.submitted_name),
+                stored_name: Some(file.stored_name),
+                blurhash,
+            },
+            UploadedEntry::Url(url) => UploadedImage {
+                url,
+                submitted_name: None,
+                stored_name: None,
+                action,
+                blurhash,
+                job: None,
+            },
+        })
+        .collect();
+
+    Ok(in_attached_order(images, indexes.as_deref()))
+}
+
+fn upload_query(param: &UploadImgParam) -> [(&'static str, String); 4] {
+    [
+        ("access_token", param.access_token().to_string()),
+        ("compress", param.compress().to_string()),
+        ("category", param.category().to_string()),
+        ("override", param.r#override().to_string()),
+    ]
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Result of [`sync`].
+pub struct SyncReport {
+    uploaded: Vec<String>,
+    unchanged: Vec<String>,
+}
+
+impl SyncReport {
+    /// Urls of the files those were uploaded, in the same order as the given paths.
+    pub fn uploaded(&self) -> &Vec<String> {
+        &self.uploaded
+    }
+
+    /// Urls of the files those were on the server as they are already.
+    pub fn unchanged(&self) -> &Vec<String> {
+        &self.unchanged
+    }
+}
+
+/// Uploads the local files those are not on the server as they are, for keeping a folder in sync
+/// with a category.
+///
+/// Each file is looked up by its name with `If-None-Match` carrying its sha256, and only those
+/// which are missing or changed are uploaded. They carry the hash as well, so that the server
+/// skips any stored by someone else in the meantime. Files the server renames, such as with a
+/// filename template or a conversion, are never found unchanged.
+pub fn sync<TPath>(
+    base_url: &str,
+    file_paths: &[TPath],
+    param: &UploadImgParam,
+) -> Result<SyncReport>
+where
+    TPath: AsRef<std::path::Path>,
+{
+    let client = Client::new();
+
+    let mut form = Form::new();
+    let mut attached = 0;
+
+    let mut unchanged = vec![];
+
+    for path in file_paths {
+        let path = path.as_ref();
+
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("no file name in [{}]", path.display()),
+                )
+            })?;
+
+        let etag = format!("\"{}\"", sha256_hex(&std::fs::read(path)?));
+
+        let url = format!(
+            "{}{}",
+            base_url,
+            api!(format!(
+                "/asset/{}/{}",
+                urlencoding::encode(param.category()),
+                urlencoding::encode(name)
+            ))
+        );
+
+        let res = client.head(&url).header(IF_NONE_MATCH, &etag).send()?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            unchanged.push(url);
+
+            continue;
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag.parse()?);
+
+        let options = PicupOptions {
+            part_headers: headers,
+            ..PicupOptions::default()
+        };
+
+        form = form.part("file", file_part(path, &options, attached)?);
+        attached += 1;
+    }
+
+    if attached == 0 {
+        return Ok(SyncReport {
+            uploaded: vec![],
+            unchanged,
+        });
+    }
+
+    let res = client
+        .post(format!("{}{}", base_url, api!("/upload")))
+        .query(&upload_query(param))
+        .multipart(form)
+        .send()?;
+
+    // url-encoded names of those stored in the meantime, for servers not telling the actions
+    let skipped = res
+        .headers()
+        .get("x-picup-unchanged")
+        .and_then(|v| v.to_str().ok())
+        .map(|names| {
+            names
+                .split(',')
+                .map(|name| format!("/{}", name.trim()))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    let (skipped, uploaded) = uploaded_images(res)?
+        .into_iter()
+        .partition::<Vec<UploadedImage>, _>(|image| match image.action {
+            Some(action) => action == UploadAction::Skipped,
+            None => skipped.iter().any(|name| image.url.ends_with(name)),
+        });
+
+    unchanged.extend(skipped.into_iter().map(|image| image.url));
+
+    let uploaded = uploaded.into_iter().map(|image| image.url).collect();
+
+    Ok(SyncReport {
+        uploaded,
+        unchanged,
+    })
+}
+
+/// Has the server fetch the remote images and store them, instead of downloading and uploading
+/// them as [`picup`] does. The images are in the same order as the urls.
+pub fn import_urls(
+    base_url: &str,
+    access_token: &str,
+    param: &UrlUploadParam,
+) -> Result<Vec<UploadedImage>> {
+    let res = Client::new()
+        .post(format!("{}{}", base_url, api!("/upload/url")))
+        .query(&TokenParam::new(access_token))
+        .json(param)
+        .send()?;
+
+    uploaded_images(res)
+}
+
+pub fn list_categories(base_url: &str, access_token: &str) -> Result<Vec<CategoryInfo>> {
+    let res = Client::new()
+        .get(format!("{}{}", base_url, api!("/categories")))
+        .query(&TokenParam::new(access_token))
+        .send()?;
+
+    parse_response(res)
+}
+
+/// The most recently uploaded assets across all categories, newest first.
+pub fn recent_uploads(base_url: &str, param: &RecentParam) -> Result<Vec<RecentUpload>> {
+    let res = Client::new()
+        .get(format!("{}{}", base_url, api!("/recent")))
+        .query(param)
+        .send()?;
+
+    parse_response(res)
+}
+
+/// Urls of a page of the assets in the category, sorted by name.
+pub fn list_images(base_url: &str, category: &str, param: &ListImgParam) -> Result<Vec<String>> {
+    let url = format!(
+        "{}{}",
+        base_url,
+        api!(format!("/category/{}", urlencoding::encode(category)))
+    );
+
+    let res = Client::new().get(&url).query(param).send()?;
+
+    parse_response(res)
+}
+
+/// The asset along with its metadata, see [`RecentUpload`].
+pub fn asset_meta(base_url: &str, category: &str, file_name: &str) -> Result<RecentUpload> {
+    let url = format!(
+        "{}{}",
+        base_url,
+        api!(format!(
+            "/meta/{}/{}",
+            urlencoding::encode(category),
+            urlencoding::encode(file_name)
+        ))
+    );
+
+    let res = Client::new().get(&url).send()?;
+
+    parse_response(res)
+}
+
+/// Whether the job of an upload has processed its files, see [`UploadedImage::job`].
+pub fn job_info(base_url: &str, id: &str) -> Result<JobInfo> {
+    let url = format!(
+        "{}{}",
+        base_url,
+        api!(format!("/job/{}", urlencoding::encode(id)))
+    );
+
+    let res = Client::new().get(&url).send()?;
+
+    parse_response(res)
+}
+
+/// Regenerates the thumbnails and placeholders of the assets of the category by its current
+/// config, in a job followed with [`job_info`].
+pub fn reprocess_category(base_url: &str, category: &str, access_token: &str) -> Result<JobInfo> {
+    let url = format!(
+        "{}{}",
+        base_url,
+        api!(format!(
+            "/category/{}/reprocess",
+            urlencoding::encode(category)
+        ))
     );
 
+    let res = Client::new()
+        .post(&url)
+        .query(&TokenParam::new(access_token))
+        .send()?;
+
+    parse_response(res)
+}
+
+pub fn presign_upload(base_url: &str, param: &PresignParam) -> Result<PresignedUpload> {
+    let res = Client::new()
+        .post(format!("{}{}", base_url, api!("/upload/presign")))
+        .query(param)
+        .send()?;
+
+    parse_response(res)
+}
+
+pub fn server_version(base_url: &str) -> Result<VersionInfo> {
+    let res = Client::new()
+        .get(format!("{}{}", base_url, api!("/version")))
+        .send()?;
+
+    parse_response(res)
+}
+
+fn parse_response<TData>(mut res: Response) -> Result<TData>
+where
+    TData: DeserializeOwned,
+{
+    let mut body_buf = vec![];
+    res.copy_to(&mut body_buf)?;
+
+    let json_str = String::from_utf8(body_buf)?;
+
+    let res = match serde_json::from_str::<RestResponse<TData>>(&json_str) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("json parse fail, should be an error: {}", e);
+
+            return Err(PicupError::BadResponse(json_str).into());
+        }
+    };
+
+    if res.code() != ResponseCode::OK {
+        return Err(PicupError::Response {
+            code: res.code(),
+            msg: res.msg().to_string(),
+        }
+        .into());
+    }
+
+    match res.data {
+        Some(data) => Ok(data),
+        None => Err(PicupError::BadResponse("no data in response".to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+const PNG_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+#[test]
+fn test_local_file() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let server = test_util::MockServer::start();
+
+    let path = temp_dir().join("picup-test-local-file.png");
+    std::fs::write(&path, PNG_BYTES)?;
+
+    let urls = picup(
+        server.base_url(),
+        &[&path],
+        &UploadImgParam::new("baka", 0, "pic", false),
+    )?;
+
+    let _ = remove_file(&path);
+
+    assert_eq!(
+        urls,
+        [format!(
+            "{}/picup/asset/pic/picup-test-local-file.png",
+            server.base_url()
+        )]
+    );
+
+    let uploads = server.uploads();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].file_count(), 1);
+    assert_eq!(uploads[0].field_names(), ["file"]);
+    assert_eq!(uploads[0].query["access_token"], "baka");
+    assert_eq!(
+        uploads[0].fields[0].content_type.as_deref(),
+        Some("image/png")
+    );
+    assert_eq!(uploads[0].fields[0].bytes, PNG_BYTES);
+
     Ok(())
 }
 
 #[test]
 fn test_remote() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    println!(
-        "{:#?}",
-        picup(
-            "https://skopzz.com",
-            &["https://cdn.sisense.com/wp-content/uploads/image-1-order-blog.png"],
-            &UploadImgParam::new("baka", 0, "pic", false),
-        )?
+    let server = test_util::MockServer::builder()
+        .remote_file("picup-test-remote.png", PNG_BYTES)
+        .start();
+
+    let urls = picup(
+        server.base_url(),
+        &[server.remote_url("picup-test-remote.png")],
+        &UploadImgParam::new("baka", 0, "pic", false),
+    )?;
+
+    assert_eq!(
+        urls,
+        [format!(
+            "{}/picup/asset/pic/picup-test-remote.png",
+            server.base_url()
+        )]
+    );
+
+    let uploads = server.uploads();
+    assert_eq!(uploads[0].file_count(), 1);
+    assert_eq!(uploads[0].fields[0].bytes, PNG_BYTES);
+
+    Ok(())
+}
+
+#[test]
+fn test_remote_url() {
+    use std::path::Path;
+
+    assert_eq!(
+        remote_url(Path::new("http://example.com/cat.png")),
+        Some("http://example.com/cat.png")
+    );
+    assert_eq!(
+        remote_url(Path::new("https://example.com/cat.png")),
+        Some("https://example.com/cat.png")
+    );
+
+    for local in ["cat.png", "/tmp/cat.png", "http-cat.png", "https/cat.png"] {
+        assert_eq!(remote_url(Path::new(local)), None, "{}", local);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"http://\xff.png"));
+        assert_eq!(remote_url(path), None);
+    }
+}
+
+#[test]
+fn test_https_is_downloaded() {
+    let server = test_util::MockServer::builder()
+        .remote_file("picup-test-https.png", PNG_BYTES)
+        .start();
+
+    let url = server
+        .remote_url("picup-test-https.png")
+        .replacen("http://", "https://", 1);
+
+    assert_eq!(remote_url(std::path::Path::new(&url)), Some(url.as_str()));
+
+    // the mock server doesn't speak tls, so this only shows that the url isn't opened as a local
+    // file, which would fail with an io error instead
+
+    let e = picup(
+        server.base_url(),
+        &[&url],
+        &UploadImgParam::new("baka", 0, "pic", false),
+    )
+    .unwrap_err();
+
+    assert!(e.downcast_ref::<reqwest::Error>().is_some(), "{}", e);
+    assert!(server.uploads().is_empty());
+}
+
+#[test]
+fn test_result_order() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let server = test_util::MockServer::builder()
+        .remote_file("picup-test-order-remote.png", PNG_BYTES)
+        .reversed()
+        .start();
+
+    let local = temp_dir().join("picup-test-order-local.png");
+    std::fs::write(&local, PNG_BYTES)?;
+
+    let urls = picup(
+        server.base_url(),
+        &[
+            server.remote_url("picup-test-order-remote.png"),
+            local.to_str().unwrap().to_string(),
+        ],
+        &UploadImgParam::new("baka", 0, "pic", false),
+    );
+
+    let _ = remove_file(&local);
+
+    assert_eq!(
+        urls?,
+        [
+            format!(
+                "{}/picup/asset/pic/picup-test-order-remote.png",
+                server.base_url()
+            ),
+            format!(
+                "{}/picup/asset/pic/picup-test-order-local.png",
+                server.base_url()
+            ),
+        ]
+    );
+
+    let uploads = server.uploads();
+    assert_eq!(uploads[0].fields[0].index.as_deref(), Some("0"));
+    assert_eq!(uploads[0].fields[1].index.as_deref(), Some("1"));
+
+    // kept as is unless the indexes are a permutation of the urls
+    let urls = || vec!["a".to_string(), "b".to_string()];
+    assert_eq!(in_attached_order(urls(), Some("1, 0")), ["b", "a"]);
+    for indexes in [None, Some("0"), Some("0, 0"), Some("0, 2"), Some("x, 1")] {
+        assert_eq!(in_attached_order(urls(), indexes), urls(), "{:?}", indexes);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_uploaded_files() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let path = temp_dir().join("picup-test-uploaded-files.png");
+    std::fs::write(&path, PNG_BYTES)?;
+
+    let url = "http://127.0.0.1/picup/asset/pic/picup-test-uploaded-files-1.png";
+
+    let server = test_util::MockServer::builder()
+        .response(serde_json::json!({
+            "code": 0,
+            "msg": "ok",
+            "data": [{
+                "submitted_name": "picup-test-uploaded-files.png",
+                "stored_name": "picup-test-uploaded-files-1.png",
+                "url": url,
+                "action": "renamed",
+            }],
+        }))
+        .start();
+
+    let report = picup_with_options(
+        server.base_url(),
+        &[&path],
+        &UploadImgParam::new("baka", 0, "pic", OverridePolicy::Rename),
+        &PicupOptions::default(),
+    );
+
+    // servers older than `UploadedFile` respond the urls only
+    let legacy = test_util::MockServer::builder()
+        .response(serde_json::json!({ "code": 0, "msg": "ok", "data": [url] }))
+        .start();
+
+    let legacy_report = picup_with_options(
+        legacy.base_url(),
+        &[&path],
+        &UploadImgParam::new("baka", 0, "pic", false),
+        &PicupOptions::default(),
+    );
+
+    let _ = remove_file(&path);
+
+    let report = report?;
+    let image = &report.images()[0];
+    assert_eq!(image.url(), url);
+    assert_eq!(
+        image.submitted_name().unwrap(),
+        "picup-test-uploaded-files.png"
+    );
+    assert_eq!(
+        image.stored_name().unwrap(),
+        "picup-test-uploaded-files-1.png"
+    );
+    assert_eq!(image.action(), Some(UploadAction::Renamed));
+
+    let legacy_report = legacy_report?;
+    let image = &legacy_report.images()[0];
+    assert_eq!(image.url(), url);
+    assert_eq!(image.submitted_name(), None);
+    assert_eq!(image.action(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let server = test_util::MockServer::builder()
+        .asset("pic", "picup-test-sync-same.png", PNG_BYTES)
+        .asset("pic", "picup-test-sync-changed.png", b"\x89PNG old")
+        .start();
+
+    let same = temp_dir().join("picup-test-sync-same.png");
+    let changed = temp_dir().join("picup-test-sync-changed.png");
+    let new = temp_dir().join("picup-test-sync-new.png");
+
+    for path in [&same, &changed, &new] {
+        std::fs::write(path, PNG_BYTES)?;
+    }
+
+    let report = sync(
+        server.base_url(),
+        &[&same, &changed, &new],
+        &UploadImgParam::new("baka", 0, "pic", true),
+    );
+
+    for path in [&same, &changed, &new] {
+        let _ = remove_file(path);
+    }
+
+    let report = report?;
+
+    let url = |name: &str| format!("{}/picup/asset/pic/{}", server.base_url(), name);
+
+    assert_eq!(report.unchanged(), &[url("picup-test-sync-same.png")]);
+    assert_eq!(
+        report.uploaded(),
+        &[
+            url("picup-test-sync-changed.png"),
+            url("picup-test-sync-new.png")
+        ]
+    );
+
+    let uploads = server.uploads();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].file_count(), 2);
+    assert_eq!(uploads[0].query["override"], "true");
+    assert_eq!(
+        uploads[0].fields[0].if_none_match,
+        Some(format!("\"{}\"", sha256_hex(PNG_BYTES)))
     );
 
     Ok(())
 }
+
+#[test]
+fn test_error_response() {
+    let server = test_util::MockServer::builder()
+        .response(serde_json::json!({ "code": 1001, "msg": "invalid token", "data": null }))
+        .start();
+
+    let path = temp_dir().join("picup-test-error-response.png");
+    std::fs::write(&path, PNG_BYTES).unwrap();
+
+    let res = picup(
+        server.base_url(),
+        &[&path],
+        &UploadImgParam::new("bad", 0, "pic", false),
+    );
+
+    let _ = remove_file(&path);
+
+    let e = res.unwrap_err();
+
+    assert_eq!(e.to_string(), "invalid token");
+    assert!(matches!(
+        e.downcast_ref::<PicupError>(),
+        Some(PicupError::Response {
+            code: ResponseCode::INVALID_TOKEN,
+            ..
+        })
+    ));
+}
@@ -0,0 +1,315 @@
+//! In-process mock of the PicUp server for exercising the client without external
+//! infrastructure. Enabled by the `test-util` feature.
+
+use std::{
+    collections::HashMap,
+    net::TcpListener as StdTcpListener,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{
+        header::{ETAG, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    serve, Json, Router,
+};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, runtime::Builder, sync::oneshot};
+
+use crate::{sha256_hex, UploadAction, UploadedFile, API_BASE_URL};
+
+/// A multipart field received by the mock server.
+#[derive(Clone, Debug)]
+pub struct ReceivedField {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub if_none_match: Option<String>,
+    pub index: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
+/// An upload request received by the mock server.
+#[derive(Clone, Debug)]
+pub struct ReceivedUpload {
+    pub query: HashMap<String, String>,
+    pub fields: Vec<ReceivedField>,
+}
+
+impl ReceivedUpload {
+    pub fn file_count(&self) -> usize {
+        self.fields
+            .iter()
+            .filter(|field| field.file_name.is_some())
+            .count()
+    }
+
+    pub fn field_names(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    base_url: String,
+    response: Option<Value>,
+    uploads: Mutex<Vec<ReceivedUpload>>,
+    remote_files: HashMap<String, Vec<u8>>,
+    assets: HashMap<(String, String), Vec<u8>>,
+    reversed: bool,
+}
+
+/// Mock server implementing the `/picup/upload` contract.
+///
+/// By default it accepts everything and responds the files as created where they would be in a
+/// real server, i.e. `{base_url}/picup/asset/{category}/{file_name}`. It can also serve files under
+/// `/remote/` to test re-hosting of remote images, and assets validated by their sha256 to test
+/// syncing.
+pub struct MockServer {
+    base_url: String,
+    state: Arc<MockState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+pub struct MockServerBuilder {
+    response: Option<Value>,
+    remote_files: HashMap<String, Vec<u8>>,
+    assets: HashMap<(String, String), Vec<u8>>,
+    reversed: bool,
+}
+
+impl MockServerBuilder {
+    /// Responds the json to every upload instead of the files.
+    pub fn response(mut self, response: Value) -> Self {
+        self.response = Some(response);
+        self
+    }
+
+    /// Serves the bytes at `/remote/{name}`.
+    pub fn remote_file(mut self, name: &str, bytes: &[u8]) -> Self {
+        self.remote_files.insert(name.to_string(), bytes.to_vec());
+        self
+    }
+
+    /// Responds the urls of uploads in reverse, as if the files arrived the other way around.
+    /// Their indexes are listed along as a real server does.
+    pub fn reversed(mut self) -> Self {
+        self.reversed = true;
+        self
+    }
+
+    /// Serves the bytes at `/picup/asset/{category}/{name}`, answering 304 to `If-None-Match`
+    /// with their sha256.
+    pub fn asset(mut self, category: &str, name: &str, bytes: &[u8]) -> Self {
+        self.assets
+            .insert((category.to_string(), name.to_string()), bytes.to_vec());
+        self
+    }
+
+    pub fn start(self) -> MockServer {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(MockState {
+            base_url: base_url.clone(),
+            response: self.response,
+            uploads: Mutex::default(),
+            remote_files: self.remote_files,
+            assets: self.assets,
+            reversed: self.reversed,
+        });
+
+        let app = Router::new()
+            .route(&format!("{}/upload", API_BASE_URL), post(upload))
+            .route(
+                &format!("{}/asset/:category/:name", API_BASE_URL),
+                get(asset),
+            )
+            .route("/remote/:name", get(remote_file))
+            .with_state(state.clone());
+
+        let (shutdown, rx) = oneshot::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async move {
+                    let listener = TcpListener::from_std(listener).unwrap();
+
+                    serve(listener, app)
+                        .with_graceful_shutdown(async {
+                            let _ = rx.await;
+                        })
+                        .await
+                        .unwrap();
+                })
+        });
+
+        MockServer {
+            base_url,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl MockServer {
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::default()
+    }
+
+    pub fn start() -> Self {
+        Self::builder().start()
+    }
+
+    /// The url to pass as `base_url` of [`crate::picup`].
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Url of a file registered by [`MockServerBuilder::remote_file`].
+    pub fn remote_url(&self, name: &str) -> String {
+        format!("{}/remote/{}", self.base_url, name)
+    }
+
+    /// Uploads received so far, in order.
+    pub fn uploads(&self) -> Vec<ReceivedUpload> {
+        self.state.uploads.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn upload(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut fields = vec![];
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(|s| s.to_string());
+        let content_type = field.content_type().map(|s| s.to_string());
+        let if_none_match = field
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        let index = field
+            .headers()
+            .get("x-picup-index")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        let bytes = field.bytes().await.map(|b| b.to_vec()).unwrap_or_default();
+
+        fields.push(ReceivedField {
+            name,
+            file_name,
+            content_type,
+            if_none_match,
+            index,
+            bytes,
+        });
+    }
+
+    let category = query.get("category").cloned().unwrap_or_default();
+
+    let mut files = fields
+        .iter()
+        .filter(|field| field.file_name.is_some())
+        .collect::<Vec<_>>();
+
+    if state.reversed {
+        files.reverse();
+    }
+
+    let uploaded = files
+        .iter()
+        .map(|field| {
+            let file_name = field.file_name.as_ref().unwrap();
+
+            let url = format!(
+                "{}{}/asset/{}/{}",
+                state.base_url, API_BASE_URL, category, file_name
+            );
+
+            UploadedFile::new(file_name, file_name, &url, UploadAction::Created)
+        })
+        .collect::<Vec<UploadedFile>>();
+
+    let indexes = files
+        .iter()
+        .map(|field| field.index.clone().unwrap_or_default())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    state
+        .uploads
+        .lock()
+        .unwrap()
+        .push(ReceivedUpload { query, fields });
+
+    match &state.response {
+        Some(response) => Json(response.clone()).into_response(),
+        None => (
+            [("x-picup-index", indexes)],
+            Json(json!({ "code": 0, "msg": "ok", "data": uploaded })),
+        )
+            .into_response(),
+    }
+}
+
+async fn remote_file(State(state): State<Arc<MockState>>, Path(name): Path<String>) -> Response {
+    match state.remote_files.get(&name) {
+        Some(bytes) => bytes.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn asset(
+    State(state): State<Arc<MockState>>,
+    Path(key): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let bytes = match state.assets.get(&key) {
+        Some(bytes) => bytes,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let etag = format!("\"{}\"", sha256_hex(bytes));
+
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|v| v.as_bytes() == etag.as_bytes())
+    {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+
+    ([(ETAG, etag)], bytes.clone()).into_response()
+}
@@ -4,7 +4,13 @@ fn main() {
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     let project_dir = Path::new(&manifest_dir);
     let build_type = env::var("PROFILE").unwrap();
-    let target_dir = project_dir.join("..").join("target").join(build_type);
+    let target_dir = project_dir.join("..").join("target").join(&build_type);
+
+    println!("cargo:rustc-env=PICUP_BUILD_PROFILE={}", build_type);
+    println!(
+        "cargo:rustc-env=PICUP_BUILD_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
 
     println!("{:?}", project_dir);
     println!("{:?}", target_dir);
@@ -0,0 +1,55 @@
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use futures_util::io::copy;
+use tokio::{
+    fs::File,
+    io::{duplex, DuplexStream},
+};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::error;
+
+/// Bytes buffered between the zip writer and the response.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Zips the files on the fly into the returned stream, without holding them in memory.
+///
+/// Entries are stored uncompressed, images rarely get smaller by deflating them again.
+pub fn zip_stream(files: Vec<(String, String)>) -> DuplexStream {
+    let (reader, writer) = duplex(PIPE_CAPACITY);
+
+    tokio::spawn(async move {
+        let mut zip = ZipFileWriter::with_tokio(writer);
+
+        for (name, path) in files {
+            let file = match File::open(&path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    // removed in the meantime
+                    error!("failed to archive [{}]: {}", path, e);
+                    continue;
+                }
+            };
+
+            let entry = ZipEntryBuilder::new(name.into(), Compression::Stored);
+
+            let written = match zip.write_entry_stream(entry).await {
+                Ok(mut entry_writer) => match copy(file.compat(), &mut entry_writer).await {
+                    Ok(_) => entry_writer.close().await,
+                    Err(e) => Err(e.into()),
+                },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = written {
+                // the client is probably gone, the archive is broken anyway
+                error!("failed to archive [{}]: {}", path, e);
+                return;
+            }
+        }
+
+        if let Err(e) = zip.close().await {
+            error!("failed to finish archive: {}", e);
+        }
+    });
+
+    reader
+}
@@ -0,0 +1,72 @@
+//! Tokens limited to some operations and categories, on top of the one token of the config which
+//! may do anything.
+
+/// What a token may do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    /// originals, archives and listings, assets themselves are public
+    Read,
+
+    /// uploads and pre-signed urls for them
+    Write,
+
+    Delete,
+}
+
+impl Scope {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "delete" => Some(Scope::Delete),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Delete => "delete",
+        }
+    }
+}
+
+/// Outcome of checking a token.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Access {
+    Granted,
+
+    /// none is configured with its value
+    Invalid,
+
+    /// it's known but may not do that
+    Forbidden,
+}
+
+/// A token of `[server.tokens]`.
+pub struct ScopedToken {
+    /// its key in the config, for logs
+    pub name: String,
+
+    pub value: String,
+
+    /// categories it may access, any if not set
+    pub categories: Option<Vec<String>>,
+
+    pub scopes: Vec<Scope>,
+}
+
+impl ScopedToken {
+    /// Whether the token may access the category, such as to list its assets.
+    pub fn allows_category(&self, category: &str) -> bool {
+        self.categories
+            .as_ref()
+            .is_none_or(|categories| categories.iter().any(|c| c == category))
+    }
+
+    /// Whether the token may do `scope` on the category, or across those it may access if `None`.
+    pub fn allows(&self, scope: Scope, category: Option<&str>) -> bool {
+        self.scopes.contains(&scope) && category.is_none_or(|c| self.allows_category(c))
+    }
+}
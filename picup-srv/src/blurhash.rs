@@ -0,0 +1,68 @@
+//! [BlurHash](https://blurha.sh) placeholders of images, kept next to them under `blurhash/` for
+//! categories computing them.
+
+use image::imageops::FilterType;
+use tokio::io;
+
+use crate::SrvState;
+
+/// Components along the width and the height, which is plenty for a placeholder.
+const COMPONENTS: (u32, u32) = (4, 3);
+
+/// Side of the square images are shrunk into first, the hash hardly differs from that of the
+/// full image.
+const SAMPLE_SIZE: u32 = 32;
+
+/// BlurHash of an image file, `None` if it isn't one.
+pub fn of_image(bytes: &[u8]) -> Option<String> {
+    let image = image::load_from_memory(bytes).ok()?;
+
+    let sample = image
+        .resize_exact(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgba8();
+
+    let (x_components, y_components) = COMPONENTS;
+
+    ::blurhash::encode(
+        x_components,
+        y_components,
+        sample.width(),
+        sample.height(),
+        sample.as_raw(),
+    )
+    .ok()
+}
+
+fn blurhash_path(state: &SrvState, category: &str, file_name: &str) -> String {
+    uri_concat!(
+        &state.stored_dir("blurhash", category, file_name),
+        file_name
+    )
+}
+
+/// Records the BlurHash of an asset that has just been committed, or drops that of the one it
+/// replaced if it has none.
+pub async fn record(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    blurhash: Option<&str>,
+) -> io::Result<()> {
+    let path = blurhash_path(state, category, file_name);
+
+    match blurhash {
+        Some(blurhash) => state.storage.write(&path, blurhash.into()).await,
+        None => state.storage.remove(&path).await,
+    }
+}
+
+/// BlurHash of the asset, `None` if it has none, or it's been stored before it was computed.
+pub async fn stored(state: &SrvState, category: &str, file_name: &str) -> Option<String> {
+    let blurhash = state
+        .storage
+        .read(&blurhash_path(state, category, file_name))
+        .await
+        .ok()?;
+
+    String::from_utf8(blurhash).ok()
+}
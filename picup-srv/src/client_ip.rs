@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+
+use axum::http::HeaderMap;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// Address of the client a request is from, put into its extensions before it's handled.
+#[derive(Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Reverse proxies whose `X-Forwarded-For` and `X-Real-IP` headers are believed.
+pub struct ProxyTrust {
+    /// networks as their first address and prefix length
+    proxies: Vec<(IpAddr, u8)>,
+}
+
+/// Parses an address, or a network such as `10.0.0.0/8`.
+pub fn network(proxy: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = match proxy.split_once('/') {
+        Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (proxy.parse::<IpAddr>().ok()?, None),
+    };
+
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+
+    match prefix.unwrap_or(max) {
+        prefix if prefix <= max => Some((ip, prefix)),
+        _ => None,
+    }
+}
+
+/// Whether the address is in the network, ipv4-mapped ipv6 addresses being ipv4 ones.
+pub fn contains((network, prefix): (IpAddr, u8), ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    };
+
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+impl ProxyTrust {
+    /// Fails with the first entry that is neither an address nor a network.
+    pub fn new(proxies: &[String]) -> Result<Self, String> {
+        let proxies = proxies
+            .iter()
+            .map(|proxy| network(proxy.trim()).ok_or_else(|| proxy.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ProxyTrust { proxies })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|network| contains(*network, ip))
+    }
+
+    /// Client behind the hops in the headers, which is the last one not trusted when walking back
+    /// from `peer`, the socket peer. Anything before it may be made up by the client, and so are
+    /// the headers if `peer` isn't a trusted proxy itself, which is the client then.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trusts(peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|hop| hop.trim().parse::<IpAddr>())
+            .collect::<Result<Vec<_>, _>>();
+
+        let hops = match forwarded {
+            Ok(hops) if !hops.is_empty() => hops,
+            // a garbled chain can't be walked
+            Err(_) => return peer,
+            Ok(_) => match headers
+                .get(X_REAL_IP)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<IpAddr>().ok())
+            {
+                Some(real_ip) => vec![real_ip],
+                None => return peer,
+            },
+        };
+
+        // all of them are proxies, the first one is as close to the client as it gets
+        let first = hops[0];
+
+        hops.into_iter()
+            .rev()
+            .find(|hop| !self.trusts(*hop))
+            .unwrap_or(first)
+    }
+}
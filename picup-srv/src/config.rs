@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// Formats of the config file, which are deserialized into the same [`Config`].
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// By the extension of the file, or by its content if it's neither `.toml` nor `.json`.
+    pub fn detect(path: &Path, content: &str) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => Format::Json,
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => Format::Toml,
+            // a toml file can't start with a brace
+            _ if content.trim_start().starts_with('{') => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+}
+
+/// The whole config file, of which only `[server]` is read.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Config {
+    pub server: ServerConfig,
+}
+
+/// `[server]`, documented in `picup-srv.toml`. Values those need the filesystem or further
+/// checks (watermarks, tls, templates...) are turned into their runtime form by the caller.
+/// Keys it doesn't know, such as misspelled ones, fail it rather than leaving their defaults.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub read_timeout: Option<u64>,
+
+    /// the former timeout of all requests, `read_timeout` and `upload_timeout` take precedence
+    pub timeout: Option<u64>,
+
+    pub upload_timeout: Option<u64>,
+
+    #[serde(default = "serde_default_timeout_retry_after")]
+    pub timeout_retry_after: u64,
+
+    pub token: String,
+
+    /// tokens limited to some categories and operations, by name
+    #[serde(default)]
+    pub tokens: HashMap<String, TokenSettings>,
+
+    /// defaults to the path of the config file
+    pub directory: Option<String>,
+
+    #[serde(default = "serde_default_port")]
+    pub port: u16,
+
+    /// defaults to `http://127.0.0.1:{port}`
+    pub url: Option<String>,
+
+    pub locale_dir: Option<String>,
+
+    #[serde(default)]
+    pub download_rate: u64,
+
+    #[serde(default = "serde_default_rejection_log_level")]
+    pub rejection_log_level: String,
+
+    #[serde(default = "serde_default_max_files_per_request")]
+    pub max_files_per_request: usize,
+
+    #[serde(default = "serde_default_url_upload_max_size")]
+    pub url_upload_max_size: u64,
+
+    #[serde(default = "serde_default_url_upload_timeout")]
+    pub url_upload_timeout: u64,
+
+    /// internal networks urls may be fetched from
+    #[serde(default)]
+    pub url_fetch_allowed_networks: Vec<String>,
+
+    #[serde(default)]
+    pub min_free_bytes: u64,
+
+    #[serde(default)]
+    pub min_free_percent: f64,
+
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
+
+    #[serde(default = "serde_default_trusted_proxies")]
+    pub trusted_proxies: Vec<String>,
+
+    #[serde(default)]
+    pub image: ImageSettings,
+
+    #[serde(default)]
+    pub http: HttpSettings,
+
+    #[serde(default)]
+    pub cors: CorsSettings,
+
+    pub categories: HashMap<String, CategorySettings>,
+
+    /// uploading to a category that is not configured creates it with `default_category`
+    #[serde(default)]
+    pub auto_create_categories: bool,
+
+    #[serde(default)]
+    pub default_category: CategorySettings,
+}
+
+impl ServerConfig {
+    pub fn read_timeout(&self) -> u64 {
+        self.read_timeout
+            .or(self.timeout)
+            .unwrap_or(serde_default_read_timeout())
+    }
+
+    pub fn upload_timeout(&self) -> u64 {
+        self.upload_timeout
+            .or(self.timeout)
+            .unwrap_or(serde_default_upload_timeout())
+    }
+}
+
+/// `[server.tokens.<name>]`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenSettings {
+    pub value: String,
+
+    /// any category if not set
+    pub categories: Option<Vec<String>>,
+
+    /// names of the scopes, `read`, `write` and `delete`
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// `[server.image]`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageSettings {
+    #[serde(default = "serde_default_true")]
+    pub autorotate: bool,
+
+    #[serde(default = "serde_default_true")]
+    pub strip_metadata: bool,
+
+    pub convert_to: Option<String>,
+
+    #[serde(default = "serde_default_true")]
+    pub convert_only_if_smaller: bool,
+
+    /// in bytes, 0 for no cache
+    #[serde(default)]
+    pub decode_cache_size: usize,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        ImageSettings {
+            autorotate: true,
+            strip_metadata: true,
+            convert_to: None,
+            convert_only_if_smaller: true,
+            decode_cache_size: 0,
+        }
+    }
+}
+
+/// `[server.http]`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSettings {
+    pub tls_cert: Option<String>,
+
+    pub tls_key: Option<String>,
+
+    #[serde(default)]
+    pub h2c: bool,
+
+    #[serde(default = "serde_default_true")]
+    pub keep_alive: bool,
+
+    /// in seconds
+    pub keep_alive_interval: Option<u64>,
+
+    pub max_concurrent_streams: Option<u32>,
+
+    pub max_connections: Option<usize>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            tls_cert: None,
+            tls_key: None,
+            h2c: false,
+            keep_alive: true,
+            keep_alive_interval: None,
+            max_concurrent_streams: None,
+            max_connections: None,
+        }
+    }
+}
+
+/// `[server.cors]`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsSettings {
+    /// of the routes serving assets, `*` for any
+    #[serde(default = "serde_default_any_origin")]
+    pub asset_origins: Vec<String>,
+
+    /// of uploads and the routes taking the token, `*` for any
+    #[serde(default = "serde_default_any_origin")]
+    pub admin_origins: Vec<String>,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        CorsSettings {
+            asset_origins: serde_default_any_origin(),
+            admin_origins: serde_default_any_origin(),
+        }
+    }
+}
+
+/// `[server.categories.<name>]`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategorySettings {
+    #[serde(default)]
+    pub allow_all_files: bool,
+
+    #[serde(default)]
+    pub shard: bool,
+
+    pub autorotate: Option<bool>,
+
+    pub strip_metadata: Option<bool>,
+
+    pub watermark: Option<String>,
+
+    #[serde(default = "serde_default_watermark_position")]
+    pub watermark_position: String,
+
+    #[serde(default = "serde_default_watermark_opacity")]
+    pub watermark_opacity: f32,
+
+    pub download_rate: Option<u64>,
+
+    #[serde(default)]
+    pub keep_original: bool,
+
+    pub filename_template: Option<String>,
+
+    #[serde(default)]
+    pub correct_extension: bool,
+
+    pub min_quality: Option<u8>,
+
+    pub max_quality: Option<u8>,
+
+    pub default_compress: Option<u8>,
+
+    #[serde(default = "serde_default_thumbnail_size")]
+    pub thumbnail_size: u32,
+
+    pub thumbnail_quality: Option<u8>,
+
+    #[serde(default)]
+    pub eager_thumbnails: bool,
+
+    #[serde(default)]
+    pub blurhash: bool,
+
+    #[serde(default)]
+    pub async_processing: bool,
+
+    #[serde(default)]
+    pub allow_svg: bool,
+
+    pub max_file_size: Option<u64>,
+
+    pub allowed_referers: Option<Vec<String>>,
+
+    #[serde(default = "serde_default_true")]
+    pub allow_empty_referer: bool,
+
+    #[serde(default)]
+    pub immutable: bool,
+
+    pub cache_control: Option<String>,
+}
+
+impl Default for CategorySettings {
+    fn default() -> Self {
+        CategorySettings {
+            allow_all_files: false,
+            shard: false,
+            autorotate: None,
+            strip_metadata: None,
+            watermark: None,
+            watermark_position: serde_default_watermark_position(),
+            watermark_opacity: serde_default_watermark_opacity(),
+            download_rate: None,
+            keep_original: false,
+            filename_template: None,
+            correct_extension: false,
+            min_quality: None,
+            max_quality: None,
+            default_compress: None,
+            thumbnail_size: serde_default_thumbnail_size(),
+            thumbnail_quality: None,
+            eager_thumbnails: false,
+            blurhash: false,
+            async_processing: false,
+            allow_svg: false,
+            max_file_size: None,
+            allowed_referers: None,
+            allow_empty_referer: true,
+            immutable: false,
+            cache_control: None,
+        }
+    }
+}
+
+fn serde_default_true() -> bool {
+    true
+}
+
+fn serde_default_read_timeout() -> u64 {
+    30
+}
+
+fn serde_default_upload_timeout() -> u64 {
+    300
+}
+
+fn serde_default_timeout_retry_after() -> u64 {
+    5
+}
+
+fn serde_default_port() -> u16 {
+    19190
+}
+
+fn serde_default_rejection_log_level() -> String {
+    "info".to_string()
+}
+
+fn serde_default_max_files_per_request() -> usize {
+    crate::DEFAULT_MAX_FILES_PER_REQUEST
+}
+
+fn serde_default_url_upload_max_size() -> u64 {
+    20 << 20
+}
+
+fn serde_default_url_upload_timeout() -> u64 {
+    30
+}
+
+fn serde_default_any_origin() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn serde_default_trusted_proxies() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
+}
+
+fn serde_default_watermark_position() -> String {
+    "bottom-right".to_string()
+}
+
+fn serde_default_watermark_opacity() -> f32 {
+    0.5
+}
+
+fn serde_default_thumbnail_size() -> u32 {
+    256
+}
+
+/// `picup-srv.toml` in the directory, or `picup-srv.json` if only that exists.
+pub fn find(dir: &Path) -> PathBuf {
+    let toml = dir.join("picup-srv.toml");
+    let json = dir.join("picup-srv.json");
+
+    if !toml.exists() && json.exists() {
+        json
+    } else {
+        toml
+    }
+}
+
+pub fn parse(content: &str, format: Format) -> Result<ServerConfig, String> {
+    match format {
+        Format::Toml => toml::from_str::<Config>(content).map_err(|e| e.to_string()),
+        Format::Json => serde_json::from_str::<Config>(content).map_err(|e| e.to_string()),
+    }
+    .map(|config| config.server)
+}
@@ -0,0 +1,42 @@
+use axum::http::HeaderValue;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+
+/// Origins browsers may call a group of routes from.
+#[derive(Clone)]
+pub enum CorsOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+impl CorsOrigins {
+    /// Any origin if `*` is among them. Fails with the first one which can't be a header value.
+    pub fn new(origins: &[String]) -> Result<Self, String> {
+        if origins.iter().any(|origin| origin.trim() == "*") {
+            return Ok(CorsOrigins::Any);
+        }
+
+        origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::try_from(origin.trim().trim_end_matches('/'))
+                    .map_err(|_| origin.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(CorsOrigins::List)
+    }
+
+    /// Answers preflights and tags responses for the origins. Credentials are never allowed,
+    /// the token goes in the query, and the headers of the responses are all exposed as clients
+    /// read the actions, indexes... of uploads from them.
+    pub fn layer(&self) -> CorsLayer {
+        let layer = CorsLayer::new()
+            .allow_methods(AllowMethods::mirror_request())
+            .allow_headers(AllowHeaders::mirror_request())
+            .expose_headers(Any);
+
+        match self {
+            CorsOrigins::Any => layer.allow_origin(Any),
+            CorsOrigins::List(origins) => layer.allow_origin(AllowOrigin::list(origins.clone())),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use lru::LruCache;
+
+use crate::imaging::Decoded;
+
+/// Category, name and modification time of an asset, which changes when it's overridden.
+type Key = (String, String, SystemTime);
+
+/// Recently decoded assets, so that generating several variants of a hot asset decodes it once.
+///
+/// Bounded by the bytes of the decoded pixels, dropping the least recently used ones first.
+pub struct DecodeCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+struct Entries {
+    lru: LruCache<Key, Arc<Decoded>>,
+
+    /// bytes of the pixels of all entries
+    size: usize,
+}
+
+impl DecodeCache {
+    pub fn new(capacity: usize) -> Self {
+        DecodeCache {
+            capacity,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &Key) -> Option<Arc<Decoded>> {
+        self.entries.lock().unwrap().lru.get(key).cloned()
+    }
+
+    /// Caches the image unless it's larger than the whole cache.
+    pub fn put(&self, key: Key, decoded: Arc<Decoded>) {
+        let size = decoded.size();
+
+        if size > self.capacity {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(old) = entries.lru.put(key, decoded) {
+            entries.size -= old.size();
+        }
+
+        entries.size += size;
+
+        while entries.size > self.capacity {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.size -= evicted.size(),
+                None => break,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().lru.len()
+    }
+}
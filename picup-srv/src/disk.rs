@@ -0,0 +1,101 @@
+//! Free space of the storage volume, so that uploads are turned down before they fill it up.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::{io, task::spawn_blocking};
+use tracing::{info, warn};
+
+/// How long a look at the free space is trusted, statvfs is cheap but not free.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Available and total bytes of the volume the path is on.
+#[cfg(unix)]
+fn free_space(path: &str) -> io::Result<(u64, u64)> {
+    use std::{ffi::CString, mem::MaybeUninit};
+
+    let path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: the path is nul-terminated and `stat` is only read once it's been filled
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        stat.assume_init()
+    };
+
+    let block_size = stat.f_frsize as u64;
+
+    Ok((
+        stat.f_bavail as u64 * block_size,
+        stat.f_blocks as u64 * block_size,
+    ))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &str) -> io::Result<(u64, u64)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is only known on unix",
+    ))
+}
+
+/// Least free space of the storage volume uploads are accepted with.
+pub struct DiskGuard {
+    min_bytes: u64,
+
+    /// of the size of the volume, from 0 to 100
+    min_percent: f64,
+
+    /// when it was last looked at, and whether it was full then
+    last: Mutex<Option<(Instant, bool)>>,
+}
+
+impl DiskGuard {
+    pub fn new(min_bytes: u64, min_percent: f64) -> Self {
+        DiskGuard {
+            min_bytes,
+            min_percent,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Whether the volume of `dir` has less free space than allowed, as of at most
+    /// [`CHECK_INTERVAL`] ago. Volumes whose free space is unknown are never full.
+    pub async fn is_full(&self, dir: &str) -> bool {
+        let was_full = match *self.last.lock().unwrap() {
+            Some((checked, full)) if checked.elapsed() < CHECK_INTERVAL => return full,
+            Some((_, full)) => full,
+            None => false,
+        };
+
+        let dir = dir.to_string();
+
+        let full = match spawn_blocking(move || free_space(&dir)).await {
+            Ok(Ok((available, total))) => {
+                available < self.min_bytes
+                    || (available as f64) < total as f64 * self.min_percent / 100.0
+            }
+            Ok(Err(e)) => {
+                warn!("failed to get free space of the storage: {}", e);
+                false
+            }
+            Err(_) => false,
+        };
+
+        if full && !was_full {
+            warn!("storage is running out of space, uploads are rejected until it's freed");
+        } else if !full && was_full {
+            info!("storage has enough space again, uploads are accepted");
+        }
+
+        *self.last.lock().unwrap() = Some((Instant::now(), full));
+
+        full
+    }
+}
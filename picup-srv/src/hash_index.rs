@@ -0,0 +1,143 @@
+//! Sha256 of assets as they were uploaded, kept next to them under `hash/` so that unchanged
+//! files can be told apart without reading them again. The names of the assets of each hash are
+//! kept under `by_hash/` as well, so that they are found by their content.
+
+use axum::http::HeaderValue;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{read_dir, read_to_string},
+    io,
+};
+
+use crate::SrvState;
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn hash_path(state: &SrvState, category: &str, file_name: &str) -> String {
+    uri_concat!(&state.stored_dir("hash", category, file_name), file_name)
+}
+
+/// Directory of the names by hash, sharded by the first byte of the hashes.
+fn by_hash_dir(state: &SrvState, category: &str, hash: &str) -> String {
+    uri_concat!(&state.pic_directory, "by_hash", category, &hash[0..2])
+}
+
+/// Records the hash of an asset that has just been committed.
+pub async fn record(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    hash: &str,
+) -> io::Result<()> {
+    state
+        .storage
+        .write(&hash_path(state, category, file_name), hash.into())
+        .await?;
+
+    let path = uri_concat!(&by_hash_dir(state, category, hash), hash);
+
+    // one name per line, those which have changed since are skipped when found
+    let mut names = match state.storage.read(&path).await {
+        Ok(names) => String::from_utf8_lossy(&names).into_owned(),
+        Err(_) => String::new(),
+    };
+
+    if names.lines().any(|name| name == file_name) {
+        return Ok(());
+    }
+
+    names.push_str(file_name);
+    names.push('\n');
+
+    state.storage.write(&path, names.into()).await
+}
+
+/// Assets of the category whose hash starts with `prefix`, one per hash along with it. Assets
+/// are only found once they have been hashed, which is on upload or when asked for their hash.
+///
+/// `prefix` is lowercase hex of 2 digits at least.
+pub async fn find(
+    state: &SrvState,
+    category: &str,
+    prefix: &str,
+) -> io::Result<Vec<(String, String)>> {
+    let mut entries = match read_dir(by_hash_dir(state, category, prefix)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut found = vec![];
+
+    while let Some(entry) = entries.next_entry().await? {
+        let hash = entry.file_name().to_string_lossy().to_string();
+
+        if !hash.starts_with(prefix) {
+            continue;
+        }
+
+        for name in read_to_string(entry.path()).await?.lines() {
+            // replaced or removed since
+            if stored(state, category, name).await?.as_deref() == Some(hash.as_str()) {
+                found.push((hash, name.to_string()));
+                break;
+            }
+        }
+    }
+
+    found.sort();
+
+    Ok(found)
+}
+
+/// Hash of the asset, `None` if it doesn't exist.
+///
+/// Assets stored before the index existed, or replaced on disk behind its back, are hashed as
+/// they are now and recorded, which only matches their upload if they weren't processed.
+pub async fn stored(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+) -> io::Result<Option<String>> {
+    let asset_path = state.asset_path(category, file_name);
+
+    let asset_modified = match state.storage.modified(&asset_path).await? {
+        Some(modified) => modified,
+        None => return Ok(None),
+    };
+
+    let hash_path = hash_path(state, category, file_name);
+
+    if let Some(modified) = state.storage.modified(&hash_path).await? {
+        if modified >= asset_modified {
+            let hash = state.storage.read(&hash_path).await?;
+
+            return String::from_utf8(hash).map(Some).map_err(io::Error::other);
+        }
+    }
+
+    let hash = sha256_hex(&state.storage.read(&asset_path).await?);
+
+    record(state, category, file_name, &hash).await?;
+
+    Ok(Some(hash))
+}
+
+/// `ETag` of an asset, which is its hash quoted.
+pub fn etag(hash: &str) -> HeaderValue {
+    // hex digits only
+    HeaderValue::try_from(format!("\"{}\"", hash)).unwrap()
+}
+
+/// Whether the `If-None-Match` header lists the hash, as a quoted entity tag or `*`.
+pub fn matches(if_none_match: &str, hash: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag).trim_matches('"') == hash)
+}
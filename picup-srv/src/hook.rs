@@ -0,0 +1,22 @@
+use futures_util::future::BoxFuture;
+
+/// In-process handler of committed uploads, for embedders indexing them into their own database,
+/// purging a cdn and such.
+///
+/// It's awaited for each file once all files of the request are committed and before the response
+/// is sent, so anything slow should be spawned off instead.
+pub trait UploadHook: Send + Sync {
+    /// Called with the category, the name the file is stored as, its path on disk and its url.
+    /// Does nothing by default.
+    fn on_uploaded<'a>(
+        &'a self,
+        category: &'a str,
+        name: &'a str,
+        path: &'a str,
+        url: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        let _ = (category, name, path, url);
+
+        Box::pin(async {})
+    }
+}
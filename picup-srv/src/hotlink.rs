@@ -0,0 +1,56 @@
+/// Referers allowed to embed the images of a category.
+pub struct Hotlink {
+    /// each allows the domain itself and its subdomains
+    domains: Vec<String>,
+
+    /// whether requests without a referer (direct visits, privacy settings...) are allowed
+    allow_empty: bool,
+}
+
+/// Host of a referer url, without the port and in lowercase.
+fn host(referer: &str) -> Option<String> {
+    let (_, rest) = referer.split_once("://")?;
+
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+
+    // ipv6 literals keep their colons
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+
+    Some(host.trim_end_matches('.').to_ascii_lowercase()).filter(|host| !host.is_empty())
+}
+
+impl Hotlink {
+    pub fn new(domains: Vec<String>, allow_empty: bool) -> Self {
+        Hotlink {
+            domains: domains
+                .into_iter()
+                .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+                .collect(),
+            allow_empty,
+        }
+    }
+
+    /// Whether the `Referer` header allows serving the image.
+    pub fn allows(&self, referer: Option<&str>) -> bool {
+        let referer = match referer.map(str::trim) {
+            Some(referer) if !referer.is_empty() => referer,
+            _ => return self.allow_empty,
+        };
+
+        let host = match host(referer) {
+            Some(host) => host,
+            None => return false,
+        };
+
+        self.domains.iter().any(|domain| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashMap, convert::Infallible, fs, path::Path, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::ACCEPT_LANGUAGE, request::Parts},
+};
+use picup_lib::ResponseCode;
+use toml::Table;
+use tracing::warn;
+
+use crate::SrvState;
+
+const DEFAULT_LANG: &str = "en";
+
+fn default_message(code: ResponseCode) -> &'static str {
+    match code {
+        ResponseCode::OK => "ok",
+        ResponseCode::BAD_REQUEST => "bad request",
+        ResponseCode::NOT_FOUND => "not found",
+        ResponseCode::METHOD_NOT_ALLOWED => "method not allowed",
+        ResponseCode::PAYLOAD_TOO_LARGE => "payload too large",
+        ResponseCode::NOT_IMPLEMENTED => "not implemented",
+        ResponseCode::INTERNAL_ERROR => "internal error",
+        ResponseCode::INVALID_TOKEN => "invalid token",
+        ResponseCode::BAD_FILE_NAME => "invalid file name",
+        ResponseCode::NOT_A_IMAGE => "not an image",
+        ResponseCode::FILE_EXISTED => "file existed",
+        ResponseCode::BAD_FILE => "bad file",
+        ResponseCode::INVALID_CATEGORY => "invalid category",
+        ResponseCode::MAINTENANCE => "server under maintenance",
+        ResponseCode::FILE_NOT_FOUND => "file not found",
+        ResponseCode::INVALID_PARAM => "invalid parameter",
+        ResponseCode::TIMEOUT => "timed out, retry later or with fewer files",
+        ResponseCode::FILE_TOO_LARGE => "file too large",
+        ResponseCode::TOO_MANY_FILES => "too many files",
+        ResponseCode::HOTLINK_DENIED => "embedding from this site is not allowed",
+        ResponseCode::DISK_FULL => "server storage is full, retry later",
+        ResponseCode::FORBIDDEN => "token not allowed to do this",
+        ResponseCode::URL_BLOCKED => "url may not be fetched by the server",
+        _ => "unknown error",
+    }
+}
+
+/// Human readable text of every [`ResponseCode`], overridable per language by locale files.
+///
+/// A locale file is a toml file named after the language tag (e.g. `zh-CN.toml`) mapping the
+/// names of codes to messages:
+///
+/// ```toml
+/// INVALID_TOKEN = "无效的令牌"
+/// ```
+#[derive(Default)]
+pub struct Messages {
+    locales: HashMap<String, HashMap<ResponseCode, String>>,
+}
+
+impl Messages {
+    pub fn load(dir: &Path) -> Self {
+        let mut messages = Messages::default();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("failed to read locale directory [{:?}]: {}", dir, e);
+                return messages;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+
+            let lang = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(lang) => lang.to_lowercase(),
+                None => continue,
+            };
+
+            let table = match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| s.parse::<Table>().map_err(|e| e.to_string()))
+            {
+                Ok(table) => table,
+                Err(e) => {
+                    warn!("failed to load locale file [{:?}]: {}", path, e);
+                    continue;
+                }
+            };
+
+            let mut texts = HashMap::new();
+
+            for (name, text) in table {
+                match (ResponseCode::from_name(&name), text.as_str()) {
+                    (Some(code), Some(text)) => {
+                        texts.insert(code, text.to_string());
+                    }
+                    _ => warn!("unknown message [{}] in locale file [{:?}]", name, path),
+                }
+            }
+
+            messages.locales.insert(lang, texts);
+        }
+
+        messages
+    }
+
+    /// Picks the best language available for an `Accept-Language` header value.
+    fn negotiate(&self, accept_language: &str) -> Option<String> {
+        let mut tags = accept_language
+            .split(',')
+            .filter_map(|tag| {
+                let mut parts = tag.trim().split(';');
+                let lang = parts.next()?.trim().to_lowercase();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+
+                Some((lang, q))
+            })
+            .filter(|(lang, q)| !lang.is_empty() && *q > 0.0)
+            .collect::<Vec<(String, f32)>>();
+
+        // stable, so equally preferred tags keep the client's order
+        tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        tags.into_iter().find_map(|(lang, _)| {
+            if self.locales.contains_key(&lang) {
+                return Some(lang);
+            }
+
+            // "zh-cn" falls back to "zh"
+            let primary = lang.split('-').next()?;
+
+            self.locales
+                .contains_key(primary)
+                .then(|| primary.to_string())
+        })
+    }
+
+    fn text(&self, lang: Option<&str>, code: ResponseCode) -> String {
+        lang.into_iter()
+            .chain([DEFAULT_LANG])
+            .find_map(|lang| self.locales.get(lang)?.get(&code))
+            .map(|text| text.to_string())
+            .unwrap_or_else(|| default_message(code).to_string())
+    }
+}
+
+/// Language of the client resolved from its `Accept-Language` header.
+pub struct Locale {
+    messages: Arc<Messages>,
+    lang: Option<String>,
+}
+
+impl Locale {
+    pub fn msg(&self, code: ResponseCode) -> String {
+        self.messages.text(self.lang.as_deref(), code)
+    }
+
+    pub fn msg_with(&self, code: ResponseCode, detail: &str) -> String {
+        format!("{}: {}", self.msg(code), detail)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<SrvState>> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<SrvState>,
+    ) -> Result<Self, Self::Rejection> {
+        let lang = parts
+            .headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| state.messages.negotiate(v));
+
+        Ok(Locale {
+            messages: state.messages.clone(),
+            lang,
+        })
+    }
+}
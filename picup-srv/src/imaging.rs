@@ -0,0 +1,648 @@
+use std::{io::Cursor, sync::Arc, time::SystemTime};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    imageops::{overlay, FilterType},
+    metadata::Orientation,
+    DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader, RgbaImage,
+};
+use tokio::{
+    fs::{create_dir_all, metadata, read, rename, write},
+    io,
+    task::spawn_blocking,
+};
+
+use crate::{fnv1a, SrvState};
+
+/// Quality of re-encoded jpeg images, unless asked otherwise.
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Where a generated variant of an asset is cached, e.g. `cache/pic/autorotate/a.jpg`.
+fn variant_path(state: &SrvState, category: &str, variant: &str, file_name: &str) -> String {
+    uri_concat!(&state.pic_directory, "cache", category, variant, file_name)
+}
+
+async fn modified(path: &str) -> Option<SystemTime> {
+    metadata(path).await.ok()?.modified().ok()
+}
+
+/// An asset decoded and upright.
+pub struct Decoded {
+    image: DynamicImage,
+    format: ImageFormat,
+    icc_profile: Option<Vec<u8>>,
+
+    /// whether the EXIF orientation had to be applied
+    rotated: bool,
+}
+
+impl Decoded {
+    /// Bytes of the pixels.
+    pub fn size(&self) -> usize {
+        self.image.as_bytes().len()
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<Decoded> {
+    let (mut decoder, format) = decoder(bytes)?;
+
+    let orientation = decoder.orientation().ok()?;
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+
+    image.apply_orientation(orientation);
+
+    Some(Decoded {
+        image,
+        format,
+        icc_profile,
+        rotated: orientation != Orientation::NoTransforms,
+    })
+}
+
+/// The asset decoded, from [`SrvState::decode_cache`] if it's on, or `None` if it isn't an image.
+async fn decoded(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    path: &str,
+    modified: Option<SystemTime>,
+) -> io::Result<Option<Arc<Decoded>>> {
+    let cache = state.decode_cache.as_ref();
+
+    let key = modified.map(|modified| (category.to_string(), file_name.to_string(), modified));
+
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        if let Some(decoded) = cache.get(key) {
+            return Ok(Some(decoded));
+        }
+    }
+
+    let bytes = read(path).await?;
+
+    let decoded = spawn_blocking(move || decode(&bytes))
+        .await
+        .map_err(io::Error::other)?;
+
+    let decoded = match decoded {
+        Some(decoded) => Arc::new(decoded),
+        None => return Ok(None),
+    };
+
+    if let (Some(cache), Some(key)) = (cache, key) {
+        cache.put(key, decoded.clone());
+    }
+
+    Ok(Some(decoded))
+}
+
+/// Path of a variant of an asset, generated by `make` from the decoded original and cached
+/// until the original changes.
+///
+/// `make` returns `None` when the variant would be the same as the original, for which `None`
+/// is returned as well, as it is for assets that aren't images.
+pub async fn variant<F>(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    variant: &str,
+    make: F,
+) -> io::Result<Option<String>>
+where
+    F: FnOnce(&Decoded) -> Option<Vec<u8>> + Send + 'static,
+{
+    let original_path = state.asset_path(category, file_name);
+    let path = variant_path(state, category, variant, file_name);
+
+    let original_modified = modified(&original_path).await;
+
+    if let (Some(cached), Some(original)) = (modified(&path).await, original_modified) {
+        if cached >= original {
+            return Ok(Some(path));
+        }
+    }
+
+    let original = decoded(
+        state,
+        category,
+        file_name,
+        &original_path,
+        original_modified,
+    )
+    .await?;
+
+    let original = match original {
+        Some(original) => original,
+        None => return Ok(None),
+    };
+
+    let made = spawn_blocking(move || make(&original))
+        .await
+        .map_err(io::Error::other)?;
+
+    let made = match made {
+        Some(made) => made,
+        None => return Ok(None),
+    };
+
+    create_dir_all(uri_concat!(
+        &state.pic_directory,
+        "cache",
+        category,
+        variant
+    ))
+    .await?;
+
+    // other requests may be reading the old one
+    let part_path = format!("{}.part", path);
+    write(&part_path, made).await?;
+    rename(&part_path, &path).await?;
+
+    Ok(Some(path))
+}
+
+fn decoder(bytes: &[u8]) -> Option<(impl ImageDecoder + '_, ImageFormat)> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?;
+
+    let format = reader.format()?;
+
+    Some((reader.into_decoder().ok()?, format))
+}
+
+/// Encodes the image in its original format if possible, or png otherwise. The color profile is
+/// kept for jpeg and png, any other metadata is dropped.
+pub fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    jpeg_quality: u8,
+    icc_profile: Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let mut buf = Cursor::new(vec![]);
+
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut buf, jpeg_quality);
+
+            if let Some(icc_profile) = icc_profile {
+                let _ = encoder.set_icc_profile(icc_profile);
+            }
+
+            // jpeg has no alpha channel
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(encoder)
+                .ok()?;
+        }
+        ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Bmp => {
+            image.write_to(&mut buf, format).ok()?;
+        }
+        _ => {
+            let mut encoder = PngEncoder::new(&mut buf);
+
+            if let Some(icc_profile) = icc_profile {
+                let _ = encoder.set_icc_profile(icc_profile);
+            }
+
+            image.write_with_encoder(encoder).ok()?;
+        }
+    }
+
+    Some(buf.into_inner())
+}
+
+/// Encodes the image with its EXIF orientation applied, or `None` if it was upright already.
+pub fn autorotate(decoded: &Decoded) -> Option<Vec<u8>> {
+    if !decoded.rotated {
+        return None;
+    }
+
+    encode(
+        &decoded.image,
+        decoded.format,
+        DEFAULT_JPEG_QUALITY,
+        decoded.icc_profile.clone(),
+    )
+}
+
+/// Scales a still image down to fit within `width` by `height` keeping its aspect ratio, where 0
+/// leaves the dimension unbounded, or `None` if it fits already or isn't a still image.
+pub fn resize(decoded: &Decoded, width: u32, height: u32) -> Option<Vec<u8>> {
+    let image = &decoded.image;
+
+    // gifs may be animated
+    if !matches!(
+        decoded.format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Bmp
+    ) {
+        return None;
+    }
+
+    let width = if width == 0 { u32::MAX } else { width };
+    let height = if height == 0 { u32::MAX } else { height };
+
+    if image.width() <= width && image.height() <= height {
+        return None;
+    }
+
+    let resized = image.resize(width, height, FilterType::Lanczos3);
+
+    encode(
+        &resized,
+        decoded.format,
+        DEFAULT_JPEG_QUALITY,
+        decoded.icc_profile.clone(),
+    )
+}
+
+/// Webp thumbnail of the image fitting in a square of `size`, which is never scaled up.
+///
+/// It's lossless without a `quality` from 1 to 100, which still beats jpeg at the size of
+/// thumbnails for most images. Both keep the alpha channel.
+pub fn webp_thumbnail(decoded: &Decoded, size: u32, quality: Option<u8>) -> Option<Vec<u8>> {
+    let image = &decoded.image;
+
+    let thumbnail = if image.width() <= size && image.height() <= size {
+        image.clone()
+    } else {
+        image.thumbnail(size, size)
+    };
+
+    // the encoders take 8-bit rgb(a) only
+    let thumbnail = if thumbnail.color().has_alpha() {
+        DynamicImage::ImageRgba8(thumbnail.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+    };
+
+    // that of `image` is lossless only
+    if let Some(quality) = quality {
+        let (width, height) = (thumbnail.width(), thumbnail.height());
+
+        let encoder = match &thumbnail {
+            DynamicImage::ImageRgba8(rgba) => webp::Encoder::from_rgba(rgba, width, height),
+            _ => webp::Encoder::from_rgb(thumbnail.as_bytes(), width, height),
+        };
+
+        return Some(encoder.encode(f32::from(quality)).to_vec());
+    }
+
+    let mut buf = Cursor::new(vec![]);
+    thumbnail.write_to(&mut buf, ImageFormat::WebP).ok()?;
+
+    Some(buf.into_inner())
+}
+
+/// How uploaded images are transformed before being stored.
+pub struct UploadProcessing {
+    /// apply the EXIF orientation to the pixels
+    pub autorotate: bool,
+
+    /// drop EXIF, XMP and IPTC metadata
+    pub strip_metadata: bool,
+
+    /// format to store images in
+    pub convert_to: Option<ImageFormat>,
+
+    /// keep the image as is if converting it doesn't make it smaller
+    pub convert_only_if_smaller: bool,
+
+    pub watermark: Option<Arc<Watermark>>,
+
+    /// quality jpeg images are re-encoded at, even those that need nothing else
+    pub quality: Option<u8>,
+}
+
+/// Where a watermark is placed in an image.
+#[derive(Clone, Copy)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top-left" => Some(Self::TopLeft),
+            "top-right" => Some(Self::TopRight),
+            "bottom-left" => Some(Self::BottomLeft),
+            "bottom-right" => Some(Self::BottomRight),
+            "center" => Some(Self::Center),
+            _ => None,
+        }
+    }
+}
+
+/// A logo drawn over uploaded images.
+pub struct Watermark {
+    pub logo: RgbaImage,
+    pub position: WatermarkPosition,
+
+    /// from 0 (invisible) to 1 (as opaque as the logo)
+    pub opacity: f32,
+}
+
+/// Pixels between a watermark in a corner and the edges of the image.
+const WATERMARK_MARGIN: u32 = 8;
+
+impl Watermark {
+    fn draw(&self, image: &DynamicImage) -> DynamicImage {
+        let mut canvas = image.to_rgba8();
+
+        let (width, height) = canvas.dimensions();
+
+        // a logo larger than the image is fit in it
+        let mut logo = if self.logo.width() > width || self.logo.height() > height {
+            DynamicImage::ImageRgba8(self.logo.clone())
+                .thumbnail(width, height)
+                .to_rgba8()
+        } else {
+            self.logo.clone()
+        };
+
+        for pixel in logo.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * self.opacity.clamp(0.0, 1.0)).round() as u8;
+        }
+
+        let (free_x, free_y) = (width - logo.width(), height - logo.height());
+        let (margin_x, margin_y) = (
+            WATERMARK_MARGIN.min(free_x / 2),
+            WATERMARK_MARGIN.min(free_y / 2),
+        );
+
+        let (x, y) = match self.position {
+            WatermarkPosition::TopLeft => (margin_x, margin_y),
+            WatermarkPosition::TopRight => (free_x - margin_x, margin_y),
+            WatermarkPosition::BottomLeft => (margin_x, free_y - margin_y),
+            WatermarkPosition::BottomRight => (free_x - margin_x, free_y - margin_y),
+            WatermarkPosition::Center => (free_x / 2, free_y / 2),
+        };
+
+        overlay(&mut canvas, &logo, x as i64, y as i64);
+
+        DynamicImage::ImageRgba8(canvas)
+    }
+}
+
+impl UploadProcessing {
+    fn jpeg_quality(&self) -> u8 {
+        self.quality.unwrap_or(DEFAULT_JPEG_QUALITY)
+    }
+}
+
+/// An uploaded image after processing.
+pub struct Processed {
+    pub bytes: Vec<u8>,
+
+    /// set if the image has been converted to another format
+    pub converted_to: Option<ImageFormat>,
+}
+
+/// Processes an uploaded image, or `None` if it's stored as is.
+pub fn process_upload(bytes: &[u8], processing: &UploadProcessing) -> Option<Processed> {
+    let oriented = match &processing.watermark {
+        Some(watermark) => apply_watermark(bytes, watermark, processing.jpeg_quality()),
+        None => orient_and_strip(bytes, processing),
+    };
+    let current = oriented.as_deref().unwrap_or(bytes);
+
+    if let Some(to) = processing.convert_to {
+        let converted = convert(current, to, processing.jpeg_quality()).filter(|converted| {
+            !processing.convert_only_if_smaller || converted.len() < current.len()
+        });
+
+        if let Some(converted) = converted {
+            return Some(Processed {
+                bytes: converted,
+                converted_to: Some(to),
+            });
+        }
+    }
+
+    oriented.map(|bytes| Processed {
+        bytes,
+        converted_to: None,
+    })
+}
+
+/// Applies the orientation, strips metadata and re-encodes jpeg images at the quality as
+/// configured, or `None` if there is nothing to do, which is also the case for anything other
+/// than jpeg and png.
+///
+/// The orientation is always applied before re-encoding, since the orientation tag would be gone
+/// along with the rest of the metadata.
+fn orient_and_strip(bytes: &[u8], processing: &UploadProcessing) -> Option<Vec<u8>> {
+    let (mut decoder, format) = decoder(bytes)?;
+
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        return None;
+    }
+
+    let orientation = decoder.orientation().ok()?;
+
+    let has_metadata = decoder.exif_metadata().ok()?.is_some()
+        || decoder.xmp_metadata().ok()?.is_some()
+        || decoder.iptc_metadata().ok()?.is_some();
+
+    let rotate = processing.autorotate && orientation != Orientation::NoTransforms;
+    let strip = processing.strip_metadata && has_metadata;
+    let recompress = processing.quality.is_some() && format == ImageFormat::Jpeg;
+
+    if !rotate && !strip && !recompress {
+        return None;
+    }
+
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+
+    image.apply_orientation(orientation);
+
+    encode(&image, format, processing.jpeg_quality(), icc_profile)
+}
+
+/// Draws the watermark over a still image, which is upright afterwards and without metadata
+/// other than the color profile, or `None` if it isn't an image.
+fn apply_watermark(bytes: &[u8], watermark: &Watermark, jpeg_quality: u8) -> Option<Vec<u8>> {
+    let (mut decoder, format) = decoder(bytes)?;
+
+    if !matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Bmp
+    ) {
+        return None;
+    }
+
+    let orientation = decoder.orientation().ok()?;
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+
+    image.apply_orientation(orientation);
+
+    encode(&watermark.draw(&image), format, jpeg_quality, icc_profile)
+}
+
+/// Re-encodes a still image in another format, or `None` if it's in that format already.
+fn convert(bytes: &[u8], to: ImageFormat, jpeg_quality: u8) -> Option<Vec<u8>> {
+    let (mut decoder, format) = decoder(bytes)?;
+
+    // gifs may be animated
+    if format == to
+        || !matches!(
+            format,
+            ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Bmp
+        )
+    {
+        return None;
+    }
+
+    let orientation = decoder.orientation().ok()?;
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+
+    image.apply_orientation(orientation);
+
+    encode(&image, to, jpeg_quality, icc_profile)
+}
+
+/// Extension of the format sniffed from the content, or `None` if that isn't an image or the
+/// extension of `file_name` already matches it, e.g. `jpg` for a jpeg named `a.png`.
+pub fn corrected_extension(file_name: &str, bytes: &[u8]) -> Option<&'static str> {
+    let format = image::guess_format(bytes).ok()?;
+
+    if ImageFormat::from_path(file_name).is_ok_and(|named| named == format) {
+        return None;
+    }
+
+    format.extensions_str().first().copied()
+}
+
+/// Outcome of [`montage`].
+pub enum Montage {
+    /// path of the montage
+    Made(String),
+
+    /// name of a file that couldn't be decoded
+    NotAnImage(String),
+}
+
+/// Path of a png of the thumbnails of the files arranged in a grid, in the order of `file_names`,
+/// cached until any of them changes.
+///
+/// The files must exist, and `file_names` should be sorted since it's the key of the cache.
+pub async fn montage(
+    state: &SrvState,
+    category: &str,
+    file_names: &[String],
+    columns: u32,
+    cell: u32,
+) -> io::Result<Montage> {
+    let path = uri_concat!(
+        &state.pic_directory,
+        "cache",
+        category,
+        "montage",
+        &format!(
+            "{:08x}-{}x{}.png",
+            fnv1a(file_names.join("/").as_bytes()),
+            columns,
+            cell
+        )
+    );
+
+    let mut originals = vec![];
+    let mut newest = SystemTime::UNIX_EPOCH;
+
+    for file_name in file_names {
+        let original_path = state.asset_path(category, file_name);
+
+        newest = newest.max(metadata(&original_path).await?.modified()?);
+        originals.push((file_name.clone(), original_path));
+    }
+
+    if modified(&path).await.is_some_and(|cached| cached >= newest) {
+        return Ok(Montage::Made(path));
+    }
+
+    let mut images = vec![];
+
+    for (file_name, original_path) in originals {
+        images.push((file_name, read(&original_path).await?));
+    }
+
+    let made = spawn_blocking(move || compose(images, columns, cell))
+        .await
+        .map_err(io::Error::other)?;
+
+    let made = match made {
+        Ok(Some(made)) => made,
+        Ok(None) => return Err(io::Error::other("failed to encode montage")),
+        Err(file_name) => return Ok(Montage::NotAnImage(file_name)),
+    };
+
+    create_dir_all(uri_concat!(
+        &state.pic_directory,
+        "cache",
+        category,
+        "montage"
+    ))
+    .await?;
+
+    let part_path = format!("{}.part", path);
+    write(&part_path, made).await?;
+    rename(&part_path, &path).await?;
+
+    Ok(Montage::Made(path))
+}
+
+/// Upright thumbnail of the image fitting in a square of `size`.
+fn thumbnail(bytes: &[u8], size: u32) -> Option<DynamicImage> {
+    let (mut decoder, _) = decoder(bytes)?;
+
+    let orientation = decoder.orientation().ok()?;
+
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+
+    image.apply_orientation(orientation);
+
+    Some(image.thumbnail(size, size))
+}
+
+/// Draws the thumbnails centered in their cells, or the name of the first file which isn't an
+/// image.
+fn compose(
+    images: Vec<(String, Vec<u8>)>,
+    columns: u32,
+    cell: u32,
+) -> Result<Option<Vec<u8>>, String> {
+    let rows = (images.len() as u32).div_ceil(columns);
+
+    let mut canvas = RgbaImage::new(columns * cell, rows * cell);
+
+    for (i, (file_name, bytes)) in images.into_iter().enumerate() {
+        let thumbnail = match thumbnail(&bytes, cell) {
+            Some(thumbnail) => thumbnail,
+            None => return Err(file_name),
+        };
+
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+
+        let x = column * cell + (cell - thumbnail.width()) / 2;
+        let y = row * cell + (cell - thumbnail.height()) / 2;
+
+        overlay(&mut canvas, &thumbnail.to_rgba8(), x as i64, y as i64);
+    }
+
+    Ok(encode(
+        &DynamicImage::ImageRgba8(canvas),
+        ImageFormat::Png,
+        DEFAULT_JPEG_QUALITY,
+        None,
+    ))
+}
@@ -0,0 +1,151 @@
+//! Uploads to categories processing them in the background, or reprocessing of categories, which
+//! are followed at `/job/:id` until they are done. Jobs are only kept in memory, those of a server
+//! that restarted are gone along with their processing.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use picup_lib::{JobInfo, JobStatus};
+use uuid::Uuid;
+
+/// How long finished jobs are still told of.
+const RETENTION: Duration = Duration::from_secs(3600);
+
+struct Job {
+    info: JobInfo,
+
+    /// files it tells as processing until it's done, by category and name
+    files: Vec<(String, String)>,
+
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+
+    /// files of the jobs still processing by category and name, along with the number of those
+    /// jobs, as a file may be uploaded again in the meantime
+    processing: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl Jobs {
+    /// Starts a job processing the files of `(name, url)`, which are all processing until it's
+    /// done, dropping those finished long ago.
+    pub fn start(&self, category: &str, files: &[(String, String)]) -> String {
+        let names = files
+            .iter()
+            .map(|(name, _)| (category.to_string(), name.clone()))
+            .collect::<Vec<_>>();
+
+        for (category, name) in &names {
+            self.mark(category, name);
+        }
+
+        self.insert(category, files, names)
+    }
+
+    /// Starts a job going through the files of `(name, url)`, which are only processing while
+    /// it's [`Jobs::mark`]ed at them, such as assets being reprocessed one after another.
+    pub fn start_unmarked(&self, category: &str, files: &[(String, String)]) -> String {
+        self.insert(category, files, vec![])
+    }
+
+    /// Tells the file as processing until it's [`Jobs::unmark`]ed.
+    pub fn mark(&self, category: &str, file_name: &str) {
+        *self
+            .processing
+            .lock()
+            .unwrap()
+            .entry((category.to_string(), file_name.to_string()))
+            .or_default() += 1;
+    }
+
+    pub fn unmark(&self, category: &str, file_name: &str) {
+        let mut processing = self.processing.lock().unwrap();
+
+        let file = (category.to_string(), file_name.to_string());
+
+        if let Some(jobs) = processing.get_mut(&file) {
+            *jobs -= 1;
+
+            if *jobs == 0 {
+                processing.remove(&file);
+            }
+        }
+    }
+
+    /// Keeps the job, whose `files` are unmarked once it's done.
+    fn insert(
+        &self,
+        category: &str,
+        files: &[(String, String)],
+        marked: Vec<(String, String)>,
+    ) -> String {
+        let id = Uuid::new_v4().to_string();
+
+        let urls = files.iter().map(|(_, url)| url.clone()).collect();
+
+        let mut jobs = self.jobs.lock().unwrap();
+
+        jobs.retain(|_, job| job.finished.is_none_or(|at| at.elapsed() < RETENTION));
+
+        jobs.insert(
+            id.clone(),
+            Job {
+                info: JobInfo::new(&id, category, urls),
+                files: marked,
+                finished: None,
+            },
+        );
+
+        id
+    }
+
+    /// Counts one more file of the job done.
+    pub fn advance(&self, id: &str) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.info.advance();
+        }
+    }
+
+    /// Marks the job as done, or failed with the error.
+    pub fn finish(&self, id: &str, error: Option<&str>) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let job = match jobs.get_mut(id) {
+            Some(job) => job,
+            None => return,
+        };
+
+        match error {
+            Some(error) => job.info.set_status(JobStatus::Failed, Some(error)),
+            None => job.info.set_status(JobStatus::Done, None),
+        }
+
+        job.finished = Some(Instant::now());
+
+        for (category, name) in std::mem::take(&mut job.files) {
+            self.unmark(&category, &name);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|job| job.info.clone())
+    }
+
+    /// Whether the asset is served as uploaded until its job is done.
+    pub fn is_processing(&self, category: &str, file_name: &str) -> bool {
+        self.processing
+            .lock()
+            .unwrap()
+            .contains_key(&(category.to_string(), file_name.to_string()))
+    }
+}
@@ -0,0 +1,3601 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Once,
+    },
+};
+use std::{env, process};
+
+use axum::body::to_bytes;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{
+    CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+    ETAG, EXPECT, IF_NONE_MATCH, REFERER, RETRY_AFTER, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderMap, HeaderValue, Response};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::IntoResponse;
+use axum::Extension;
+use axum::{
+    body::Body,
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use image::ImageFormat;
+
+use auth::{Access, Scope, ScopedToken};
+use client_ip::{ClientIp, ProxyTrust};
+use cors::CorsOrigins;
+use disk::DiskGuard;
+use picup_lib::{
+    ArchiveParam, BatchParam, CategoryInfo, GetImgParam, HashParam, JobInfo, ListImgParam,
+    Metadata, MontageParam, OverridePolicy, PresignParam, PresignedUpload, RecentParam,
+    RecentUpload, ResponseCode, RestResponse, TokenParam, UploadAction, UploadImgParam,
+    UploadedFile, UrlUploadParam, VersionInfo, ACTIONS_HEADER, API_BASE_URL, BLURHASH_HEADER,
+    JOB_HEADER, METADATA_HEADER,
+};
+use tokio::io::{self, AsyncReadExt};
+use tokio::{
+    fs::{
+        copy, create_dir_all, metadata, read, read_dir, remove_file, rename, try_exists, write,
+        File,
+    },
+    net::TcpListener,
+    signal::ctrl_c,
+    task::spawn_blocking,
+    time::timeout,
+};
+
+use tokio_util::io::ReaderStream;
+use tower::ServiceBuilder;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{error, info, warn, Level, Span};
+use urlencoding::encode;
+use uuid::Uuid;
+
+macro_rules! uri_concat {
+    ($base: expr, $( $s: expr ),*) => {
+        {
+            let mut uri = $base.to_string();
+            $(
+                uri.push('/');
+                uri.push_str($s);
+            )*
+            uri
+        }
+    };
+}
+
+// declared after the macros so that they can use them
+mod archive;
+mod auth;
+mod blurhash;
+mod client_ip;
+mod config;
+mod cors;
+mod decode_cache;
+mod disk;
+mod hash_index;
+mod hook;
+mod hotlink;
+mod i18n;
+mod imaging;
+mod jobs;
+mod metadata;
+mod mixed;
+mod naming;
+mod presign;
+mod server;
+mod storage;
+mod svg;
+mod throttle;
+mod url_fetch;
+
+use config::CategorySettings;
+use decode_cache::DecodeCache;
+pub use hook::UploadHook;
+use hotlink::Hotlink;
+use i18n::{Locale, Messages};
+use imaging::{UploadProcessing, Watermark, WatermarkPosition};
+use jobs::Jobs;
+use naming::FilenameTemplate;
+use server::HttpConfig;
+use storage::Storage;
+use url_fetch::{Allowlist, FetchError, UrlFetcher};
+
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 60;
+
+/// Seconds clients are told to wait before retrying an upload turned down for lack of space.
+const DISK_FULL_RETRY_AFTER_SECS: u64 = 300;
+
+/// Plain text error bodies up to it are kept as the detail of their json replacements.
+const ERROR_DETAIL_LIMIT: usize = 1024;
+
+/// Pre-signed upload urls can't be valid for longer than a day.
+const PRESIGN_MAX_EXPIRES_IN_SECS: u64 = 24 * 60 * 60;
+
+/// Largest width or height images can be resized to on download.
+const RESIZE_MAX_DIMENSION: u32 = 4096;
+
+/// Least hex digits of a sha256 assets are looked up by, see [`get_by_hash`].
+const SHORT_HASH_MIN_LEN: usize = 8;
+
+/// Most uploads listed by `/recent` at once.
+const RECENT_MAX_LIMIT: usize = 1000;
+
+/// Most urls a page of `/category/:category` may list.
+const LIST_MAX_LIMIT: usize = 1000;
+
+/// Header of successful uploads telling the jpeg quality they were re-encoded at, if any.
+const QUALITY_HEADER: &str = "x-picup-quality";
+
+/// Header of successful uploads listing the files skipped for being stored already, url-encoded
+/// and separated by commas.
+const UNCHANGED_HEADER: &str = "x-picup-unchanged";
+
+/// Header of file parts telling their position among those the client sent, which successful
+/// uploads list in the order of their urls, separated by commas. Multipart parts are processed
+/// in the order they arrive in, which the client's http stack doesn't necessarily keep.
+const INDEX_HEADER: &str = "x-picup-index";
+
+/// Bytes of a request body at most.
+const MAX_BODY_SIZE: usize = 1024 * 1024 * 32;
+
+/// Of the images decoded and processed, those of the features of `image`.
+const IMAGE_EXTENSIONS: [&str; 6] = ["bmp", "gif", "jpeg", "jpg", "png", "webp"];
+
+/// Files an upload request may contain unless configured otherwise.
+const DEFAULT_MAX_FILES_PER_REQUEST: usize = 1000;
+
+/// `Cache-Control` of assets those may be replaced, and of montages.
+const DEFAULT_CACHE_CONTROL: &str = "public, max-age=1919810";
+
+/// `Cache-Control` of assets in immutable categories, which are cached for a year.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Files a batch may ask for.
+const BATCH_MAX_FILES: usize = 100;
+
+/// Header of batches listing the files those are not found, url-encoded and separated by commas.
+const MISSING_HEADER: &str = "x-picup-missing";
+
+/// Limits of a montage, which is drawn in memory.
+const MONTAGE_MAX_FILES: usize = 64;
+const MONTAGE_MAX_COLUMNS: u32 = 16;
+const MONTAGE_MAX_CELL: u32 = 512;
+
+type JRestResponse<TData> = (StatusCode, Json<RestResponse<TData>>);
+
+trait JsonResponse {
+    fn response(status: StatusCode, s: Self) -> (StatusCode, Json<Self>)
+    where
+        Self: Sized;
+}
+
+impl<TData> JsonResponse for RestResponse<TData> {
+    fn response(status: StatusCode, s: Self) -> JRestResponse<TData>
+    where
+        Self: Sized,
+    {
+        (status, Json(s))
+    }
+}
+
+fn response_ok_no_data() -> JRestResponse<()> {
+    RestResponse::response(
+        StatusCode::OK,
+        RestResponse::new_no_data(ResponseCode::OK, "ok"),
+    )
+}
+
+fn response_ok<TData>(data: TData) -> JRestResponse<TData> {
+    RestResponse::response(
+        StatusCode::OK,
+        RestResponse::new(ResponseCode::OK, "ok", data),
+    )
+}
+
+fn response_no<TData>(locale: &Locale, code: ResponseCode) -> JRestResponse<TData> {
+    RestResponse::response(
+        StatusCode::BAD_REQUEST,
+        RestResponse::new_no_data(code, &locale.msg(code)),
+    )
+}
+
+fn response_no_status<TData>(
+    status: StatusCode,
+    locale: &Locale,
+    code: ResponseCode,
+) -> JRestResponse<TData> {
+    RestResponse::response(status, RestResponse::new_no_data(code, &locale.msg(code)))
+}
+
+fn response_no_status_with<TData>(
+    status: StatusCode,
+    locale: &Locale,
+    code: ResponseCode,
+    detail: &str,
+) -> JRestResponse<TData> {
+    RestResponse::response(
+        status,
+        RestResponse::new_no_data(code, &locale.msg_with(code, detail)),
+    )
+}
+
+/// Rejects the token unless it may do `scope` on the category, with `INVALID_TOKEN` if it's
+/// unknown and `FORBIDDEN` if it's not allowed to.
+fn deny_token<TData>(
+    state: &SrvState,
+    locale: &Locale,
+    token: &str,
+    scope: Scope,
+    category: Option<&str>,
+) -> Option<JRestResponse<TData>> {
+    match state.access(token, scope, category) {
+        Access::Granted => None,
+        Access::Invalid => Some(response_no(locale, ResponseCode::INVALID_TOKEN)),
+        Access::Forbidden => Some(response_no_status(
+            StatusCode::FORBIDDEN,
+            locale,
+            ResponseCode::FORBIDDEN,
+        )),
+    }
+}
+
+/// Rejects any token but the one of the config, for operations on the whole server.
+fn deny_non_master_token<TData>(
+    state: &SrvState,
+    locale: &Locale,
+    token: &str,
+) -> Option<JRestResponse<TData>> {
+    if token == state.access_token {
+        None
+    } else if state.scoped_token(token).is_some() {
+        Some(response_no_status(
+            StatusCode::FORBIDDEN,
+            locale,
+            ResponseCode::FORBIDDEN,
+        ))
+    } else {
+        Some(response_no(locale, ResponseCode::INVALID_TOKEN))
+    }
+}
+
+fn response_no_with<TData>(
+    locale: &Locale,
+    code: ResponseCode,
+    detail: &str,
+) -> JRestResponse<TData> {
+    RestResponse::response(
+        StatusCode::BAD_REQUEST,
+        RestResponse::new_no_data(code, &locale.msg_with(code, detail)),
+    )
+}
+
+struct SrvState {
+    categories: HashMap<String, CategoryConfig>,
+
+    /// config of categories created by uploading to them, which are rejected if not set
+    default_category: Option<CategoryConfig>,
+
+    messages: Arc<Messages>,
+    image: ImageConfig,
+    access_token: String,
+
+    /// tokens of `[server.tokens]`, `access_token` may still do anything
+    scoped_tokens: Vec<ScopedToken>,
+
+    pic_url_prefix: String,
+    pic_directory: String,
+
+    /// of uploads and what's kept next to them, under `pic_directory`
+    storage: Arc<dyn Storage>,
+
+    /// rejects writes with 503 while set, toggled at runtime
+    maintenance: AtomicBool,
+
+    timeouts: Timeouts,
+
+    /// bytes per second each asset is served at most, 0 for no limit
+    download_rate: u64,
+
+    /// files an upload request may contain
+    max_files_per_request: usize,
+
+    upload_hook: Option<Box<dyn UploadHook>>,
+
+    /// level rejected uploads are logged at, not logged if not set
+    rejection_log_level: Option<Level>,
+
+    /// decoded assets reused by variants, off if not set
+    decode_cache: Option<DecodeCache>,
+
+    /// proxies telling the client of requests, which is the socket peer if not set
+    proxy_trust: Option<ProxyTrust>,
+
+    /// rejects uploads while the storage is short of space, never if not set
+    disk_guard: Option<DiskGuard>,
+
+    /// origins browsers may read assets from
+    asset_cors: CorsOrigins,
+
+    /// origins browsers may upload and call the routes taking the token from
+    admin_cors: CorsOrigins,
+
+    /// of uploads by url
+    url_fetcher: UrlFetcher,
+
+    /// of uploads to categories processing them in the background
+    jobs: Jobs,
+}
+
+/// Time limits of handling a request, before the body of the response is streamed.
+struct Timeouts {
+    /// for uploads, which take longer with large batches
+    upload: Duration,
+
+    /// for everything else
+    read: Duration,
+
+    /// seconds clients are told to wait before retrying a timed out request
+    retry_after: u64,
+}
+
+impl SrvState {
+    /// Config of the category, which is the default one for any valid name that is not configured
+    /// if categories are created on upload.
+    fn category(&self, name: &str) -> Option<&CategoryConfig> {
+        self.categories.get(name).or_else(|| {
+            self.default_category
+                .as_ref()
+                .filter(|_| naming::is_valid_category(name))
+        })
+    }
+
+    fn scoped_token(&self, token: &str) -> Option<&ScopedToken> {
+        self.scoped_tokens.iter().find(|t| t.value == token)
+    }
+
+    /// Whether the token may do `scope` on the category, or across categories if `None`.
+    fn access(&self, token: &str, scope: Scope, category: Option<&str>) -> Access {
+        if token == self.access_token {
+            return Access::Granted;
+        }
+
+        match self.scoped_token(token) {
+            Some(t) if t.allows(scope, category) => Access::Granted,
+            Some(t) => {
+                info!(
+                    "token [{}] is not allowed to {} [{}]",
+                    t.name,
+                    scope.name(),
+                    category.unwrap_or("*")
+                );
+                Access::Forbidden
+            }
+            None => Access::Invalid,
+        }
+    }
+
+    /// Whether listings across categories made with the token show the category.
+    fn lists_category(&self, token: &str, category: &str) -> bool {
+        token == self.access_token
+            || self
+                .scoped_token(token)
+                .is_some_and(|t| t.allows_category(category))
+    }
+
+    /// Config of the category if it's configured, or has been created by an upload.
+    async fn existing_category(&self, name: &str) -> Option<&CategoryConfig> {
+        if let Some(config) = self.categories.get(name) {
+            return Some(config);
+        }
+
+        let config = self.category(name)?;
+
+        match try_exists(uri_concat!(&self.pic_directory, "asset", name)).await {
+            Ok(true) => Some(config),
+            _ => None,
+        }
+    }
+
+    /// Configured categories and those created by uploads, sorted.
+    async fn category_names(&self) -> io::Result<Vec<String>> {
+        let mut names = self.categories.keys().cloned().collect::<Vec<String>>();
+
+        if self.default_category.is_some() {
+            let mut entries = read_dir(uri_concat!(&self.pic_directory, "asset")).await?;
+
+            while let Some(entry) = entries.next_entry().await? {
+                match entry.file_name().into_string() {
+                    Ok(name)
+                        if entry.file_type().await?.is_dir()
+                            && !self.categories.contains_key(&name)
+                            && naming::is_valid_category(&name) =>
+                    {
+                        names.push(name)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Directory a file of the category is stored in under `root`, which is nested by a hash of
+    /// its name if the category is sharded.
+    fn stored_dir(&self, root: &str, category: &str, file_name: &str) -> String {
+        let sharded = self.category(category).is_some_and(|config| config.shard);
+
+        if !sharded {
+            return uri_concat!(&self.pic_directory, root, category);
+        }
+
+        let hash = format!("{:08x}", fnv1a(file_name.as_bytes()));
+
+        uri_concat!(
+            &self.pic_directory,
+            root,
+            category,
+            &hash[0..2],
+            &hash[2..4]
+        )
+    }
+
+    fn asset_dir(&self, category: &str, file_name: &str) -> String {
+        self.stored_dir("asset", category, file_name)
+    }
+
+    fn asset_path(&self, category: &str, file_name: &str) -> String {
+        uri_concat!(&self.asset_dir(category, file_name), file_name)
+    }
+
+    /// Directory the untouched upload of a processed asset is kept in, see
+    /// [`CategoryConfig::keep_original`].
+    fn original_dir(&self, category: &str, file_name: &str) -> String {
+        self.stored_dir("original", category, file_name)
+    }
+
+    fn original_path(&self, category: &str, file_name: &str) -> String {
+        uri_concat!(&self.original_dir(category, file_name), file_name)
+    }
+
+    /// Names and paths of all assets in the category, sorted by name.
+    async fn list_assets(&self, category: &str) -> io::Result<Vec<(String, String)>> {
+        let mut assets = vec![];
+
+        // shard directories are nested two levels deep
+        let max_depth = match self.category(category) {
+            Some(config) if config.shard => 2,
+            _ => 0,
+        };
+
+        let mut dirs = vec![(uri_concat!(&self.pic_directory, "asset", category), 0)];
+
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = match read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+
+                let path = uri_concat!(&dir, &name);
+                let file_type = entry.file_type().await?;
+
+                if file_type.is_file() {
+                    assets.push((name, path));
+                } else if file_type.is_dir() && depth < max_depth {
+                    dirs.push((path, depth + 1));
+                }
+            }
+        }
+
+        assets.sort();
+
+        Ok(assets)
+    }
+}
+
+struct CategoryConfig {
+    allow_non_image_content: bool,
+    shard: bool,
+
+    /// overrides of [`ImageConfig`]
+    autorotate: Option<bool>,
+    strip_metadata: Option<bool>,
+
+    watermark: Option<Arc<Watermark>>,
+
+    /// overrides [`SrvState::download_rate`]
+    download_rate: Option<u64>,
+
+    /// keeps uploads changed by processing as they were under `original/`
+    keep_original: bool,
+
+    /// names files are stored as instead of those they are uploaded with
+    filename_template: Option<FilenameTemplate>,
+
+    /// renames images whose extension doesn't match their content
+    correct_extension: bool,
+
+    /// referers allowed to embed the images, anyone if not set
+    hotlink: Option<Hotlink>,
+
+    /// accepts svg images, which are sanitized
+    allow_svg: bool,
+
+    /// bytes of each file at most as submitted, only the limit of the request body if not set
+    max_file_size: Option<u64>,
+
+    /// bounds of the jpeg quality uploads are re-encoded at, whatever is asked for
+    min_quality: Option<u8>,
+    max_quality: Option<u8>,
+
+    /// jpeg quality of uploads not asking for any
+    default_compress: Option<u8>,
+
+    /// side of the square webp thumbnails fit in
+    thumbnail_size: u32,
+
+    /// of lossy webp thumbnails from 1 to 100, lossless if not set
+    thumbnail_quality: Option<u8>,
+
+    /// makes thumbnails of uploads right away instead of on their first request
+    eager_thumbnails: bool,
+
+    /// computes placeholders of uploaded images, see [`blurhash`]
+    blurhash: bool,
+
+    /// stores uploads as they are and processes them afterwards, see [`jobs`]
+    async_processing: bool,
+
+    /// assets are never replaced, so that they are cached for good
+    immutable: bool,
+
+    /// `Cache-Control` of assets in a mutable category, [`DEFAULT_CACHE_CONTROL`] if not set
+    cache_control: Option<HeaderValue>,
+}
+
+impl CategoryConfig {
+    /// Extensions of the images taken and processed, any file if `None`. Others uploaded as
+    /// `image/*` are still stored as they are.
+    fn allowed_extensions(&self) -> Option<Vec<String>> {
+        if self.allow_non_image_content {
+            return None;
+        }
+
+        let mut extensions = IMAGE_EXTENSIONS.to_vec();
+
+        if self.allow_svg {
+            extensions.push("svg");
+        }
+
+        Some(extensions.into_iter().map(str::to_string).collect())
+    }
+
+    fn cache_control(&self) -> HeaderValue {
+        if self.immutable {
+            HeaderValue::from_static(IMMUTABLE_CACHE_CONTROL)
+        } else {
+            self.cache_control
+                .clone()
+                .unwrap_or(HeaderValue::from_static(DEFAULT_CACHE_CONTROL))
+        }
+    }
+
+    /// Jpeg quality uploads are re-encoded at when asked for `compress` (0 for none), clamped to
+    /// the bounds of the category. Uploads asking for none get the default of the category, or
+    /// else the upper bound if there is one.
+    fn quality(&self, compress: u8) -> Option<u8> {
+        let compress = match compress {
+            0 => self.default_compress.unwrap_or(0),
+            compress => compress,
+        };
+
+        match compress {
+            0 => self.max_quality,
+            compress => Some(compress.clamp(
+                self.min_quality.unwrap_or(1),
+                self.max_quality.unwrap_or(100),
+            )),
+        }
+    }
+}
+
+/// Defaults of image processing on upload for all categories.
+struct ImageConfig {
+    autorotate: bool,
+    strip_metadata: bool,
+    convert_to: Option<ImageFormat>,
+    convert_only_if_smaller: bool,
+}
+
+impl SrvState {
+    /// `Cache-Control` of the asset and its thumbnail, which aren't cached while the asset is
+    /// still to be processed.
+    fn cache_control(
+        &self,
+        config: &CategoryConfig,
+        category: &str,
+        file_name: &str,
+    ) -> HeaderValue {
+        if self.jobs.is_processing(category, file_name) {
+            HeaderValue::from_static("no-store")
+        } else {
+            config.cache_control()
+        }
+    }
+
+    fn upload_processing(&self, config: &CategoryConfig, quality: Option<u8>) -> UploadProcessing {
+        UploadProcessing {
+            autorotate: config.autorotate.unwrap_or(self.image.autorotate),
+            strip_metadata: config.strip_metadata.unwrap_or(self.image.strip_metadata),
+            convert_to: self.image.convert_to,
+            convert_only_if_smaller: self.image.convert_only_if_smaller,
+            watermark: config.watermark.clone(),
+            quality,
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// 32-bit FNV-1a, which stays the same across builds unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// A file of an upload, as a multipart field or fetched by the server.
+struct IncomingFile {
+    file_name: Option<String>,
+    content_type: Option<String>,
+
+    /// of the part, empty for fetched files
+    headers: HeaderMap,
+
+    /// `None` if it couldn't be read
+    bytes: Option<Bytes>,
+}
+
+/// Where the files of an upload come from.
+enum UploadSource {
+    Multipart(Multipart),
+
+    /// by [`upload_urls`]
+    Fetched(std::vec::IntoIter<IncomingFile>),
+}
+
+impl UploadSource {
+    /// The next file, or the reason the body is cut off before its final boundary.
+    async fn next_file(&mut self) -> Result<Option<IncomingFile>, String> {
+        let multipart = match self {
+            UploadSource::Multipart(multipart) => multipart,
+            UploadSource::Fetched(files) => return Ok(files.next()),
+        };
+
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e.body_text()),
+        };
+
+        Ok(Some(IncomingFile {
+            file_name: field.file_name().map(str::to_owned),
+            content_type: field.content_type().map(str::to_owned),
+            headers: field.headers().clone(),
+            bytes: field.bytes().await.ok(),
+        }))
+    }
+}
+
+async fn upload_img(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    client: Option<Extension<ClientIp>>,
+    Query(param): Query<UploadImgParam>,
+    multipart: Multipart,
+) -> Response<Body> {
+    upload(
+        state,
+        locale,
+        client,
+        param,
+        UploadSource::Multipart(multipart),
+    )
+    .await
+}
+
+/// Fetches the images of the urls and stores them as if they were uploaded, all of them or none.
+async fn upload_urls(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    client: Option<Extension<ClientIp>>,
+    Query(token): Query<TokenParam>,
+    Json(urls): Json<UrlUploadParam>,
+) -> Response<Body> {
+    let param = UploadImgParam::new(token.access_token(), 0, urls.category(), urls.r#override());
+
+    // nothing is fetched for uploads rejected before their first file anyway
+    if authorize_upload(&state, &param).is_err() || state.category(param.category()).is_none() {
+        let source = UploadSource::Fetched(Vec::new().into_iter());
+
+        return upload(state, locale, client, param, source).await;
+    }
+
+    let mut files = Vec::with_capacity(urls.urls().len());
+
+    let rejection = if urls.urls().len() > state.max_files_per_request {
+        Some(response_no_with::<()>(
+            &locale,
+            ResponseCode::TOO_MANY_FILES,
+            &format!("at most {}", state.max_files_per_request),
+        ))
+    } else {
+        let mut rejection = None;
+
+        for url in urls.urls() {
+            match state.url_fetcher.fetch(url).await {
+                Ok(fetched) => files.push(IncomingFile {
+                    file_name: Some(fetched.file_name),
+                    content_type: fetched.content_type,
+                    headers: HeaderMap::new(),
+                    bytes: Some(fetched.bytes),
+                }),
+                Err(e) => {
+                    let (code, detail) = match e {
+                        FetchError::Invalid(detail) => (ResponseCode::INVALID_PARAM, detail),
+                        FetchError::Blocked(detail) => (ResponseCode::URL_BLOCKED, detail),
+                        FetchError::TooLarge => (
+                            ResponseCode::FILE_TOO_LARGE,
+                            format!("{}, at most {}", url, state.url_fetcher.max_size()),
+                        ),
+                        FetchError::Failed(detail) => (ResponseCode::BAD_FILE, detail),
+                    };
+
+                    rejection = Some(response_no_with(&locale, code, &detail));
+                    break;
+                }
+            }
+        }
+
+        rejection
+    };
+
+    match rejection {
+        Some((status, json)) => {
+            if let Some(level) = state.rejection_log_level {
+                log_rejection(level, &json, urls.category(), &client_name(client));
+            }
+
+            (status, json).into_response()
+        }
+        None => {
+            upload(
+                state,
+                locale,
+                client,
+                param,
+                UploadSource::Fetched(files.into_iter()),
+            )
+            .await
+        }
+    }
+}
+
+fn client_name(client: Option<Extension<ClientIp>>) -> String {
+    client.map_or("unknown".to_string(), |Extension(ClientIp(ip))| {
+        ip.to_string()
+    })
+}
+
+/// Stores the files and responds what has been done with them, see [`upload_files`].
+async fn upload(
+    state: Arc<SrvState>,
+    locale: Locale,
+    client: Option<Extension<ClientIp>>,
+    param: UploadImgParam,
+    source: UploadSource,
+) -> Response<Body> {
+    let quality = state
+        .category(param.category())
+        .and_then(|config| config.quality(param.compress()));
+
+    let category = param.category().to_owned();
+    let rejection_log_level = state.rejection_log_level;
+
+    let mut outcome = UploadOutcome::default();
+
+    let (status, json) = upload_files(state, locale, param, source, &mut outcome).await;
+
+    if let Some(level) = rejection_log_level.filter(|_| !status.is_success()) {
+        log_rejection(level, &json, &category, &client_name(client));
+    }
+
+    let mut response = (status, json).into_response();
+
+    // reported only if all went well, otherwise nothing is stored
+    if let Some(quality) = quality.filter(|_| status.is_success()) {
+        response
+            .headers_mut()
+            .insert(QUALITY_HEADER, HeaderValue::from(u16::from(quality)));
+    }
+
+    if status.is_success() && !outcome.unchanged.is_empty() {
+        let names = outcome
+            .unchanged
+            .iter()
+            .map(|name| encode(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if let Ok(names) = HeaderValue::try_from(names) {
+            response.headers_mut().insert(UNCHANGED_HEADER, names);
+        }
+    }
+
+    if status.is_success() {
+        let actions = outcome
+            .actions
+            .iter()
+            .map(UploadAction::name)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        response
+            .headers_mut()
+            .insert(ACTIONS_HEADER, HeaderValue::try_from(actions).unwrap());
+    }
+
+    if status.is_success() && outcome.blurhashes.iter().any(Option::is_some) {
+        let blurhashes = outcome
+            .blurhashes
+            .iter()
+            .map(|blurhash| blurhash.as_deref().map(encode).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if let Ok(blurhashes) = HeaderValue::try_from(blurhashes) {
+            response.headers_mut().insert(BLURHASH_HEADER, blurhashes);
+        }
+    }
+
+    if let Some(job) = outcome.job.filter(|_| status.is_success()) {
+        response
+            .headers_mut()
+            .insert(JOB_HEADER, HeaderValue::try_from(job).unwrap());
+    }
+
+    // only if every file has one, they wouldn't line up otherwise
+    if let Some(indexes) = outcome
+        .indexes
+        .into_iter()
+        .collect::<Option<Vec<usize>>>()
+        .filter(|indexes| status.is_success() && !indexes.is_empty())
+    {
+        let indexes = indexes
+            .iter()
+            .map(|index| index.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        response
+            .headers_mut()
+            .insert(INDEX_HEADER, HeaderValue::try_from(indexes).unwrap());
+    }
+
+    response
+}
+
+/// Logs a rejected upload at the configured level, where the file is usually in the message.
+fn log_rejection<TData>(
+    level: Level,
+    response: &RestResponse<TData>,
+    category: &str,
+    client: &str,
+) {
+    macro_rules! rejected {
+        ($level: expr) => {
+            tracing::event!(
+                target: "picup::rejection",
+                $level,
+                "rejected upload from [{}] to [{}]: {} ({}) {}",
+                client,
+                category,
+                response.code().name(),
+                response.code().to_u16(),
+                response.msg()
+            )
+        };
+    }
+
+    match level {
+        Level::ERROR => rejected!(Level::ERROR),
+        Level::WARN => rejected!(Level::WARN),
+        Level::INFO => rejected!(Level::INFO),
+        Level::DEBUG => rejected!(Level::DEBUG),
+        Level::TRACE => rejected!(Level::TRACE),
+    }
+}
+
+/// A received file waiting for all others of the request before it's committed.
+struct StagedFile {
+    /// name of the file part, before templates, conversions and renaming
+    submitted_name: String,
+
+    name: String,
+
+    /// whether its untouched upload is under `temp/original/` as well
+    has_original: bool,
+
+    /// of the file as uploaded
+    hash: String,
+
+    /// [`UploadAction::Skipped`] if it's stored from the same upload already, or
+    /// [`UploadAction::Deduplicated`] if the same content is under its name, only its url is
+    /// responded then
+    action: UploadAction,
+
+    /// given by the client, see [`INDEX_HEADER`]
+    index: Option<usize>,
+
+    /// of the file as stored, if its category computes them
+    blurhash: Option<String>,
+
+    /// given along with it, that of unchanged files is kept if not
+    metadata: Option<Metadata>,
+}
+
+/// What successful uploads tell in their headers besides the urls.
+#[derive(Default)]
+struct UploadOutcome {
+    /// names of the files those were stored already
+    unchanged: Vec<String>,
+
+    /// of each url
+    indexes: Vec<Option<usize>>,
+
+    /// of each url
+    actions: Vec<UploadAction>,
+
+    /// of each url
+    blurhashes: Vec<Option<String>>,
+
+    /// processing the files in the background, see [`jobs`]
+    job: Option<String>,
+}
+
+/// Whether the upload carries the token or a valid signature of a pre-signed url, with the reason
+/// it doesn't if there is something to tell.
+fn authorize_upload(
+    state: &SrvState,
+    param: &UploadImgParam,
+) -> Result<(), (ResponseCode, Option<&'static str>)> {
+    match state.access(param.access_token(), Scope::Write, Some(param.category())) {
+        Access::Granted => return Ok(()),
+        Access::Forbidden => return Err((ResponseCode::FORBIDDEN, None)),
+        Access::Invalid => {}
+    }
+
+    if param.signature().is_empty() {
+        return Err((ResponseCode::INVALID_TOKEN, None));
+    }
+
+    let grant = presign::Grant {
+        category: param.category(),
+        r#override: param.r#override().replaces(),
+        max_size: param.max_size(),
+        expires: param.expires(),
+    };
+
+    if !grant.verify(&state.access_token, param.signature()) {
+        return Err((ResponseCode::INVALID_TOKEN, Some("bad signature")));
+    }
+
+    if unix_time() > param.expires() {
+        return Err((ResponseCode::INVALID_TOKEN, Some("signature expired")));
+    }
+
+    Ok(())
+}
+
+async fn upload_files(
+    state: Arc<SrvState>,
+    locale: Locale,
+    param: UploadImgParam,
+    mut source: UploadSource,
+    outcome: &mut UploadOutcome,
+) -> JRestResponse<Vec<UploadedFile>> {
+    if let Err(e) = truncate_temp(&state).await {
+        error!("failed to truncate temp directory: {}", e);
+        return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+    }
+
+    let r#override = param.r#override();
+
+    match authorize_upload(&state, &param) {
+        Ok(()) => {}
+        Err((ResponseCode::FORBIDDEN, _)) => {
+            return response_no_status(StatusCode::FORBIDDEN, &locale, ResponseCode::FORBIDDEN);
+        }
+        Err((code, Some(detail))) => return response_no_with(&locale, code, detail),
+        Err((code, None)) => return response_no(&locale, code),
+    }
+
+    let mut staged: Vec<StagedFile> = Vec::new();
+
+    let category = param.category();
+
+    let category_config = state.category(category);
+
+    if category_config.is_none() {
+        return response_no(&locale, ResponseCode::INVALID_CATEGORY);
+    }
+
+    let category_config = category_config.unwrap();
+
+    let compress = param.compress();
+
+    if compress > 100 {
+        return response_no_with(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            "compress must be from 0 to 100",
+        );
+    }
+
+    let quality = category_config.quality(compress);
+
+    let mut handled = 0;
+    let mut size = 0;
+
+    loop {
+        // a body cut off before its final boundary is an error, nothing is committed then
+        let field = match source.next_file().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("incomplete upload after {} files: {}", handled, e);
+                return response_no_with(&locale, ResponseCode::BAD_FILE, &e);
+            }
+        };
+
+        // nothing is committed yet
+        if handled == state.max_files_per_request {
+            return response_no_with(
+                &locale,
+                ResponseCode::TOO_MANY_FILES,
+                &format!("at most {}", state.max_files_per_request),
+            );
+        }
+
+        let file_name = field.file_name;
+
+        if file_name.is_none() {
+            return response_no_with(
+                &locale,
+                ResponseCode::BAD_FILE_NAME,
+                &format!("file no. {}", handled + 1),
+            );
+        }
+
+        let submitted_name = file_name.unwrap();
+
+        let mut file_name = submitted_name.clone();
+
+        // templates sanitize what they take of the name
+        if let Some(template) = &category_config.filename_template {
+            file_name = template.render(&file_name, category);
+        }
+
+        // it would be stored in another category, or outside of them
+        if !is_plain_file_name(&file_name) {
+            return response_no_with(&locale, ResponseCode::BAD_FILE_NAME, &submitted_name);
+        }
+
+        if !category_config.allow_non_image_content
+            && !field.content_type.unwrap_or_default().contains("image")
+        {
+            return response_no_with(&locale, ResponseCode::NOT_A_IMAGE, &file_name);
+        }
+
+        let index = match field.headers.get(INDEX_HEADER) {
+            Some(index) => match index.to_str().ok().and_then(|index| index.parse().ok()) {
+                Some(index) => Some(index),
+                None => {
+                    return response_no_with(
+                        &locale,
+                        ResponseCode::INVALID_PARAM,
+                        &format!("{} of {}", INDEX_HEADER, file_name),
+                    )
+                }
+            },
+            None => None,
+        };
+
+        let metadata = match field.headers.get(METADATA_HEADER) {
+            Some(metadata) => match metadata
+                .to_str()
+                .map_err(|e| e.to_string())
+                .and_then(metadata::parse)
+            {
+                Ok(metadata) => Some(metadata),
+                Err(e) => {
+                    return response_no_with(
+                        &locale,
+                        ResponseCode::INVALID_PARAM,
+                        &format!("{} of {}: {}", METADATA_HEADER, file_name, e),
+                    )
+                }
+            },
+            None => None,
+        };
+
+        // hash of the file the client may have uploaded before, see `hash_index`
+        let if_none_match = field
+            .headers
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+
+        let bytes = field.bytes;
+
+        if bytes.is_none() {
+            return response_no_with(&locale, ResponseCode::BAD_FILE, &file_name);
+        }
+
+        let mut bytes = bytes.unwrap();
+
+        let hash = hash_index::sha256_hex(&bytes);
+
+        // they may run scripts, unlike the other images
+        if svg::is_svg(&file_name, &bytes) {
+            if category_config.allow_svg {
+                match svg::sanitize(&bytes) {
+                    Ok(sanitized) => bytes = Bytes::from(sanitized),
+                    Err(e) => {
+                        warn!("failed to sanitize svg [{}]: {}", file_name, e);
+                        return response_no_with(&locale, ResponseCode::BAD_FILE, &file_name);
+                    }
+                }
+            } else if !category_config.allow_non_image_content {
+                return response_no_with(
+                    &locale,
+                    ResponseCode::NOT_A_IMAGE,
+                    &format!("{} (svg)", file_name),
+                );
+            }
+        }
+
+        if category_config
+            .max_file_size
+            .is_some_and(|max| bytes.len() as u64 > max)
+        {
+            return response_no_with(&locale, ResponseCode::FILE_TOO_LARGE, &file_name);
+        }
+
+        size += bytes.len() as u64;
+
+        if param.max_size() != 0 && size > param.max_size() {
+            return response_no_with(&locale, ResponseCode::FILE_TOO_LARGE, &file_name);
+        }
+
+        let processing = state.upload_processing(category_config, quality);
+
+        // done by the job of the upload once it's stored
+        let processed = if category_config.async_processing {
+            Ok(None)
+        } else {
+            let bytes = bytes.clone();
+            spawn_blocking(move || imaging::process_upload(&bytes, &processing)).await
+        };
+
+        let (bytes, original) = match processed {
+            Ok(Some(processed)) => {
+                if let Some(format) = processed.converted_to {
+                    let converted_name = PathBuf::from(&file_name)
+                        .with_extension(format.extensions_str()[0])
+                        .to_string_lossy()
+                        .to_string();
+
+                    info!(
+                        "[{}] converted to [{}], {} -> {} bytes",
+                        file_name,
+                        converted_name,
+                        bytes.len(),
+                        processed.bytes.len()
+                    );
+
+                    file_name = converted_name;
+                }
+
+                (Bytes::from(processed.bytes), Some(bytes))
+            }
+            Ok(None) => (bytes, None),
+            Err(e) => {
+                error!("failed to process [{}]: {}", file_name, e);
+                return response_no_with(&locale, ResponseCode::BAD_FILE, &file_name);
+            }
+        };
+
+        if category_config.correct_extension {
+            if let Some(extension) = imaging::corrected_extension(&file_name, &bytes) {
+                let corrected_name = PathBuf::from(&file_name)
+                    .with_extension(extension)
+                    .to_string_lossy()
+                    .to_string();
+
+                info!(
+                    "[{}] is actually {}, stored as [{}]",
+                    file_name, extension, corrected_name
+                );
+
+                file_name = corrected_name;
+            }
+        }
+
+        // both would be written to the same temp file, overriding doesn't make sense here
+        if staged.iter().any(|file| file.name == file_name) {
+            return response_no_with(&locale, ResponseCode::FILE_EXISTED, &file_name);
+        }
+
+        // stored from the very same upload before, which is neither an error nor replaced
+        if let Some(if_none_match) = &if_none_match {
+            if hash_index::matches(if_none_match, &hash) {
+                match hash_index::stored(&state, category, &file_name).await {
+                    Ok(Some(stored)) if stored == hash => {
+                        let blurhash = blurhash::stored(&state, category, &file_name).await;
+
+                        staged.push(StagedFile {
+                            submitted_name,
+                            name: file_name,
+                            has_original: false,
+                            hash,
+                            action: UploadAction::Skipped,
+                            index,
+                            blurhash,
+                            metadata,
+                        });
+                        handled += 1;
+
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("failed to hash [{}/{}]: {}", category, file_name, e);
+                        return response_no_with(
+                            &locale,
+                            ResponseCode::INTERNAL_ERROR,
+                            "file system",
+                        );
+                    }
+                }
+            }
+        }
+
+        // checked after processing, which may change the extension
+        let file_path = state.asset_path(category, &file_name);
+
+        let exists = state.storage.modified(&file_path).await;
+
+        if exists.is_err() {
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+
+        let exists = exists.unwrap().is_some();
+
+        let action = if !exists {
+            UploadAction::Created
+        } else {
+            match r#override {
+                OverridePolicy::Never => {
+                    return response_no_with(&locale, ResponseCode::FILE_EXISTED, &file_name)
+                }
+                OverridePolicy::Always => UploadAction::Overwritten,
+                OverridePolicy::IfDifferent => {
+                    match hash_index::stored(&state, category, &file_name).await {
+                        Ok(Some(stored)) if stored == hash => UploadAction::Deduplicated,
+                        Ok(_) => UploadAction::Overwritten,
+                        Err(e) => {
+                            error!("failed to hash [{}/{}]: {}", category, file_name, e);
+                            return response_no_with(
+                                &locale,
+                                ResponseCode::INTERNAL_ERROR,
+                                "file system",
+                            );
+                        }
+                    }
+                }
+                OverridePolicy::Rename => {
+                    match free_name(&state, category, &file_name, &staged).await {
+                        Ok(free_name) => {
+                            info!("[{}] exists, stored as [{}]", file_name, free_name);
+
+                            file_name = free_name;
+
+                            UploadAction::Renamed
+                        }
+                        Err(e) => {
+                            error!("failed to rename [{}/{}]: {}", category, file_name, e);
+                            return response_no_with(
+                                &locale,
+                                ResponseCode::INTERNAL_ERROR,
+                                "file system",
+                            );
+                        }
+                    }
+                }
+            }
+        };
+
+        // assets of immutable categories may be cached forever
+        if action == UploadAction::Overwritten && category_config.immutable {
+            return response_no_with(&locale, ResponseCode::FILE_EXISTED, &file_name);
+        }
+
+        if action == UploadAction::Deduplicated {
+            let blurhash = blurhash::stored(&state, category, &file_name).await;
+
+            staged.push(StagedFile {
+                submitted_name,
+                name: file_name,
+                has_original: false,
+                hash,
+                action,
+                index,
+                blurhash,
+                metadata,
+            });
+            handled += 1;
+
+            continue;
+        }
+
+        let file_temp_path = uri_concat!(&state.pic_directory, "temp", &file_name);
+
+        if let Err(e) = state.storage.write(&file_temp_path, bytes.to_vec()).await {
+            error!("failed to write temp file [{}]: {}", file_temp_path, e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+
+        let original = original.filter(|_| category_config.keep_original);
+
+        if let Some(original) = &original {
+            let original_temp_path =
+                uri_concat!(&state.pic_directory, "temp", "original", &file_name);
+
+            let written =
+                match create_dir_all(uri_concat!(&state.pic_directory, "temp", "original")).await {
+                    Ok(_) => write(&original_temp_path, original).await,
+                    Err(e) => Err(e),
+                };
+
+            if let Err(e) = written {
+                error!("failed to write temp file [{}]: {}", original_temp_path, e);
+                return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+            }
+        }
+
+        let blurhash = if category_config.blurhash && !category_config.async_processing {
+            let bytes = bytes.clone();
+            spawn_blocking(move || blurhash::of_image(&bytes))
+                .await
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+
+        staged.push(StagedFile {
+            submitted_name,
+            name: file_name,
+            has_original: original.is_some(),
+            hash,
+            action,
+            index,
+            blurhash,
+            metadata,
+        });
+        handled += 1;
+    }
+
+    let mut uploaded = Vec::new();
+
+    // names and urls of the files those are written
+    let mut written = Vec::new();
+
+    // of the files written, if they are processed later
+    let mut hashes = Vec::new();
+
+    // promising all files should be successfully uploaded
+    for file in staged {
+        let file_name = file.name;
+
+        let url = uri_concat!(
+            &state.pic_url_prefix,
+            "asset",
+            category,
+            &encode(&file_name)
+        );
+
+        outcome.indexes.push(file.index);
+        outcome.actions.push(file.action);
+        outcome.blurhashes.push(file.blurhash.clone());
+
+        uploaded.push(UploadedFile::new(
+            &file.submitted_name,
+            &file_name,
+            &url,
+            file.action,
+        ));
+
+        if matches!(
+            file.action,
+            UploadAction::Skipped | UploadAction::Deduplicated
+        ) {
+            if let Some(metadata) = &file.metadata {
+                if let Err(e) = metadata::record(&state, category, &file_name, Some(metadata)).await
+                {
+                    error!(
+                        "failed to record metadata of [{}/{}]: {}",
+                        category, file_name, e
+                    );
+                    return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+                }
+            }
+
+            outcome.unchanged.push(file_name);
+
+            continue;
+        }
+
+        let committed = state
+            .storage
+            .rename(
+                &uri_concat!(&state.pic_directory, "temp", &file_name),
+                &state.asset_path(category, &file_name),
+            )
+            .await;
+
+        if let Err(e) = committed {
+            error!("failed to commit [{}] to [{}]: {}", file_name, category, e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+
+        if category_config.keep_original {
+            let committed = if file.has_original {
+                match create_dir_all(state.original_dir(category, &file_name)).await {
+                    Ok(_) => {
+                        commit_file(
+                            &uri_concat!(&state.pic_directory, "temp", "original", &file_name),
+                            &state.original_path(category, &file_name),
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                }
+            } else {
+                // the asset is its own original now, drop that of the one it overrode
+                match remove_file(state.original_path(category, &file_name)).await {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                    _ => Ok(()),
+                }
+            };
+
+            if let Err(e) = committed {
+                error!(
+                    "failed to commit original of [{}] to [{}]: {}",
+                    file_name, category, e
+                );
+                return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+            }
+        }
+
+        // the asset is there regardless, it's just hashed again when asked for. That of one
+        // processed later is recorded once it's processed, the unprocessed one would be kept for
+        // the same upload otherwise
+        if category_config.async_processing {
+            hashes.push(file.hash);
+        } else if let Err(e) = hash_index::record(&state, category, &file_name, &file.hash).await {
+            warn!(
+                "failed to record hash of [{}/{}]: {}",
+                category, file_name, e
+            );
+        }
+
+        // a stale one would be served for the new asset otherwise
+        if let Err(e) =
+            blurhash::record(&state, category, &file_name, file.blurhash.as_deref()).await
+        {
+            warn!(
+                "failed to record blurhash of [{}/{}]: {}",
+                category, file_name, e
+            );
+        }
+
+        // that of the one it replaced would be told otherwise
+        if let Err(e) = metadata::record(&state, category, &file_name, file.metadata.as_ref()).await
+        {
+            warn!(
+                "failed to record metadata of [{}/{}]: {}",
+                category, file_name, e
+            );
+        }
+
+        written.push((file_name, url));
+    }
+
+    if category_config.async_processing && !written.is_empty() {
+        let job = state.jobs.start(category, &written);
+
+        outcome.job = Some(job.clone());
+
+        tokio::spawn(process_in_background(
+            state.clone(),
+            job,
+            category.to_string(),
+            quality,
+            written.into_iter().zip(hashes).collect(),
+        ));
+
+        return RestResponse::response(
+            StatusCode::ACCEPTED,
+            RestResponse::new(ResponseCode::OK, "ok", uploaded),
+        );
+    }
+
+    uploaded_written(&state, category, written).await;
+
+    response_ok(uploaded)
+}
+
+/// Tells the hook of the files written to the category and makes their thumbnails if it wants
+/// them, once they are processed.
+async fn uploaded_written(state: &Arc<SrvState>, category: &str, written: Vec<(String, String)>) {
+    if let Some(hook) = &state.upload_hook {
+        for (file_name, url) in &written {
+            let path = state.asset_path(category, file_name);
+
+            hook.on_uploaded(category, file_name, &path, url).await;
+        }
+    }
+
+    if state
+        .category(category)
+        .is_some_and(|config| config.eager_thumbnails)
+    {
+        let state = state.clone();
+        let category = category.to_string();
+
+        // not worth holding the response for
+        tokio::spawn(async move {
+            let category_config = state.category(&category).unwrap();
+
+            for (file_name, _) in written {
+                if let Err(e) = thumbnail(&state, &category, &file_name, category_config).await {
+                    warn!(
+                        "failed to make thumbnail of [{}/{}]: {}",
+                        category, file_name, e
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Processes the files of `((name, url), hash)` an upload stored as they were, as
+/// [`upload_files`] does before storing them in other categories. The job fails with the first
+/// file that couldn't be processed, which is served as it was uploaded then.
+async fn process_in_background(
+    state: Arc<SrvState>,
+    job: String,
+    category: String,
+    quality: Option<u8>,
+    files: Vec<((String, String), String)>,
+) {
+    let mut error = None;
+    let mut written = Vec::new();
+
+    for ((file_name, url), hash) in files {
+        if let Err(e) = process_stored(&state, &category, &file_name, &hash, quality).await {
+            error!(
+                "failed to process [{}/{}] in the background: {}",
+                category, file_name, e
+            );
+
+            error.get_or_insert(format!("{}: {}", file_name, e));
+        }
+
+        written.push((file_name, url));
+
+        state.jobs.advance(&job);
+    }
+
+    uploaded_written(&state, &category, written).await;
+
+    state.jobs.finish(&job, error.as_deref());
+}
+
+/// Processes an asset stored as it was uploaded in place. It isn't converted, which would change
+/// its name the upload responded already.
+async fn process_stored(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    hash: &str,
+    quality: Option<u8>,
+) -> io::Result<()> {
+    let category_config = state
+        .category(category)
+        .ok_or_else(|| io::Error::other("unknown category"))?;
+
+    let path = state.asset_path(category, file_name);
+
+    let uploaded = read(&path).await?;
+
+    let mut processing = state.upload_processing(category_config, quality);
+    processing.convert_to = None;
+
+    let processed = {
+        let uploaded = uploaded.clone();
+        spawn_blocking(move || imaging::process_upload(&uploaded, &processing))
+            .await
+            .map_err(io::Error::other)?
+    };
+
+    if let Some(processed) = &processed {
+        // replaced by another upload meanwhile, which its own job processes
+        if read(&path).await? != uploaded {
+            return Ok(());
+        }
+
+        if category_config.keep_original {
+            create_dir_all(state.original_dir(category, file_name)).await?;
+            write(state.original_path(category, file_name), &uploaded).await?;
+        }
+
+        let part_path = format!("{}.part", path);
+
+        write(&part_path, &processed.bytes).await?;
+        rename(&part_path, &path).await?;
+    }
+
+    if category_config.blurhash {
+        let bytes = processed.map_or(uploaded, |processed| processed.bytes);
+
+        let blurhash = spawn_blocking(move || blurhash::of_image(&bytes))
+            .await
+            .ok()
+            .flatten();
+
+        if let Err(e) = blurhash::record(state, category, file_name, blurhash.as_deref()).await {
+            warn!(
+                "failed to record blurhash of [{}/{}]: {}",
+                category, file_name, e
+            );
+        }
+    }
+
+    if let Err(e) = hash_index::record(state, category, file_name, hash).await {
+        warn!(
+            "failed to record hash of [{}/{}]: {}",
+            category, file_name, e
+        );
+    }
+
+    Ok(())
+}
+
+/// First of `a-1.png`, `a-2.png` and so on for `a.png` which is neither stored nor staged.
+async fn free_name(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    staged: &[StagedFile],
+) -> io::Result<String> {
+    let path = PathBuf::from(file_name);
+
+    let stem = path.file_stem().map_or(file_name.to_string(), |stem| {
+        stem.to_string_lossy().to_string()
+    });
+
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    for n in 1.. {
+        let candidate = format!("{}-{}{}", stem, n, extension);
+
+        if !staged.iter().any(|file| file.name == candidate)
+            && state
+                .storage
+                .modified(&state.asset_path(category, &candidate))
+                .await?
+                .is_none()
+        {
+            return Ok(candidate);
+        }
+    }
+
+    unreachable!()
+}
+
+async fn presign_upload(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Query(param): Query<PresignParam>,
+) -> JRestResponse<PresignedUpload> {
+    if let Some(denied) = deny_token(
+        &state,
+        &locale,
+        param.access_token(),
+        Scope::Write,
+        Some(param.category()),
+    ) {
+        return denied;
+    }
+
+    if state.category(param.category()).is_none() {
+        return response_no(&locale, ResponseCode::INVALID_CATEGORY);
+    }
+
+    if !(1..=PRESIGN_MAX_EXPIRES_IN_SECS).contains(&param.expires_in()) {
+        return response_no_with(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("expires_in, 1 to {} expected", PRESIGN_MAX_EXPIRES_IN_SECS),
+        );
+    }
+
+    let grant = presign::Grant {
+        category: param.category(),
+        r#override: param.r#override(),
+        max_size: param.max_size(),
+        expires: unix_time() + param.expires_in(),
+    };
+
+    let url = format!(
+        "{}?category={}&override={}&max_size={}&expires={}&signature={}",
+        uri_concat!(&state.pic_url_prefix, "upload"),
+        encode(grant.category),
+        grant.r#override,
+        grant.max_size,
+        grant.expires,
+        grant.sign(&state.access_token)
+    );
+
+    response_ok(PresignedUpload::new(&url, grant.expires))
+}
+
+async fn get_img(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path((category, file_name)): Path<(String, String)>,
+    Query(param): Query<GetImgParam>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let category_config = match state.existing_category(&category).await {
+        Some(category_config) => category_config,
+        None => {
+            return response_no_status::<()>(
+                StatusCode::NOT_FOUND,
+                &locale,
+                ResponseCode::INVALID_CATEGORY,
+            )
+            .into_response()
+        }
+    };
+
+    if let Some(denied) = deny_hotlink(&locale, category_config, &headers) {
+        return denied;
+    }
+
+    if !is_plain_file_name(&file_name) {
+        return response_no_with::<()>(&locale, ResponseCode::BAD_FILE_NAME, &file_name)
+            .into_response();
+    }
+
+    let mut path = state.asset_path(&category, &file_name);
+
+    if param.original() {
+        if let Some(denied) = deny_token::<()>(
+            &state,
+            &locale,
+            param.access_token(),
+            Scope::Read,
+            Some(&category),
+        ) {
+            return denied.into_response();
+        }
+
+        let original_path = state.original_path(&category, &file_name);
+
+        // only processed assets have one kept apart
+        if try_exists(&original_path).await.unwrap_or(false) {
+            path = original_path;
+        }
+    } else if param.w() != 0 || param.h() != 0 {
+        let (width, height) = (param.w(), param.h());
+
+        if width > RESIZE_MAX_DIMENSION || height > RESIZE_MAX_DIMENSION {
+            return response_no_with::<()>(
+                &locale,
+                ResponseCode::INVALID_PARAM,
+                &format!("w and h must be at most {}", RESIZE_MAX_DIMENSION),
+            )
+            .into_response();
+        }
+
+        // resizing applies the orientation as well
+        match imaging::variant(
+            &state,
+            &category,
+            &file_name,
+            &format!("resize-{}x{}", width, height),
+            move |bytes| imaging::resize(bytes, width, height),
+        )
+        .await
+        {
+            Ok(Some(resized)) => path = resized,
+            Ok(None) => {}
+            Err(e) => warn!("failed to resize [{}/{}]: {}", category, file_name, e),
+        }
+    } else if param.autorotate() {
+        match imaging::variant(
+            &state,
+            &category,
+            &file_name,
+            "autorotate",
+            imaging::autorotate,
+        )
+        .await
+        {
+            Ok(Some(rotated)) => path = rotated,
+            Ok(None) => {}
+            Err(e) => warn!("failed to autorotate [{}/{}]: {}", category, file_name, e),
+        }
+    }
+
+    // variants and originals are not validated, they're cached long enough on their own
+    let hash = if path == state.asset_path(&category, &file_name) {
+        match hash_index::stored(&state, &category, &file_name).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!("failed to hash [{}/{}]: {}", category, file_name, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(hash) = &hash {
+        let if_none_match = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+
+        if if_none_match.is_some_and(|tags| hash_index::matches(tags, hash)) {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [
+                    (ETAG, hash_index::etag(hash)),
+                    (CACHE_CONTROL, category_config.cache_control()),
+                ],
+            )
+                .into_response();
+        }
+    }
+
+    let stream = match state.storage.stream(&path).await {
+        Ok(stream) => stream,
+        Err(_) => {
+            return response_no_status::<()>(
+                StatusCode::NOT_FOUND,
+                &locale,
+                ResponseCode::FILE_NOT_FOUND,
+            )
+            .into_response()
+        }
+    };
+
+    let download_rate = category_config.download_rate.unwrap_or(state.download_rate);
+
+    let compress = param.compress();
+
+    if compress != 0 {
+        return response_no_status::<()>(
+            StatusCode::NOT_IMPLEMENTED,
+            &locale,
+            ResponseCode::NOT_IMPLEMENTED,
+        )
+        .into_response();
+    }
+
+    let body = match download_rate {
+        0 => Body::from_stream(stream),
+        rate => Body::from_stream(throttle::throttle(stream, rate)),
+    };
+
+    let mut response = (StatusCode::OK, body).into_response();
+
+    if let Ok(format) = ImageFormat::from_path(&file_name) {
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(format.to_mime_type()),
+        );
+    } else if svg::is_svg(&file_name, &[]) {
+        // scripts can't run in one embedded with <img>, but can in one opened directly
+        let headers = response.headers_mut();
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/svg+xml"));
+        headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'; sandbox"),
+        );
+        headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    }
+
+    response.headers_mut().insert(
+        CACHE_CONTROL,
+        state.cache_control(category_config, &category, &file_name),
+    );
+
+    if let Some(hash) = &hash {
+        response.headers_mut().insert(ETAG, hash_index::etag(hash));
+    }
+
+    response
+}
+
+async fn list_recent(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Query(param): Query<RecentParam>,
+) -> JRestResponse<Vec<RecentUpload>> {
+    if let Some(denied) = deny_token(&state, &locale, param.access_token(), Scope::Read, None) {
+        return denied;
+    }
+
+    let limit = param.limit();
+
+    if !(1..=RECENT_MAX_LIMIT).contains(&limit) {
+        return response_no_with(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("limit must be from 1 to {}", RECENT_MAX_LIMIT),
+        );
+    }
+
+    // the newest ones so far, oldest on top to be dropped first
+    let mut recent = BinaryHeap::with_capacity(limit + 1);
+
+    let categories = match state.category_names().await {
+        Ok(categories) => categories,
+        Err(e) => {
+            error!("failed to list categories: {}", e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+    };
+
+    let categories = categories
+        .into_iter()
+        .filter(|category| state.lists_category(param.access_token(), category))
+        .collect::<Vec<String>>();
+
+    for category in &categories {
+        let assets = match state.list_assets(category).await {
+            Ok(assets) => assets,
+            Err(e) => {
+                error!("failed to list [{}]: {}", category, e);
+                return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+            }
+        };
+
+        for (name, path) in assets {
+            // removed in the meantime
+            let meta = match metadata(&path).await {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+
+            recent.push(Reverse((uploaded_at(&meta), category, name, meta.len())));
+
+            if recent.len() > limit {
+                recent.pop();
+            }
+        }
+    }
+
+    let mut uploads = Vec::with_capacity(recent.len());
+
+    for Reverse((uploaded, category, name, size)) in recent.into_sorted_vec() {
+        uploads.push(asset_info(&state, category, &name, size, uploaded).await);
+    }
+
+    response_ok(uploads)
+}
+
+/// Unix seconds the asset was stored at, as far as the file system knows.
+fn uploaded_at(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// The asset with its url and what's recorded of it besides.
+async fn asset_info(
+    state: &SrvState,
+    category: &str,
+    name: &str,
+    size: u64,
+    uploaded: u64,
+) -> RecentUpload {
+    let url = uri_concat!(&state.pic_url_prefix, "asset", category, &encode(name));
+
+    let blurhash = match state.category(category) {
+        Some(config) if config.blurhash => blurhash::stored(state, category, name).await,
+        _ => None,
+    };
+
+    RecentUpload::new(
+        category,
+        name,
+        &url,
+        size,
+        uploaded,
+        blurhash.as_deref(),
+        metadata::stored(state, category, name).await,
+    )
+}
+
+/// Size, time of upload, placeholder and metadata of an asset, which are as public as it is.
+async fn get_meta(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path((category, file_name)): Path<(String, String)>,
+) -> JRestResponse<RecentUpload> {
+    if state.existing_category(&category).await.is_none() {
+        return response_no_status(
+            StatusCode::NOT_FOUND,
+            &locale,
+            ResponseCode::INVALID_CATEGORY,
+        );
+    }
+
+    // it would tell of files anywhere otherwise
+    if !is_plain_file_name(&file_name) {
+        return response_no_with(&locale, ResponseCode::BAD_FILE_NAME, &file_name);
+    }
+
+    let meta = match metadata(state.asset_path(&category, &file_name)).await {
+        Ok(meta) if meta.is_file() => meta,
+        _ => {
+            return response_no_status_with(
+                StatusCode::NOT_FOUND,
+                &locale,
+                ResponseCode::FILE_NOT_FOUND,
+                &file_name,
+            )
+        }
+    };
+
+    response_ok(
+        asset_info(
+            &state,
+            &category,
+            &file_name,
+            meta.len(),
+            uploaded_at(&meta),
+        )
+        .await,
+    )
+}
+
+async fn get_job(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(id): Path<String>,
+) -> JRestResponse<JobInfo> {
+    match state.jobs.get(&id) {
+        Some(job) => response_ok(job),
+        None => {
+            response_no_status_with(StatusCode::NOT_FOUND, &locale, ResponseCode::NOT_FOUND, &id)
+        }
+    }
+}
+
+/// Regenerates what's derived from the assets of the category by its current config, such as
+/// thumbnails after their size changed, in a job followed at `/job/:id`. The assets themselves
+/// are left as they are, converting them would change the names they're known by.
+async fn reprocess_category(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(category): Path<String>,
+    Query(param): Query<TokenParam>,
+) -> JRestResponse<JobInfo> {
+    if let Some(denied) = deny_token(
+        &state,
+        &locale,
+        param.access_token(),
+        Scope::Write,
+        Some(&category),
+    ) {
+        return denied;
+    }
+
+    if state.existing_category(&category).await.is_none() {
+        return response_no(&locale, ResponseCode::INVALID_CATEGORY);
+    }
+
+    let assets = match state.list_assets(&category).await {
+        Ok(assets) => assets,
+        Err(e) => {
+            error!("failed to list [{}]: {}", category, e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+    };
+
+    let files = assets
+        .into_iter()
+        .map(|(name, _)| {
+            let url = uri_concat!(&state.pic_url_prefix, "asset", &category, &encode(&name));
+            (name, url)
+        })
+        .collect::<Vec<_>>();
+
+    // assets are only served uncached while they are the one being reprocessed
+    let job = state.jobs.start_unmarked(&category, &files);
+    let info = state.jobs.get(&job).unwrap();
+
+    tokio::spawn(reprocess_in_background(
+        state.clone(),
+        job,
+        category,
+        files.into_iter().map(|(name, _)| name).collect(),
+    ));
+
+    RestResponse::response(
+        StatusCode::ACCEPTED,
+        RestResponse::new(ResponseCode::OK, "ok", info),
+    )
+}
+
+/// Reprocesses the assets of a category one after another for [`reprocess_category`]. The job
+/// fails with the first asset that couldn't be reprocessed, the others are done regardless.
+async fn reprocess_in_background(
+    state: Arc<SrvState>,
+    job: String,
+    category: String,
+    file_names: Vec<String>,
+) {
+    let mut error = None;
+
+    for file_name in file_names {
+        state.jobs.mark(&category, &file_name);
+
+        let reprocessed = reprocess_asset(&state, &category, &file_name).await;
+
+        state.jobs.unmark(&category, &file_name);
+
+        if let Err(e) = reprocessed {
+            error!("failed to reprocess [{}/{}]: {}", category, file_name, e);
+
+            error.get_or_insert(format!("{}: {}", file_name, e));
+        }
+
+        state.jobs.advance(&job);
+    }
+
+    state.jobs.finish(&job, error.as_deref());
+}
+
+/// Makes the thumbnail of the asset by the current config of its category, and its BlurHash if
+/// the category computes them and it has none yet.
+async fn reprocess_asset(state: &SrvState, category: &str, file_name: &str) -> io::Result<()> {
+    let category_config = state
+        .category(category)
+        .ok_or_else(|| io::Error::other("unknown category"))?;
+
+    thumbnail(state, category, file_name, category_config).await?;
+
+    if category_config.blurhash && blurhash::stored(state, category, file_name).await.is_none() {
+        let bytes = read(state.asset_path(category, file_name)).await?;
+
+        let computed = spawn_blocking(move || blurhash::of_image(&bytes))
+            .await
+            .map_err(io::Error::other)?;
+
+        if let Some(computed) = computed {
+            blurhash::record(state, category, file_name, Some(&computed)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn get_archive(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(category): Path<String>,
+    Query(param): Query<ArchiveParam>,
+) -> Response<Body> {
+    if let Some(denied) = deny_token::<()>(
+        &state,
+        &locale,
+        param.access_token(),
+        Scope::Read,
+        Some(&category),
+    ) {
+        return denied.into_response();
+    }
+
+    if state.existing_category(&category).await.is_none() {
+        return response_no::<()>(&locale, ResponseCode::INVALID_CATEGORY).into_response();
+    }
+
+    let files = match state.list_assets(&category).await {
+        Ok(files) => files,
+        Err(e) => {
+            error!("failed to list [{}]: {}", category, e);
+            return response_no_with::<()>(&locale, ResponseCode::INTERNAL_ERROR, "file system")
+                .into_response();
+        }
+    };
+
+    let files = files
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(param.prefix().as_str()))
+        .collect();
+
+    let stream = ReaderStream::new(archive::zip_stream(files));
+
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, "application/zip".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.zip\"", category),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Several assets of a category in a single `multipart/mixed` response, in the order asked for.
+async fn get_batch(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(category): Path<String>,
+    Query(param): Query<BatchParam>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let category_config = match state.existing_category(&category).await {
+        Some(category_config) => category_config,
+        None => return response_no::<()>(&locale, ResponseCode::INVALID_CATEGORY).into_response(),
+    };
+
+    if let Some(denied) = deny_hotlink(&locale, category_config, &headers) {
+        return denied;
+    }
+
+    let file_names = param
+        .files()
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<&str>>();
+
+    if file_names.is_empty() || file_names.len() > BATCH_MAX_FILES {
+        return response_no_with::<()>(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("files, 1 to {} names expected", BATCH_MAX_FILES),
+        )
+        .into_response();
+    }
+
+    let mut parts = vec![];
+    let mut missing = vec![];
+
+    for file_name in file_names {
+        if !is_plain_file_name(file_name) {
+            return response_no_with::<()>(&locale, ResponseCode::BAD_FILE_NAME, file_name)
+                .into_response();
+        }
+
+        let path = state.asset_path(&category, file_name);
+
+        if !try_exists(&path).await.unwrap_or(false) {
+            missing.push(encode(file_name));
+            continue;
+        }
+
+        parts.push(mixed::MixedPart {
+            name: file_name.to_string(),
+            path,
+            content_type: content_type_of(file_name),
+        });
+    }
+
+    let boundary = Uuid::new_v4().simple().to_string();
+
+    let stream = mixed::mixed_stream(boundary.clone(), parts);
+
+    let body = match category_config.download_rate.unwrap_or(state.download_rate) {
+        0 => Body::from_stream(stream),
+        rate => Body::from_stream(throttle::throttle(stream, rate)),
+    };
+
+    let mut response = (
+        StatusCode::OK,
+        [
+            (
+                CONTENT_TYPE,
+                HeaderValue::try_from(format!("multipart/mixed; boundary={}", boundary)).unwrap(),
+            ),
+            (CACHE_CONTROL, category_config.cache_control()),
+        ],
+        body,
+    )
+        .into_response();
+
+    if !missing.is_empty() {
+        if let Ok(missing) = HeaderValue::try_from(missing.join(", ")) {
+            response.headers_mut().insert(MISSING_HEADER, missing);
+        }
+    }
+
+    response
+}
+
+/// 403 if the category doesn't allow the referer of the request to embed its assets.
+fn deny_hotlink(
+    locale: &Locale,
+    category_config: &CategoryConfig,
+    headers: &HeaderMap,
+) -> Option<Response<Body>> {
+    let hotlink = category_config.hotlink.as_ref()?;
+
+    let referer = headers.get(REFERER).and_then(|v| v.to_str().ok());
+
+    if hotlink.allows(referer) {
+        return None;
+    }
+
+    Some(
+        response_no_status::<()>(StatusCode::FORBIDDEN, locale, ResponseCode::HOTLINK_DENIED)
+            .into_response(),
+    )
+}
+
+/// Serves the asset uploaded with the sha256, or any of them if there are several, as
+/// `/asset/:category/:file_name` would. The hash may be shortened to [`SHORT_HASH_MIN_LEN`]
+/// digits as long as it's unambiguous.
+///
+/// It's that of the file as uploaded, before it was sanitized or processed, which is not that of
+/// the asset served if it has been changed.
+async fn get_by_hash(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path((category, hash)): Path<(String, String)>,
+    Query(hash_param): Query<HashParam>,
+    param: Query<GetImgParam>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let hash = hash.to_ascii_lowercase();
+
+    if !(SHORT_HASH_MIN_LEN..=64).contains(&hash.len())
+        || !hash.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return response_no_with::<()>(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("sha256, {} to 64 hex digits expected", SHORT_HASH_MIN_LEN),
+        )
+        .into_response();
+    }
+
+    if state.existing_category(&category).await.is_none() {
+        return response_no_status::<()>(
+            StatusCode::NOT_FOUND,
+            &locale,
+            ResponseCode::INVALID_CATEGORY,
+        )
+        .into_response();
+    }
+
+    let mut categories = vec![category.clone()];
+
+    if hash_param.any_category() {
+        match state.category_names().await {
+            Ok(names) => categories.extend(names.into_iter().filter(|c| c != &category)),
+            Err(e) => {
+                error!("failed to list categories: {}", e);
+                return response_no_with::<()>(
+                    &locale,
+                    ResponseCode::INTERNAL_ERROR,
+                    "file system",
+                )
+                .into_response();
+            }
+        }
+    }
+
+    for category in categories {
+        let mut found = match hash_index::find(&state, &category, &hash).await {
+            Ok(found) => found,
+            Err(e) => {
+                error!("failed to look up [{}] in [{}]: {}", hash, category, e);
+                return response_no_with::<()>(
+                    &locale,
+                    ResponseCode::INTERNAL_ERROR,
+                    "file system",
+                )
+                .into_response();
+            }
+        };
+
+        if found.len() > 1 {
+            return response_no_with::<()>(
+                &locale,
+                ResponseCode::INVALID_PARAM,
+                &format!("ambiguous sha256 [{}]", hash),
+            )
+            .into_response();
+        }
+
+        if let Some((_, file_name)) = found.pop() {
+            return get_img(
+                State(state),
+                locale,
+                Path((category, file_name)),
+                param,
+                headers,
+            )
+            .await;
+        }
+    }
+
+    response_no_status::<()>(StatusCode::NOT_FOUND, &locale, ResponseCode::FILE_NOT_FOUND)
+        .into_response()
+}
+
+/// Whether a file name asked for stays in the directory of its category once it's joined into a
+/// path, which those decoded from the path of a request may not.
+fn is_plain_file_name(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && !file_name.contains(['/', '\\'])
+        && file_name != "."
+        && file_name != ".."
+}
+
+/// Webp thumbnail of an image, see [`CategoryConfig::thumbnail_size`].
+async fn get_thumb(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path((category, file_name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let category_config = match state.existing_category(&category).await {
+        Some(category_config) => category_config,
+        None => {
+            return response_no_status::<()>(
+                StatusCode::NOT_FOUND,
+                &locale,
+                ResponseCode::INVALID_CATEGORY,
+            )
+            .into_response()
+        }
+    };
+
+    if let Some(denied) = deny_hotlink(&locale, category_config, &headers) {
+        return denied;
+    }
+
+    if !is_plain_file_name(&file_name) {
+        return response_no_with::<()>(&locale, ResponseCode::BAD_FILE_NAME, &file_name)
+            .into_response();
+    }
+
+    if !try_exists(state.asset_path(&category, &file_name))
+        .await
+        .unwrap_or(false)
+    {
+        return response_no_status::<()>(
+            StatusCode::NOT_FOUND,
+            &locale,
+            ResponseCode::FILE_NOT_FOUND,
+        )
+        .into_response();
+    }
+
+    let path = match thumbnail(&state, &category, &file_name, category_config).await {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            return response_no_status::<()>(
+                StatusCode::NOT_FOUND,
+                &locale,
+                ResponseCode::NOT_A_IMAGE,
+            )
+            .into_response()
+        }
+        Err(e) => {
+            error!(
+                "failed to make thumbnail of [{}/{}]: {}",
+                category, file_name, e
+            );
+            return response_no_with::<()>(&locale, ResponseCode::INTERNAL_ERROR, "thumbnail")
+                .into_response();
+        }
+    };
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("failed to open thumbnail [{}]: {}", path, e);
+            return response_no_with::<()>(&locale, ResponseCode::INTERNAL_ERROR, "file system")
+                .into_response();
+        }
+    };
+
+    let stream = ReaderStream::new(file);
+
+    let body = match category_config.download_rate.unwrap_or(state.download_rate) {
+        0 => Body::from_stream(stream),
+        rate => Body::from_stream(throttle::throttle(stream, rate)),
+    };
+
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, HeaderValue::from_static("image/webp")),
+            (
+                CACHE_CONTROL,
+                state.cache_control(category_config, &category, &file_name),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Path of the cached thumbnail of the asset, made if it's missing or outdated, or `None` if the
+/// asset isn't an image.
+async fn thumbnail(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    category_config: &CategoryConfig,
+) -> io::Result<Option<String>> {
+    let size = category_config.thumbnail_size;
+    let quality = category_config.thumbnail_quality;
+
+    // made again once either changes
+    let variant = match quality {
+        Some(quality) => format!("thumb-{}-q{}", size, quality),
+        None => format!("thumb-{}", size),
+    };
+
+    imaging::variant(state, category, file_name, &variant, move |decoded| {
+        imaging::webp_thumbnail(decoded, size, quality)
+    })
+    .await
+}
+
+/// Mime type of an asset by its extension.
+fn content_type_of(file_name: &str) -> &'static str {
+    match ImageFormat::from_path(file_name) {
+        Ok(format) => format.to_mime_type(),
+        Err(_) if svg::is_svg(file_name, &[]) => "image/svg+xml",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+async fn get_montage(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(category): Path<String>,
+    Query(param): Query<MontageParam>,
+) -> Response<Body> {
+    if state.existing_category(&category).await.is_none() {
+        return response_no::<()>(&locale, ResponseCode::INVALID_CATEGORY).into_response();
+    }
+
+    let mut file_names = param
+        .files()
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect::<Vec<String>>();
+
+    // the montage is keyed by the list
+    file_names.sort();
+    file_names.dedup();
+
+    if file_names.is_empty() || file_names.len() > MONTAGE_MAX_FILES {
+        return response_no_with::<()>(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("files, 1 to {} names expected", MONTAGE_MAX_FILES),
+        )
+        .into_response();
+    }
+
+    if !(1..=MONTAGE_MAX_COLUMNS).contains(&param.columns()) {
+        return response_no_with::<()>(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("columns, 1 to {} expected", MONTAGE_MAX_COLUMNS),
+        )
+        .into_response();
+    }
+
+    if !(1..=MONTAGE_MAX_CELL).contains(&param.cell()) {
+        return response_no_with::<()>(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("cell, 1 to {} expected", MONTAGE_MAX_CELL),
+        )
+        .into_response();
+    }
+
+    for file_name in &file_names {
+        if !is_plain_file_name(file_name) {
+            return response_no_with::<()>(&locale, ResponseCode::BAD_FILE_NAME, file_name)
+                .into_response();
+        }
+
+        if !try_exists(state.asset_path(&category, file_name))
+            .await
+            .unwrap_or(false)
+        {
+            return response_no_with::<()>(&locale, ResponseCode::FILE_NOT_FOUND, file_name)
+                .into_response();
+        }
+    }
+
+    // fewer files than columns makes a single narrower row
+    let columns = param.columns().min(file_names.len() as u32);
+
+    let path = match imaging::montage(&state, &category, &file_names, columns, param.cell()).await {
+        Ok(imaging::Montage::Made(path)) => path,
+        Ok(imaging::Montage::NotAnImage(file_name)) => {
+            return response_no_with::<()>(&locale, ResponseCode::NOT_A_IMAGE, &file_name)
+                .into_response();
+        }
+        Err(e) => {
+            error!("failed to make montage of [{}]: {}", category, e);
+            return response_no_with::<()>(&locale, ResponseCode::INTERNAL_ERROR, "montage")
+                .into_response();
+        }
+    };
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("failed to open montage [{}]: {}", path, e);
+            return response_no_with::<()>(&locale, ResponseCode::INTERNAL_ERROR, "file system")
+                .into_response();
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, "image/png"),
+            (CACHE_CONTROL, DEFAULT_CACHE_CONTROL),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+async fn get_img_urls(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Path(category): Path<String>,
+    Query(param): Query<ListImgParam>,
+) -> JRestResponse<Vec<String>> {
+    if let Some(denied) = deny_token(
+        &state,
+        &locale,
+        param.access_token(),
+        Scope::Read,
+        Some(&category),
+    ) {
+        return denied;
+    }
+
+    if state.existing_category(&category).await.is_none() {
+        return response_no(&locale, ResponseCode::INVALID_CATEGORY);
+    }
+
+    let limit = param.limit();
+
+    if !(1..=LIST_MAX_LIMIT).contains(&limit) {
+        return response_no_with(
+            &locale,
+            ResponseCode::INVALID_PARAM,
+            &format!("limit must be from 1 to {}", LIST_MAX_LIMIT),
+        );
+    }
+
+    if param.page() == 0 {
+        return response_no_with(&locale, ResponseCode::INVALID_PARAM, "page starts from 1");
+    }
+
+    // a category whose directory is gone has nothing yet
+    let assets = match state.list_assets(&category).await {
+        Ok(assets) => assets,
+        Err(e) => {
+            error!("failed to list [{}]: {}", category, e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+    };
+
+    let urls = assets
+        .into_iter()
+        .skip((param.page() - 1).saturating_mul(limit))
+        .take(limit)
+        .map(|(name, _)| uri_concat!(&state.pic_url_prefix, "asset", &category, &encode(&name)))
+        .collect();
+
+    response_ok(urls)
+}
+
+async fn list_categories(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Query(param): Query<TokenParam>,
+) -> JRestResponse<Vec<CategoryInfo>> {
+    if let Some(denied) = deny_token(&state, &locale, param.access_token(), Scope::Read, None) {
+        return denied;
+    }
+
+    let names = match state.category_names().await {
+        Ok(names) => names,
+        Err(e) => {
+            error!("failed to list categories: {}", e);
+            return response_no_with(&locale, ResponseCode::INTERNAL_ERROR, "file system");
+        }
+    };
+
+    let categories = names
+        .iter()
+        .filter(|name| state.lists_category(param.access_token(), name))
+        .filter_map(|name| {
+            let config = state.category(name)?;
+
+            Some(CategoryInfo::new(
+                name,
+                config.allow_non_image_content,
+                config.max_file_size.unwrap_or(0),
+                config.allowed_extensions(),
+            ))
+        })
+        .collect::<Vec<CategoryInfo>>();
+
+    response_ok(categories)
+}
+
+async fn start_maintenance(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Query(param): Query<TokenParam>,
+) -> JRestResponse<()> {
+    if let Some(denied) = deny_non_master_token(&state, &locale, param.access_token()) {
+        return denied;
+    }
+
+    state.maintenance.store(true, Ordering::Relaxed);
+
+    info!("PicUp server is now under maintenance, uploads are rejected.");
+
+    response_ok_no_data()
+}
+
+async fn stop_maintenance(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    Query(param): Query<TokenParam>,
+) -> JRestResponse<()> {
+    if let Some(denied) = deny_non_master_token(&state, &locale, param.access_token()) {
+        return denied;
+    }
+
+    state.maintenance.store(false, Ordering::Relaxed);
+
+    info!("PicUp server is no longer under maintenance.");
+
+    response_ok_no_data()
+}
+
+async fn upload_timeout_guard(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    timeout_guard(&state, locale, state.timeouts.upload, req, next).await
+}
+
+async fn read_timeout_guard(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    timeout_guard(&state, locale, state.timeouts.read, req, next).await
+}
+
+/// Answers uploads sent with `Expect: 100-continue` before their body if they would be rejected
+/// anyway, with 401 for the token, 400 for the category and 413 for the declared size. The body is
+/// only asked for, with `100 Continue`, once the handler reads it.
+async fn expect_continue_guard(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    let expect = match req.headers().get(EXPECT) {
+        Some(expect) => expect,
+        None => return next.run(req).await,
+    };
+
+    if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+        return response_no_status_with::<()>(
+            StatusCode::EXPECTATION_FAILED,
+            &locale,
+            ResponseCode::BAD_REQUEST,
+            "only 100-continue is expected",
+        )
+        .into_response();
+    }
+
+    // malformed ones are rejected by the handler as usual
+    let param = match Query::<UploadImgParam>::try_from_uri(req.uri()) {
+        Ok(Query(param)) => param,
+        Err(_) => return next.run(req).await,
+    };
+
+    match authorize_upload(&state, &param) {
+        Ok(()) => {}
+        Err((code, detail)) => {
+            let status = if code == ResponseCode::FORBIDDEN {
+                StatusCode::FORBIDDEN
+            } else {
+                StatusCode::UNAUTHORIZED
+            };
+
+            return match detail {
+                Some(detail) => response_no_status_with::<()>(status, &locale, code, detail),
+                None => response_no_status::<()>(status, &locale, code),
+            }
+            .into_response();
+        }
+    }
+
+    if state.category(param.category()).is_none() {
+        return response_no::<()>(&locale, ResponseCode::INVALID_CATEGORY).into_response();
+    }
+
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    if content_length.is_some_and(|length| length > MAX_BODY_SIZE as u64) {
+        return response_no_status_with::<()>(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &locale,
+            ResponseCode::PAYLOAD_TOO_LARGE,
+            &format!("at most {} bytes", MAX_BODY_SIZE),
+        )
+        .into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Responds 408 with `TIMEOUT` if the request isn't handled in time.
+async fn timeout_guard(
+    state: &SrvState,
+    locale: Locale,
+    limit: Duration,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    let uri = req.uri().clone();
+
+    match timeout(limit, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("request [{}] timed out after {:?}", uri, limit);
+
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                [(RETRY_AFTER, state.timeouts.retry_after.to_string())],
+                Json(RestResponse::<()>::new_no_data(
+                    ResponseCode::TIMEOUT,
+                    &locale.msg(ResponseCode::TIMEOUT),
+                )),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Routes told to clients requesting one that doesn't exist.
+const PUBLIC_ROUTES: [&str; 14] = [
+    "POST /picup/upload",
+    "POST /picup/upload/url",
+    "GET /picup/asset/:category/:file_name",
+    "GET /picup/thumb/:category/:file_name",
+    "GET /picup/hash/:category/:sha256",
+    "GET /picup/meta/:category/:file_name",
+    "GET /picup/job/:id",
+    "GET /picup/category/:category",
+    "GET /picup/category/:category/archive",
+    "GET /picup/category/:category/montage",
+    "GET /picup/category/:category/batch",
+    "GET /picup/categories",
+    "GET /picup/recent",
+    "GET /picup/version",
+];
+
+async fn route_not_found(locale: Locale) -> JRestResponse<Vec<&'static str>> {
+    RestResponse::response(
+        StatusCode::NOT_FOUND,
+        RestResponse::new(
+            ResponseCode::NOT_FOUND,
+            &locale.msg_with(ResponseCode::NOT_FOUND, "no such route"),
+            PUBLIC_ROUTES.to_vec(),
+        ),
+    )
+}
+
+/// Wraps error responses which aren't json, like those of axum and tower-http themselves, into
+/// [`RestResponse`], so that clients only have to handle one shape of errors.
+async fn json_errors(locale: Locale, req: Request, next: Next) -> Response<Body> {
+    let response = next.run(req).await;
+
+    let status = response.status();
+
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+
+    if is_json {
+        return response;
+    }
+
+    let code = match status {
+        StatusCode::NOT_FOUND => ResponseCode::NOT_FOUND,
+        StatusCode::METHOD_NOT_ALLOWED => ResponseCode::METHOD_NOT_ALLOWED,
+        StatusCode::REQUEST_TIMEOUT => ResponseCode::TIMEOUT,
+        StatusCode::PAYLOAD_TOO_LARGE => ResponseCode::PAYLOAD_TOO_LARGE,
+        StatusCode::NOT_IMPLEMENTED => ResponseCode::NOT_IMPLEMENTED,
+        StatusCode::SERVICE_UNAVAILABLE => ResponseCode::MAINTENANCE,
+        _ if status.is_client_error() => ResponseCode::BAD_REQUEST,
+        _ => ResponseCode::INTERNAL_ERROR,
+    };
+
+    let (mut parts, body) = response.into_parts();
+
+    // rejections of extractors explain themselves in plain text
+    let detail = to_bytes(body, ERROR_DETAIL_LIMIT)
+        .await
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+        .filter(|detail| !detail.is_empty());
+
+    let msg = match detail {
+        Some(detail) => locale.msg_with(code, &detail),
+        None => locale.msg(code),
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    let json = Json(RestResponse::<()>::new_no_data(code, &msg)).into_response();
+
+    Response::from_parts(parts, json.into_body())
+}
+
+/// Rejects the request with 503 while the storage is short of space, see [`DiskGuard`].
+async fn disk_space_guard(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    let full = match &state.disk_guard {
+        Some(disk_guard) => disk_guard.is_full(&state.pic_directory).await,
+        None => false,
+    };
+
+    if !full {
+        return next.run(req).await;
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, DISK_FULL_RETRY_AFTER_SECS.to_string())],
+        Json(RestResponse::<()>::new_no_data(
+            ResponseCode::DISK_FULL,
+            &locale.msg(ResponseCode::DISK_FULL),
+        )),
+    )
+        .into_response()
+}
+
+/// Rejects the request with 503 while the server is under maintenance.
+async fn maintenance_guard(
+    State(state): State<Arc<SrvState>>,
+    locale: Locale,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    if !state.maintenance.load(Ordering::Relaxed) {
+        return next.run(req).await;
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, MAINTENANCE_RETRY_AFTER_SECS.to_string())],
+        Json(RestResponse::<()>::new_no_data(
+            ResponseCode::MAINTENANCE,
+            &locale.msg(ResponseCode::MAINTENANCE),
+        )),
+    )
+        .into_response()
+}
+
+/// Tells the handlers and the access log who the request is from, see [`ProxyTrust`]. Requests
+/// not from a socket, such as in tests, are from nobody known.
+async fn resolve_client_ip(
+    State(state): State<Arc<SrvState>>,
+    mut req: Request,
+    next: Next,
+) -> Response<Body> {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    if let Some(peer) = peer {
+        let ip = match &state.proxy_trust {
+            Some(proxy_trust) => proxy_trust.client_ip(peer, req.headers()),
+            None => peer,
+        };
+
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+
+    next.run(req).await
+}
+
+/// Span of each request in the access log, along with its client.
+fn request_span<B>(req: &axum::http::Request<B>) -> Span {
+    let client = req
+        .extensions()
+        .get::<ClientIp>()
+        .map_or("unknown".to_string(), |ClientIp(ip)| ip.to_string());
+
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+        client = %client,
+    )
+}
+
+async fn version() -> JRestResponse<VersionInfo> {
+    response_ok(VersionInfo::new(
+        env!("CARGO_PKG_VERSION"),
+        env!("PICUP_BUILD_PROFILE"),
+        env!("PICUP_BUILD_TARGET"),
+    ))
+}
+
+/// A server as configured by its config file, which binaries embedding it run with their own
+/// [`UploadHook`]:
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// picup_srv::Server::load_default().await?.serve().await
+/// # }
+/// ```
+pub struct Server {
+    state: SrvState,
+    port: u16,
+    http: HttpConfig,
+}
+
+impl Server {
+    /// Loads `picup-srv.toml`, or `picup-srv.json` if only that exists, next to the executable.
+    pub async fn load_default() -> io::Result<Self> {
+        Self::load(&config::find(&exe_path())).await
+    }
+
+    /// Loads the config file, toml or json, and makes the directories of the storage. Invalid
+    /// values panic with what's wrong with them.
+    pub async fn load(path: &std::path::Path) -> io::Result<Self> {
+        let mut cfg = String::new();
+        let read = match File::open(path).await {
+            Ok(mut file) => file.read_to_string(&mut cfg).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = read {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("config file [{}]: {}", path.display(), e),
+            ));
+        }
+
+        load_server(path, &cfg).await
+    }
+
+    /// Calls the hook for each file uploaded once it's committed.
+    pub fn upload_hook(mut self, hook: impl UploadHook + 'static) -> Self {
+        self.state.upload_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Routes of the server under `/picup`, to be served by the embedder itself.
+    pub fn router(self) -> Router {
+        app(Arc::new(self.state))
+    }
+
+    /// Serves the routes on the port of the config until Ctrl+C or SIGTERM.
+    pub async fn serve(self) -> io::Result<()> {
+        let (port, http) = (self.port, self.http);
+
+        let app = app(Arc::new(self.state));
+
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+
+        info!(
+            "PicUp server is now listening to port {}. Ctrl+C to stop the server.",
+            port
+        );
+
+        tokio::select! {
+            _ = server::serve(listener, app, http) => {}
+            _ = sigterm() => {}
+        }
+
+        Ok(())
+    }
+}
+
+async fn load_server(dir: &std::path::Path, cfg: &str) -> io::Result<Server> {
+    let dir_str = dir.to_str().unwrap().to_string();
+
+    let format = config::Format::detect(dir, cfg);
+
+    let cfg = config::parse(cfg, format)
+        .unwrap_or_else(|e| panic!("invalid config file [{}]: {}", dir_str, e));
+
+    let read_timeout = cfg.read_timeout();
+    let upload_timeout = cfg.upload_timeout();
+    let port = cfg.port;
+
+    let directory = cfg.directory.unwrap_or(dir_str);
+    let url = cfg
+        .url
+        .unwrap_or_else(|| format!("http://127.0.0.1:{}", port));
+
+    let messages = match cfg.locale_dir {
+        Some(locale_dir) => Messages::load(&exe_path().join(locale_dir)),
+        None => Messages::default(),
+    };
+
+    let rejection_log_level = match cfg.rejection_log_level.as_str() {
+        "off" => None,
+        level => Some(
+            level
+                .parse::<Level>()
+                .unwrap_or_else(|_| panic!("unknown rejection_log_level [{}]", level)),
+        ),
+    };
+
+    let decode_cache = match cfg.image.decode_cache_size {
+        0 => None,
+        size => Some(DecodeCache::new(size)),
+    };
+
+    let image = ImageConfig {
+        autorotate: cfg.image.autorotate,
+        strip_metadata: cfg.image.strip_metadata,
+        convert_to: cfg.image.convert_to.map(|format| {
+            ImageFormat::from_extension(&format)
+                .filter(|format| {
+                    matches!(
+                        format,
+                        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP
+                    )
+                })
+                .unwrap_or_else(|| panic!("unsupported convert_to [{}]", format))
+        }),
+        convert_only_if_smaller: cfg.image.convert_only_if_smaller,
+    };
+
+    let tls = match (cfg.http.tls_cert, cfg.http.tls_key) {
+        (Some(cert), Some(key)) => Some(
+            server::load_tls(&exe_path().join(cert), &exe_path().join(key))
+                .unwrap_or_else(|e| panic!("failed to load tls certificate: {}", e)),
+        ),
+        (None, None) => None,
+        _ => panic!("both tls_cert and tls_key are required for tls"),
+    };
+
+    let http = HttpConfig {
+        tls,
+        h2c: cfg.http.h2c,
+        keep_alive: cfg.http.keep_alive,
+        keep_alive_interval: cfg.http.keep_alive_interval.map(Duration::from_secs),
+        max_concurrent_streams: cfg.http.max_concurrent_streams,
+        max_connections: cfg.http.max_connections,
+    };
+
+    let proxy_trust = cfg.trust_proxy_headers.then(|| {
+        ProxyTrust::new(&cfg.trusted_proxies)
+            .unwrap_or_else(|proxy| panic!("invalid trusted_proxies entry [{}]", proxy))
+    });
+
+    let url_fetch_allowlist = Allowlist::new(&cfg.url_fetch_allowed_networks)
+        .unwrap_or_else(|network| panic!("invalid url_fetch_allowed_networks entry [{}]", network));
+
+    if !(0.0..=100.0).contains(&cfg.min_free_percent) {
+        panic!("min_free_percent must be from 0 to 100");
+    }
+
+    let asset_cors = CorsOrigins::new(&cfg.cors.asset_origins)
+        .unwrap_or_else(|origin| panic!("invalid asset_origins entry [{}]", origin));
+
+    let admin_cors = CorsOrigins::new(&cfg.cors.admin_origins)
+        .unwrap_or_else(|origin| panic!("invalid admin_origins entry [{}]", origin));
+
+    let scoped_tokens = cfg
+        .tokens
+        .into_iter()
+        .map(|(name, token)| {
+            if token.value.is_empty() || token.value == cfg.token {
+                panic!("token [{}] must have a value of its own", name);
+            }
+
+            let scopes = token
+                .scopes
+                .iter()
+                .map(|scope| {
+                    Scope::from_name(scope)
+                        .unwrap_or_else(|| panic!("unknown scope [{}] of token [{}]", scope, name))
+                })
+                .collect();
+
+            ScopedToken {
+                name,
+                value: token.value,
+                categories: token.categories,
+                scopes,
+            }
+        })
+        .collect::<Vec<ScopedToken>>();
+
+    let disk_guard = (cfg.min_free_bytes > 0 || cfg.min_free_percent > 0.0)
+        .then(|| DiskGuard::new(cfg.min_free_bytes, cfg.min_free_percent));
+
+    let mut category_configs = HashMap::new();
+
+    for (name, config) in cfg.categories {
+        category_configs.insert(name.clone(), category_config(&name, config));
+    }
+
+    let default_category = cfg
+        .auto_create_categories
+        .then(|| category_config("default_category", cfg.default_category));
+
+    let state = SrvState {
+        categories: category_configs,
+        default_category,
+        messages: Arc::new(messages),
+        image,
+        access_token: cfg.token,
+        scoped_tokens,
+        pic_url_prefix: format!("{}{}", url, API_BASE_URL),
+        pic_directory: directory,
+        maintenance: AtomicBool::new(false),
+        timeouts: Timeouts {
+            upload: Duration::from_secs(upload_timeout),
+            read: Duration::from_secs(read_timeout),
+            retry_after: cfg.timeout_retry_after,
+        },
+        download_rate: cfg.download_rate,
+        max_files_per_request: cfg.max_files_per_request,
+        upload_hook: None,
+        rejection_log_level,
+        decode_cache,
+        proxy_trust,
+        disk_guard,
+        asset_cors,
+        admin_cors,
+        url_fetcher: UrlFetcher::new(
+            Duration::from_secs(cfg.url_upload_timeout),
+            cfg.url_upload_max_size,
+            url_fetch_allowlist,
+        ),
+        jobs: Jobs::default(),
+        storage: Arc::new(storage::FileSystem),
+    };
+
+    create_dir_all(&state.pic_directory).await?;
+    create_dir_all(uri_concat!(&state.pic_directory, "temp")).await?;
+
+    for category in state.categories.keys() {
+        create_dir_all(uri_concat!(&state.pic_directory, "asset", category)).await?;
+    }
+
+    Ok(Server { state, port, http })
+}
+
+fn app(state: Arc<SrvState>) -> Router {
+    // routes writing to the storage, which are closed during maintenance
+    let write_routes = Router::new()
+        .route("/upload", post(upload_img))
+        .route("/upload/url", post(upload_urls))
+        .route("/category/:category/reprocess", post(reprocess_category))
+        .route_layer(from_fn_with_state(state.clone(), expect_continue_guard))
+        .route_layer(from_fn_with_state(state.clone(), disk_space_guard))
+        .route_layer(from_fn_with_state(state.clone(), upload_timeout_guard))
+        .route_layer(from_fn_with_state(state.clone(), maintenance_guard));
+
+    // routes taking the token, which browsers may call from fewer origins
+    let admin_routes = Router::new()
+        .route("/upload/presign", post(presign_upload))
+        .route(
+            "/maintenance",
+            post(start_maintenance).delete(stop_maintenance),
+        )
+        .route("/category/:category", get(get_img_urls))
+        .route("/category/:category/archive", get(get_archive))
+        .route("/categories", get(list_categories))
+        .route("/recent", get(list_recent))
+        .route_layer(from_fn_with_state(state.clone(), read_timeout_guard));
+
+    let asset_routes = Router::new()
+        .route("/asset/:category/:file_name", get(get_img))
+        .route("/thumb/:category/:file_name", get(get_thumb))
+        .route("/hash/:category/:sha256", get(get_by_hash))
+        .route("/meta/:category/:file_name", get(get_meta))
+        .route("/job/:id", get(get_job))
+        .route("/category/:category/montage", get(get_montage))
+        .route("/category/:category/batch", get(get_batch))
+        .route("/version", get(version))
+        .route_layer(from_fn_with_state(state.clone(), read_timeout_guard))
+        .layer(state.asset_cors.layer());
+
+    let api_routes = write_routes
+        .merge(admin_routes)
+        .layer(state.admin_cors.layer())
+        .merge(asset_routes);
+
+    Router::new()
+        .nest(API_BASE_URL, api_routes)
+        .fallback(route_not_found)
+        .with_state(state.clone())
+        .layer(
+            ServiceBuilder::new()
+                .layer(from_fn_with_state(state.clone(), json_errors))
+                .layer(from_fn_with_state(state, resolve_client_ip))
+                .layer(RequestBodyLimitLayer::new(MAX_BODY_SIZE))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(request_span)
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                ),
+        )
+}
+
+async fn sigterm() {
+    let ctrl_c = async { ctrl_c().await.unwrap() };
+
+    tokio::select! {
+        _ = ctrl_c => {
+            info!("PicUp server is now shutting down!");
+            process::exit(0);
+        }
+    }
+}
+
+async fn truncate_temp(state: &Arc<SrvState>) -> io::Result<()> {
+    state
+        .storage
+        .clear(&uri_concat!(&state.pic_directory, "temp"))
+        .await
+}
+
+/// Moves a staged file to where it's committed, copying it instead if they are on different file
+/// systems, such as `temp` and `asset` on different overlay layers of a container.
+async fn commit_file(from: &str, to: &str) -> io::Result<()> {
+    static CROSSES_DEVICES: Once = Once::new();
+
+    match rename(from, to).await {
+        Err(e) if is_cross_device(&e) => {
+            CROSSES_DEVICES.call_once(|| {
+                warn!(
+                    "[{}] and [{}] are on different file systems, files are copied to commit them",
+                    from, to
+                )
+            });
+
+            copy_then_remove(from, to).await
+        }
+        committed => committed,
+    }
+}
+
+/// Whether the error is failing to rename across file systems, `EXDEV` on unix.
+fn is_cross_device(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::CrossesDevices
+}
+
+/// Copies the file next to `to` first, so that it's never seen half written, and removes `from`
+/// once it's in place.
+async fn copy_then_remove(from: &str, to: &str) -> io::Result<()> {
+    let part_path = format!("{}.part", to);
+
+    if let Err(e) = copy(from, &part_path).await {
+        let _ = remove_file(&part_path).await;
+        return Err(e);
+    }
+
+    rename(&part_path, to).await?;
+
+    remove_file(from).await
+}
+
+/// Runtime form of a category in the config file, checking its values.
+fn category_config(name: &str, config: CategorySettings) -> CategoryConfig {
+    for (key, quality) in [
+        ("min_quality", config.min_quality),
+        ("max_quality", config.max_quality),
+        ("default_compress", config.default_compress),
+        ("thumbnail_quality", config.thumbnail_quality),
+    ] {
+        if quality.is_some_and(|quality| !(1..=100).contains(&quality)) {
+            panic!("{} of category [{}] must be from 1 to 100", key, name);
+        }
+    }
+
+    if config.max_file_size == Some(0) {
+        panic!("max_file_size of category [{}] must be above 0", name);
+    }
+
+    if !(1..=RESIZE_MAX_DIMENSION).contains(&config.thumbnail_size) {
+        panic!(
+            "thumbnail_size of category [{}] must be from 1 to {}",
+            name, RESIZE_MAX_DIMENSION
+        );
+    }
+
+    if config.immutable && config.cache_control.is_some() {
+        panic!(
+            "cache_control and immutable of category [{}] can't be both set",
+            name
+        );
+    }
+
+    if let (Some(min), Some(max)) = (config.min_quality, config.max_quality) {
+        if min > max {
+            panic!(
+                "min_quality of category [{}] is above its max_quality",
+                name
+            );
+        }
+    }
+
+    CategoryConfig {
+        allow_non_image_content: config.allow_all_files,
+        shard: config.shard,
+        autorotate: config.autorotate,
+        strip_metadata: config.strip_metadata,
+        watermark: config
+            .watermark
+            .as_deref()
+            .map(|path| Arc::new(load_watermark(path, &config))),
+        download_rate: config.download_rate,
+        keep_original: config.keep_original,
+        filename_template: config.filename_template.as_deref().map(|template| {
+            FilenameTemplate::parse(template).unwrap_or_else(|e| {
+                panic!("invalid filename_template of category [{}]: {}", name, e)
+            })
+        }),
+        correct_extension: config.correct_extension,
+        min_quality: config.min_quality,
+        max_quality: config.max_quality,
+        default_compress: config.default_compress,
+        thumbnail_size: config.thumbnail_size,
+        thumbnail_quality: config.thumbnail_quality,
+        eager_thumbnails: config.eager_thumbnails,
+        blurhash: config.blurhash,
+        async_processing: config.async_processing,
+        allow_svg: config.allow_svg,
+        max_file_size: config.max_file_size,
+        hotlink: config
+            .allowed_referers
+            .map(|domains| Hotlink::new(domains, config.allow_empty_referer)),
+        immutable: config.immutable,
+        cache_control: config.cache_control.map(|cache_control| {
+            HeaderValue::try_from(cache_control)
+                .unwrap_or_else(|_| panic!("invalid cache_control of category [{}]", name))
+        }),
+    }
+}
+
+/// Loads the logo of a category, relative to the executable if not absolute, along with its
+/// placement in `config`.
+fn load_watermark(path: &str, config: &CategorySettings) -> Watermark {
+    let logo = image::open(exe_path().join(path))
+        .unwrap_or_else(|e| panic!("failed to load watermark [{}]: {}", path, e))
+        .to_rgba8();
+
+    let position = &config.watermark_position;
+
+    Watermark {
+        logo,
+        position: WatermarkPosition::from_name(position)
+            .unwrap_or_else(|| panic!("unknown watermark_position [{}]", position)),
+        opacity: config.watermark_opacity,
+    }
+}
+
+fn exe_path() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+
+    path.pop();
+
+    path
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,93 @@
+//! Key/value metadata of assets given along with them at upload, such as alt texts and captions,
+//! kept next to them under `metadata/` as json objects.
+
+use picup_lib::Metadata;
+use tokio::io;
+
+use crate::SrvState;
+
+/// Bytes of the keys and values of a file together.
+pub const MAX_SIZE: usize = 4096;
+
+const MAX_KEY_LEN: usize = 64;
+
+/// Letters, digits, `-`, `_` and `.`, such as `alt` or `exif.author`.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Parses the [`picup_lib::METADATA_HEADER`] of a file part, telling what's wrong with the first
+/// pair that isn't valid.
+pub fn parse(header: &str) -> Result<Metadata, String> {
+    let mut metadata = Metadata::new();
+    let mut size = 0;
+
+    for pair in header.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        let (key, value) = match (urlencoding::decode(key), urlencoding::decode(value)) {
+            (Ok(key), Ok(value)) => (key.into_owned(), value.into_owned()),
+            _ => return Err(format!("[{}] is not url-encoded utf-8", pair)),
+        };
+
+        if !is_valid_key(&key) {
+            return Err(format!("invalid key [{}]", key));
+        }
+
+        size += key.len() + value.len();
+
+        if size > MAX_SIZE {
+            return Err(format!("more than {} bytes", MAX_SIZE));
+        }
+
+        if metadata.contains_key(&key) {
+            return Err(format!("key [{}] given twice", key));
+        }
+
+        metadata.insert(key, value);
+    }
+
+    Ok(metadata)
+}
+
+fn metadata_path(state: &SrvState, category: &str, file_name: &str) -> String {
+    uri_concat!(
+        &state.stored_dir("metadata", category, file_name),
+        file_name
+    )
+}
+
+/// Records the metadata of an asset, or drops what it has if there is none.
+pub async fn record(
+    state: &SrvState,
+    category: &str,
+    file_name: &str,
+    metadata: Option<&Metadata>,
+) -> io::Result<()> {
+    let path = metadata_path(state, category, file_name);
+
+    match metadata.filter(|metadata| !metadata.is_empty()) {
+        Some(metadata) => {
+            state
+                .storage
+                .write(&path, serde_json::to_vec(metadata)?)
+                .await
+        }
+        None => state.storage.remove(&path).await,
+    }
+}
+
+/// Metadata of the asset, empty if it has none.
+pub async fn stored(state: &SrvState, category: &str, file_name: &str) -> Metadata {
+    state
+        .storage
+        .read(&metadata_path(state, category, file_name))
+        .await
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default()
+}
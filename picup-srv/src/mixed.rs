@@ -0,0 +1,59 @@
+use axum::body::Bytes;
+use futures_util::{
+    future::ready,
+    stream::{self, once},
+    Stream, StreamExt,
+};
+use tokio::{fs::File, io};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+/// A file sent as a part of a `multipart/mixed` body.
+pub struct MixedPart {
+    pub name: String,
+    pub path: String,
+    pub content_type: &'static str,
+}
+
+/// Streams the files one after another as parts delimited by `boundary`, opening each only once
+/// it's its turn, so that they are never held in memory at once.
+///
+/// Files removed in the meantime are left out, the headers have been sent already by then.
+pub fn mixed_stream(
+    boundary: String,
+    parts: Vec<MixedPart>,
+) -> impl Stream<Item = io::Result<Bytes>> + Unpin {
+    let closing = Bytes::from(format!("--{}--\r\n", boundary));
+
+    let parts = stream::iter(parts)
+        .then(move |part| {
+            let boundary = boundary.clone();
+
+            async move {
+                let file = match File::open(&part.path).await {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!("failed to stream [{}]: {}", part.path, e);
+                        return None;
+                    }
+                };
+
+                let head = format!(
+                    "--{}\r\nContent-Type: {}\r\nContent-Disposition: attachment; filename=\"{}\"\r\n\r\n",
+                    boundary,
+                    part.content_type,
+                    part.name.replace(['"', '\\'], "_")
+                );
+
+                Some(
+                    once(ready(Ok(Bytes::from(head))))
+                        .chain(ReaderStream::new(file))
+                        .chain(once(ready(Ok(Bytes::from_static(b"\r\n"))))),
+                )
+            }
+        })
+        .filter_map(ready)
+        .flatten();
+
+    Box::pin(parts.chain(once(ready(Ok(closing)))))
+}
@@ -0,0 +1,127 @@
+use uuid::Uuid;
+
+use crate::unix_time;
+
+const PLACEHOLDERS: [&str; 6] = ["name", "ext", "category", "date", "timestamp", "uuid"];
+
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+/// Naming scheme of uploaded files, such as `{date}-{uuid}{ext}`.
+///
+/// Placeholders are `{name}` (the uploaded name without extension), `{ext}` (its extension with
+/// the dot, or nothing), `{category}`, `{date}` (`YYYYMMDD` in UTC), `{timestamp}` (unix seconds)
+/// and `{uuid}` (a random one).
+pub struct FilenameTemplate {
+    parts: Vec<Part>,
+}
+
+/// Whether the char is kept as is in file names and urls.
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+/// Replaces everything but ascii letters, digits, `-`, `_` and `.` with `_`.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if is_safe(c) { c } else { '_' })
+        .collect()
+}
+
+/// Whether a category that is not configured may be created under the name, which becomes a
+/// directory and a url segment.
+pub fn is_valid_category(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && !name.starts_with('.') && name.chars().all(is_safe)
+}
+
+impl FilenameTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed placeholder in [{}]", template))?;
+
+                    let name = &rest[1..end];
+
+                    let placeholder = PLACEHOLDERS
+                        .iter()
+                        .find(|placeholder| **placeholder == name)
+                        .ok_or_else(|| format!("unknown placeholder {{{}}}", name))?;
+
+                    parts.push(Part::Placeholder(placeholder));
+                    rest = &rest[end + 1..];
+                }
+                found => {
+                    let end = found.unwrap_or(rest.len());
+                    let literal = &rest[..end];
+
+                    if let Some(c) = literal.chars().find(|c| !is_safe(*c)) {
+                        return Err(format!("[{}] is not allowed in file names", c));
+                    }
+
+                    parts.push(Part::Literal(literal.to_string()));
+                    rest = &rest[end..];
+                }
+            }
+        }
+
+        if parts.is_empty() {
+            return Err("empty template".to_string());
+        }
+
+        Ok(FilenameTemplate { parts })
+    }
+
+    /// Name of an uploaded file, safe to use in paths and urls.
+    pub fn render(&self, file_name: &str, category: &str) -> String {
+        let (name, ext) = match file_name.rfind('.') {
+            Some(dot) if dot > 0 => (&file_name[..dot], &file_name[dot..]),
+            _ => (file_name, ""),
+        };
+
+        let mut rendered = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Placeholder("name") => rendered.push_str(&sanitize(name)),
+                Part::Placeholder("ext") => rendered.push_str(&sanitize(ext)),
+                Part::Placeholder("category") => rendered.push_str(&sanitize(category)),
+                Part::Placeholder("date") => rendered.push_str(&utc_date(unix_time())),
+                Part::Placeholder("timestamp") => rendered.push_str(&unix_time().to_string()),
+                Part::Placeholder(_) => rendered.push_str(&Uuid::new_v4().simple().to_string()),
+            }
+        }
+
+        // would be hidden, or refer to a parent directory
+        match rendered.trim_start_matches('.') {
+            "" => "_".to_string(),
+            trimmed => trimmed.to_string(),
+        }
+    }
+}
+
+/// `YYYYMMDD` of the unix time, see http://howardhinnant.github.io/date_algorithms.html
+fn utc_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64 + 719468;
+
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}", year, month, day)
+}
@@ -0,0 +1,148 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::{net::TcpListener, sync::Semaphore};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+/// How connections are served, see `[server.http]` in the config.
+pub struct HttpConfig {
+    /// serves https, where http/2 is negotiated by ALPN
+    pub tls: Option<Arc<ServerConfig>>,
+
+    /// serves http/2 over plain tcp to clients speaking it with prior knowledge
+    pub h2c: bool,
+
+    /// keeps http/1 connections open for further requests
+    pub keep_alive: bool,
+
+    /// interval of http/2 pings keeping idle connections alive
+    pub keep_alive_interval: Option<Duration>,
+
+    pub max_concurrent_streams: Option<u32>,
+
+    /// connections beyond it wait to be accepted
+    pub max_connections: Option<usize>,
+}
+
+/// Loads the certificate chain and the private key from pem files, offering http/2 and http/1.1.
+pub fn load_tls(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_reader_iter(&mut BufReader::new(
+        File::open(cert).map_err(|e| format!("[{}]: {}", cert.display(), e))?,
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| format!("[{}]: {}", cert.display(), e))?;
+
+    let key =
+        PrivateKeyDer::from_pem_file(key).map_err(|e| format!("[{}]: {}", key.display(), e))?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| e.to_string())?;
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+pub async fn serve(listener: TcpListener, app: Router, config: HttpConfig) {
+    let tls = config.tls.clone().map(TlsAcceptor::from);
+
+    let mut builder = Builder::new(TokioExecutor::new());
+
+    builder.http1().keep_alive(config.keep_alive);
+    builder
+        .http2()
+        .keep_alive_interval(config.keep_alive_interval);
+
+    // hyper limits it to 200 by default
+    if let Some(max) = config.max_concurrent_streams {
+        builder.http2().max_concurrent_streams(max);
+    }
+
+    // plain http/2 only if asked for, tls negotiates it anyway
+    if tls.is_none() && !config.h2c {
+        builder = builder.http1_only();
+    }
+
+    let builder = Arc::new(builder);
+
+    let limit = config
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+
+    info!(
+        "serving {}, http/2: {}",
+        if tls.is_some() { "https" } else { "http" },
+        tls.is_some() || config.h2c
+    );
+
+    loop {
+        let permit = match &limit {
+            Some(limit) => Some(limit.clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
+
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // e.g. out of file descriptors, which may pass
+                warn!("failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let _ = stream.set_nodelay(true);
+
+        let tls = tls.clone();
+        let builder = builder.clone();
+        // for handlers extracting `ConnectInfo<SocketAddr>`
+        let service =
+            TowerToHyperService::new(app.clone().map_request(move |mut req: Request<_>| {
+                req.extensions_mut().insert(ConnectInfo(addr));
+                req
+            }));
+
+        tokio::spawn(async move {
+            // released when the connection is closed
+            let _permit = permit;
+
+            let served = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => {
+                        builder
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await
+                    }
+                    Err(e) => {
+                        warn!("tls handshake with [{}] failed: {}", addr, e);
+                        return;
+                    }
+                },
+                None => {
+                    builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                }
+            };
+
+            if let Err(e) = served {
+                warn!("connection with [{}] failed: {}", addr, e);
+            }
+        });
+    }
+}
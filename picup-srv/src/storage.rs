@@ -0,0 +1,210 @@
+//! Where uploads are committed and served from, by their paths under `pic_directory`, along with
+//! the hashes, BlurHashes and metadata kept next to them. [`FileSystem`] is what the server uses,
+//! [`Memory`] keeps everything in memory for handler tests.
+//!
+//! Only uploading and getting assets goes through it. Variants and thumbnails, kept originals,
+//! background processing, listings, archives, montages and categories created by uploads still go
+//! to the file system directly, so tests of those need a [`FileSystem`] state.
+
+use std::time::SystemTime;
+
+use axum::body::Bytes;
+use futures_util::{future::BoxFuture, stream::BoxStream, StreamExt};
+use tokio::{
+    fs::{create_dir_all, metadata, read, remove_dir_all, remove_file, write, File},
+    io,
+};
+use tokio_util::io::ReaderStream;
+
+pub trait Storage: Send + Sync {
+    /// `NotFound` if nothing is stored there.
+    fn read<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>>;
+
+    /// The bytes stored there as they are read, `NotFound` if there are none.
+    fn stream<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> BoxFuture<'a, io::Result<BoxStream<'static, io::Result<Bytes>>>>;
+
+    /// Stores the bytes, replacing those stored there already.
+    fn write<'a>(&'a self, path: &'a str, bytes: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Moves what's stored at `from` to `to`, replacing what's there.
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Drops what's stored there, if anything.
+    fn remove<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Drops everything stored under the directory.
+    fn clear<'a>(&'a self, dir: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// When it was last written, `None` if nothing is stored there.
+    fn modified<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Option<SystemTime>>>;
+}
+
+/// Files at their paths, whose directories are made as they are needed.
+pub struct FileSystem;
+
+async fn create_parent(path: &str) -> io::Result<()> {
+    match std::path::Path::new(path).parent() {
+        Some(parent) => create_dir_all(parent).await,
+        None => Ok(()),
+    }
+}
+
+impl Storage for FileSystem {
+    fn read<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(read(path))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> BoxFuture<'a, io::Result<BoxStream<'static, io::Result<Bytes>>>> {
+        Box::pin(async move { Ok(ReaderStream::new(File::open(path).await?).boxed()) })
+    }
+
+    fn write<'a>(&'a self, path: &'a str, bytes: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            create_parent(path).await?;
+
+            write(path, bytes).await
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        // the directory might have been removed while running, or is a new shard
+        Box::pin(async move {
+            create_parent(to).await?;
+
+            crate::commit_file(from, to).await
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn clear<'a>(&'a self, dir: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match remove_dir_all(dir).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+
+            create_dir_all(dir).await
+        })
+    }
+
+    fn modified<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Option<SystemTime>>> {
+        Box::pin(async move {
+            match metadata(path).await {
+                Ok(meta) => meta.modified().map(Some),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+/// Bytes by path, along with when they were written, which are gone with it.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Memory {
+    files: std::sync::Mutex<std::collections::HashMap<String, (Vec<u8>, SystemTime)>>,
+}
+
+#[cfg(test)]
+impl Memory {
+    fn get(&self, path: &str) -> io::Result<Vec<u8>> {
+        match self.files.lock().unwrap().get(path) {
+            Some((bytes, _)) => Ok(bytes.clone()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    /// Paths of all that's stored, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        paths.sort();
+
+        paths
+    }
+}
+
+#[cfg(test)]
+impl Storage for Memory {
+    fn read<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move { self.get(path) })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> BoxFuture<'a, io::Result<BoxStream<'static, io::Result<Bytes>>>> {
+        Box::pin(async move {
+            let bytes = Bytes::from(self.get(path)?);
+
+            Ok(futures_util::stream::once(async move { Ok(bytes) }).boxed())
+        })
+    }
+
+    fn write<'a>(&'a self, path: &'a str, bytes: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.files
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), (bytes, SystemTime::now()));
+
+            Ok(())
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut files = self.files.lock().unwrap();
+
+            let file = files.remove(from).ok_or(io::ErrorKind::NotFound)?;
+            files.insert(to.to_string(), file);
+
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.files.lock().unwrap().remove(path);
+
+            Ok(())
+        })
+    }
+
+    fn clear<'a>(&'a self, dir: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+
+        Box::pin(async move {
+            self.files
+                .lock()
+                .unwrap()
+                .retain(|path, _| !path.starts_with(&prefix));
+
+            Ok(())
+        })
+    }
+
+    fn modified<'a>(&'a self, path: &'a str) -> BoxFuture<'a, io::Result<Option<SystemTime>>> {
+        Box::pin(async move { Ok(self.files.lock().unwrap().get(path).map(|(_, at)| *at)) })
+    }
+}
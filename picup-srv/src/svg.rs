@@ -0,0 +1,190 @@
+use quick_xml::{
+    events::{attributes::Attribute, BytesStart, Event},
+    Reader, Writer,
+};
+
+/// Elements dropped along with their content, which can run scripts or embed other documents.
+const BLOCKED_ELEMENTS: [&str; 7] = [
+    "script",
+    "foreignobject",
+    "iframe",
+    "embed",
+    "object",
+    "handler",
+    "listener",
+];
+
+/// Whether the file is an svg image, by its extension or content.
+pub fn is_svg(file_name: &str, bytes: &[u8]) -> bool {
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension);
+
+    if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("svg")) {
+        return true;
+    }
+
+    // the root element is svg, after the declaration, doctype and comments if any
+    let head = &bytes[..bytes.len().min(1024)];
+    let head = String::from_utf8_lossy(head);
+
+    let mut rest = head.trim_start_matches('\u{feff}').trim_start();
+
+    while let Some(tag) = rest.strip_prefix('<') {
+        if tag.starts_with("svg") {
+            return true;
+        }
+
+        if !tag.starts_with(['?', '!']) {
+            return false;
+        }
+
+        rest = match rest.find('>') {
+            Some(end) => rest[end + 1..].trim_start(),
+            None => return false,
+        };
+    }
+
+    false
+}
+
+/// Whether css loads anything from outside the document.
+fn has_external_url(css: &str) -> bool {
+    let css = css.to_ascii_lowercase();
+
+    css.contains("@import")
+        || css.match_indices("url(").any(|(i, _)| {
+            let target = css[i + 4..].trim_start_matches([' ', '"', '\'']);
+
+            !target.starts_with('#')
+        })
+}
+
+/// Whether a reference stays within the document, or is inline raster data.
+fn is_local_ref(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+
+    value.starts_with('#')
+        || (value.starts_with("data:image/") && !value.starts_with("data:image/svg"))
+}
+
+/// Whether an animation targets a reference or an event handler.
+fn animates_unsafely(attribute_name: &str) -> bool {
+    let attribute_name = attribute_name.trim().to_ascii_lowercase();
+
+    attribute_name == "href"
+        || attribute_name.ends_with(":href")
+        || attribute_name.starts_with("on")
+}
+
+/// Copy of the element without event handlers, external references and the like, or `None` if
+/// the whole element has to go.
+fn sanitize_element(element: &BytesStart) -> Result<Option<BytesStart<'static>>, String> {
+    let name = String::from_utf8_lossy(element.local_name().as_ref()).to_ascii_lowercase();
+
+    if BLOCKED_ELEMENTS.contains(&name.as_str()) {
+        return Ok(None);
+    }
+
+    let mut sanitized =
+        BytesStart::new(String::from_utf8_lossy(element.name().as_ref()).to_string());
+
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+
+        let key = String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_ascii_lowercase();
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+
+        // <set attributeName="href" to="javascript:..."> and such
+        if key == "attributename" && animates_unsafely(&value) {
+            return Ok(None);
+        }
+
+        let dropped = key.starts_with("on")
+            || (key == "href" && !is_local_ref(&value))
+            || (key == "style" && has_external_url(&value));
+
+        if !dropped {
+            sanitized.push_attribute(Attribute {
+                key: attribute.key,
+                value: value.as_bytes().to_vec().into(),
+            });
+        }
+    }
+
+    Ok(Some(sanitized))
+}
+
+/// Strips scripts, event handlers and references to anything outside the image from an svg, so
+/// that it can't run code or leak visits when opened. The doctype is dropped as well, which may
+/// declare entities.
+pub fn sanitize(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader::from_reader(bytes);
+    let mut writer = Writer::new(Vec::with_capacity(bytes.len()));
+
+    // depth within a dropped element, 0 if not in one
+    let mut dropping = 0;
+    let mut in_style = false;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("at {}: {}", reader.buffer_position(), e))?;
+
+        if dropping > 0 {
+            match event {
+                Event::Start(_) => dropping += 1,
+                Event::End(_) => dropping -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+
+            continue;
+        }
+
+        let event = match event {
+            Event::Start(element) => match sanitize_element(&element)? {
+                Some(sanitized) => {
+                    in_style = sanitized
+                        .local_name()
+                        .as_ref()
+                        .eq_ignore_ascii_case(b"style");
+                    Event::Start(sanitized)
+                }
+                None => {
+                    dropping = 1;
+                    continue;
+                }
+            },
+            Event::Empty(element) => match sanitize_element(&element)? {
+                Some(sanitized) => Event::Empty(sanitized),
+                None => continue,
+            },
+            Event::End(element) => {
+                in_style = false;
+                Event::End(element)
+            }
+            Event::Text(text) => {
+                let unescaped = text.unescape().map_err(|e| e.to_string())?;
+
+                if in_style && has_external_url(&unescaped) {
+                    continue;
+                }
+
+                Event::Text(text)
+            }
+            Event::CData(cdata) => {
+                if in_style && has_external_url(&String::from_utf8_lossy(&cdata)) {
+                    continue;
+                }
+
+                Event::CData(cdata)
+            }
+            Event::Decl(decl) => Event::Decl(decl),
+            Event::DocType(_) | Event::PI(_) | Event::Comment(_) => continue,
+            Event::Eof => break,
+        };
+
+        writer.write_event(event).map_err(|e| e.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
@@ -0,0 +1,3402 @@
+use std::{
+    collections::HashMap,
+    env::temp_dir,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{
+            CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, EXPECT, IF_NONE_MATCH, REFERER,
+            RETRY_AFTER,
+        },
+        HeaderValue, Request, StatusCode,
+    },
+    Router,
+};
+use futures_util::{future::BoxFuture, StreamExt};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    app,
+    auth::{Scope, ScopedToken},
+    client_ip::ProxyTrust,
+    cors::CorsOrigins,
+    decode_cache::DecodeCache,
+    disk::DiskGuard,
+    hash_index,
+    hook::UploadHook,
+    hotlink::Hotlink,
+    i18n::Messages,
+    imaging::{Watermark, WatermarkPosition},
+    jobs::Jobs,
+    metadata,
+    naming::FilenameTemplate,
+    storage::{FileSystem, Memory, Storage},
+    throttle,
+    url_fetch::{Allowlist, UrlFetcher},
+    CategoryConfig, ImageConfig, SrvState, Timeouts,
+};
+
+const BOUNDARY: &str = "picup-test-boundary";
+
+pub(crate) const PNG_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+/// A fresh state with categories `pic` (images only) and `files` (anything), storing into a
+/// temp directory named after the test.
+pub(crate) fn test_state(name: &str) -> Arc<SrvState> {
+    test_state_with(name, |_| {})
+}
+
+/// [`test_state`] customized by `f` before its directories are created.
+pub(crate) fn test_state_with(name: &str, f: impl FnOnce(&mut SrvState)) -> Arc<SrvState> {
+    let dir = temp_dir().join(format!("picup-srv-test-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut state = unstored_test_state(dir.to_str().unwrap());
+
+    f(&mut state);
+
+    for category in state.categories.keys() {
+        std::fs::create_dir_all(uri_concat!(&state.pic_directory, "asset", category)).unwrap();
+    }
+    std::fs::create_dir_all(uri_concat!(&state.pic_directory, "temp")).unwrap();
+
+    Arc::new(state)
+}
+
+/// [`test_state_with`] storing uploads in memory instead, along with the store, so that tests
+/// don't need a directory of their own. Only uploads, downloads and what's recorded next to them
+/// go through it, see [`crate::storage`].
+pub(crate) fn test_state_in_memory(f: impl FnOnce(&mut SrvState)) -> (Arc<SrvState>, Arc<Memory>) {
+    let memory = Arc::new(Memory::default());
+
+    let mut state = unstored_test_state("/picup-srv-in-memory");
+    state.storage = memory.clone();
+
+    f(&mut state);
+
+    (Arc::new(state), memory)
+}
+
+/// The state of [`test_state`] under `dir`, which isn't created.
+fn unstored_test_state(dir: &str) -> SrvState {
+    let mut categories = HashMap::new();
+
+    categories.insert(
+        "pic".to_string(),
+        CategoryConfig {
+            allow_non_image_content: false,
+            shard: false,
+            autorotate: None,
+            strip_metadata: None,
+            watermark: None,
+            download_rate: None,
+            keep_original: false,
+            filename_template: None,
+            correct_extension: false,
+            hotlink: None,
+            allow_svg: false,
+            max_file_size: None,
+            min_quality: None,
+            max_quality: None,
+            default_compress: None,
+            thumbnail_size: 256,
+            thumbnail_quality: None,
+            eager_thumbnails: false,
+            blurhash: false,
+            async_processing: false,
+            immutable: false,
+            cache_control: None,
+        },
+    );
+    categories.insert(
+        "files".to_string(),
+        CategoryConfig {
+            allow_non_image_content: true,
+            shard: false,
+            autorotate: None,
+            strip_metadata: None,
+            watermark: None,
+            download_rate: None,
+            keep_original: false,
+            filename_template: None,
+            correct_extension: false,
+            hotlink: None,
+            allow_svg: false,
+            max_file_size: None,
+            min_quality: None,
+            max_quality: None,
+            default_compress: None,
+            thumbnail_size: 256,
+            thumbnail_quality: None,
+            eager_thumbnails: false,
+            blurhash: false,
+            async_processing: false,
+            immutable: false,
+            cache_control: None,
+        },
+    );
+
+    SrvState {
+        categories,
+        default_category: None,
+        messages: Arc::new(Messages::default()),
+        image: ImageConfig {
+            autorotate: true,
+            strip_metadata: true,
+            convert_to: None,
+            convert_only_if_smaller: true,
+        },
+        access_token: "baka".to_string(),
+        pic_url_prefix: "http://127.0.0.1:19190/picup".to_string(),
+        pic_directory: dir.to_string(),
+        maintenance: AtomicBool::new(false),
+        timeouts: Timeouts {
+            upload: Duration::from_secs(30),
+            read: Duration::from_secs(30),
+            retry_after: 5,
+        },
+        download_rate: 0,
+        max_files_per_request: 1000,
+        upload_hook: None,
+        rejection_log_level: Some(tracing::Level::INFO),
+        decode_cache: None,
+        proxy_trust: None,
+        scoped_tokens: vec![],
+        asset_cors: CorsOrigins::Any,
+        admin_cors: CorsOrigins::Any,
+        disk_guard: None,
+        // the remote images of tests are on loopback
+        url_fetcher: UrlFetcher::new(
+            Duration::from_secs(5),
+            1 << 20,
+            Allowlist::new(&["127.0.0.0/8".to_string()]).unwrap(),
+        ),
+        jobs: Jobs::default(),
+        storage: Arc::new(FileSystem),
+    }
+}
+
+pub(crate) fn test_app(state: &Arc<SrvState>) -> Router {
+    app(state.clone())
+}
+
+/// Builds a multipart upload request of `(file name, content type, bytes)`.
+pub(crate) fn upload_request(query: &str, files: &[(&str, &str, &[u8])]) -> Request<Body> {
+    let mut body = vec![];
+
+    for (file_name, content_type, bytes) in files {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                BOUNDARY, file_name, content_type
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+    Request::post(format!("/picup/upload?{}", query))
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Sends the request and parses the response body as json.
+pub(crate) async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = app.clone().oneshot(req).await.unwrap();
+
+    let status = res.status();
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_upload_recovers_missing_dirs() {
+    let state = test_state("recovers-missing-dirs");
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    // an operator removes them while the server runs
+    std::fs::remove_dir_all(uri_concat!(&state.pic_directory, "temp")).unwrap();
+    std::fs::remove_dir_all(uri_concat!(&state.pic_directory, "asset", "pic")).unwrap();
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("b.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["code"], 0);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/b.png"
+    );
+
+    let stored = std::fs::read(uri_concat!(&state.pic_directory, "asset", "pic", "b.png")).unwrap();
+    assert_eq!(stored, PNG_BYTES);
+}
+
+#[tokio::test]
+async fn test_upload_rejects_duplicate_file_names() {
+    let (state, memory) = test_state_in_memory(|_| {});
+    let app = test_app(&state);
+
+    for query in [
+        "access_token=baka&category=pic",
+        "access_token=baka&category=pic&override=true",
+    ] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                query,
+                &[
+                    ("a.png", "image/png", PNG_BYTES),
+                    ("a.png", "image/png", b"\x89PNG\r\n\x1a\nother"),
+                ],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+        assert_eq!(json["code"], 1004, "{}", json);
+    }
+
+    // nothing is committed, the staged copy is dropped with the next upload
+    assert_eq!(memory.paths(), ["/picup-srv-in-memory/temp/a.png"]);
+    assert!(!std::path::Path::new(&state.pic_directory).exists());
+}
+
+#[tokio::test]
+async fn test_upload_rejects_too_many_files() {
+    let state = test_state_with("rejects-too-many-files", |state| {
+        state.max_files_per_request = 2;
+    });
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("a.png", "image/png", PNG_BYTES),
+                ("b.png", "image/png", PNG_BYTES),
+                ("c.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+    assert_eq!(json["code"], 1012, "{}", json);
+
+    // nothing is committed
+    assert!(
+        !std::path::Path::new(&uri_concat!(&state.pic_directory, "asset", "pic", "a.png")).exists()
+    );
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("a.png", "image/png", PNG_BYTES),
+                ("b.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+}
+
+#[tokio::test]
+async fn test_disk_full() {
+    let state = test_state_with("disk-full", |state| {
+        // no volume has that much free
+        state.disk_guard = Some(DiskGuard::new(u64::MAX, 0.0));
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "a.png"),
+        PNG_BYTES,
+    )
+    .unwrap();
+
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=pic",
+            &[("b.png", "image/png", PNG_BYTES)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(RETRY_AFTER));
+
+    let json: Value =
+        serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(json["code"], 1014, "{}", json);
+
+    let (status, body) = get_bytes(&app, "/picup/asset/pic/a.png").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, PNG_BYTES);
+}
+
+#[tokio::test]
+async fn test_maintenance() {
+    let state = test_state_with("maintenance", |state| {
+        state.scoped_tokens = vec![ScopedToken {
+            name: "admin".to_string(),
+            value: "admin".to_string(),
+            categories: None,
+            scopes: vec![Scope::Read, Scope::Write, Scope::Delete],
+        }];
+    });
+    let app = test_app(&state);
+
+    let upload = || {
+        upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[("a.png", "image/png", PNG_BYTES)],
+        )
+    };
+
+    let toggle = |method: &str, token: &str| {
+        Request::builder()
+            .method(method)
+            .uri(format!("/picup/maintenance?access_token={}", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, json) = send(&app, upload()).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    // only the token of the config may toggle it
+    for method in ["POST", "DELETE"] {
+        let (status, json) = send(&app, toggle(method, "nope")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+        assert_eq!(json["code"], 1001);
+
+        let (status, json) = send(&app, toggle(method, "admin")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", json);
+        assert_eq!(json["code"], 1015);
+    }
+    assert!(!state.maintenance.load(Ordering::Relaxed));
+
+    let (status, json) = send(&app, toggle("POST", "baka")).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let res = app.clone().oneshot(upload()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()[RETRY_AFTER], "60");
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["code"], 1007);
+
+    // reads go on
+    let (status, body) = get_bytes(&app, "/picup/asset/pic/a.png").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, PNG_BYTES);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/categories?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (status, json) = send(&app, toggle("DELETE", "baka")).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (status, json) = send(&app, upload()).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+}
+
+#[tokio::test]
+async fn test_cors() {
+    let state = test_state_with("cors", |state| {
+        state.admin_cors = CorsOrigins::new(&["https://admin.example/".to_string()]).unwrap();
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "a.png"),
+        PNG_BYTES,
+    )
+    .unwrap();
+
+    let allowed_origin = |req: Request<Body>| {
+        let app = app.clone();
+
+        async move {
+            let res = app.oneshot(req).await.unwrap();
+
+            res.headers()
+                .get("access-control-allow-origin")
+                .map(|v| v.to_str().unwrap().to_string())
+        }
+    };
+
+    let preflight = |uri: &str, origin: &str| {
+        Request::options(uri)
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for (uri, origin, allowed) in [
+        (
+            "/picup/upload",
+            "https://admin.example",
+            Some("https://admin.example"),
+        ),
+        ("/picup/upload", "https://evil.example", None),
+        ("/picup/recent", "https://evil.example", None),
+        ("/picup/asset/pic/a.png", "https://evil.example", Some("*")),
+    ] {
+        assert_eq!(
+            allowed_origin(preflight(uri, origin)).await.as_deref(),
+            allowed,
+            "{} from {}",
+            uri,
+            origin
+        );
+    }
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/asset/pic/a.png")
+                .header("origin", "https://evil.example")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["access-control-allow-origin"], "*");
+    assert_eq!(res.headers()["access-control-expose-headers"], "*");
+
+    assert!(CorsOrigins::new(&["bad\norigin".to_string()]).is_err());
+}
+
+#[tokio::test]
+async fn test_scoped_tokens() {
+    let state = test_state_with("scoped-tokens", |state| {
+        state.scoped_tokens = vec![
+            ScopedToken {
+                name: "frontend".to_string(),
+                value: "front".to_string(),
+                categories: Some(vec!["pic".to_string()]),
+                scopes: vec![Scope::Write],
+            },
+            ScopedToken {
+                name: "viewer".to_string(),
+                value: "view".to_string(),
+                categories: Some(vec!["pic".to_string()]),
+                scopes: vec![Scope::Read],
+            },
+        ];
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "files", "b.txt"),
+        "b",
+    )
+    .unwrap();
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=front&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    for (req, status, code) in [
+        (
+            upload_request(
+                "access_token=front&category=files",
+                &[("c.txt", "text/plain", b"c".as_slice())],
+            ),
+            StatusCode::FORBIDDEN,
+            1015,
+        ),
+        (
+            upload_request(
+                "access_token=view&category=pic",
+                &[("c.png", "image/png", PNG_BYTES)],
+            ),
+            StatusCode::FORBIDDEN,
+            1015,
+        ),
+        (
+            Request::get("/picup/recent?access_token=front")
+                .body(Body::empty())
+                .unwrap(),
+            StatusCode::FORBIDDEN,
+            1015,
+        ),
+        (
+            Request::post("/picup/maintenance?access_token=view")
+                .body(Body::empty())
+                .unwrap(),
+            StatusCode::FORBIDDEN,
+            1015,
+        ),
+        (
+            Request::get("/picup/recent?access_token=nope")
+                .body(Body::empty())
+                .unwrap(),
+            StatusCode::BAD_REQUEST,
+            1001,
+        ),
+    ] {
+        let (actual, json) = send(&app, req).await;
+        assert_eq!(actual, status, "{}", json);
+        assert_eq!(json["code"], code, "{}", json);
+    }
+
+    // only the categories of the token are listed
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=view")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    let names = json["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|upload| upload["name"].as_str().unwrap())
+        .collect::<Vec<&str>>();
+    assert_eq!(names, ["a.png"]);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["data"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_expect_continue() {
+    let state = test_state("expect-continue");
+    let app = test_app(&state);
+
+    // never read, as if the client is waiting to send it
+    for (query, expect, length, status, code) in [
+        (
+            "access_token=baka&category=pic",
+            "100-continue",
+            1024,
+            StatusCode::OK,
+            0,
+        ),
+        (
+            "access_token=baka&category=pic",
+            "something-else",
+            1024,
+            StatusCode::EXPECTATION_FAILED,
+            990,
+        ),
+        (
+            "access_token=nope&category=pic",
+            "100-continue",
+            1024,
+            StatusCode::UNAUTHORIZED,
+            1001,
+        ),
+        (
+            "access_token=baka&category=nope",
+            "100-Continue",
+            1024,
+            StatusCode::BAD_REQUEST,
+            1006,
+        ),
+        (
+            "access_token=baka&category=pic",
+            "100-continue",
+            1024 * 1024 * 64,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            993,
+        ),
+    ] {
+        let mut req = upload_request(query, &[("a.png", "image/png", PNG_BYTES)]);
+
+        if status != StatusCode::OK {
+            *req.body_mut() = Body::empty();
+            req.headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+
+        req.headers_mut()
+            .insert(EXPECT, HeaderValue::from_static(expect));
+
+        let (actual, json) = send(&app, req).await;
+        assert_eq!(actual, status, "{}", json);
+        assert_eq!(json["code"], code, "{}", json);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_timeout() {
+    let state = test_state_with("upload-timeout", |state| {
+        state.timeouts.upload = Duration::from_millis(50);
+    });
+    let app = test_app(&state);
+
+    // a client stalling in the middle of the body
+    let head = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n",
+        BOUNDARY
+    );
+    let body = futures_util::stream::once(async move { Ok::<_, std::io::Error>(head) })
+        .chain(futures_util::stream::pending());
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::post("/picup/upload?access_token=baka&category=pic")
+                .header(
+                    CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(Body::from_stream(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(res.headers()[RETRY_AFTER], "5");
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["code"], 1010, "{}", json);
+
+    // reads have their own limit
+    let (status, _) = get_bytes(&app, "/picup/version").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_upload_rejects_truncated_body() {
+    let state = test_state("rejects-truncated-body");
+    let app = test_app(&state);
+
+    let full = to_bytes(
+        upload_request(
+            "",
+            &[
+                ("a.png", "image/png", PNG_BYTES),
+                ("b.png", "image/png", PNG_BYTES),
+            ],
+        )
+        .into_body(),
+        usize::MAX,
+    )
+    .await
+    .unwrap();
+
+    // cut off in the middle of the second file, and a connection dropped there
+    let cut = full.len() - BOUNDARY.len() - 12;
+    let ended = Body::from(full.slice(..cut));
+    let dropped = Body::from_stream(
+        futures_util::stream::once(async move { Ok(full.slice(..cut)) }).chain(
+            futures_util::stream::once(async {
+                Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+            }),
+        ),
+    );
+
+    for body in [ended, dropped] {
+        let (status, json) = send(
+            &app,
+            Request::post("/picup/upload?access_token=baka&category=pic")
+                .header(
+                    CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(body)
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+        assert_eq!(json["code"], 1005, "{}", json);
+    }
+
+    // not even the complete first file is committed
+    assert!(
+        !std::path::Path::new(&uri_concat!(&state.pic_directory, "asset", "pic", "a.png")).exists()
+    );
+}
+
+/// A jpeg of the size with an EXIF orientation tag inserted after SOI.
+pub(crate) fn jpeg_with_orientation(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+    let image = image::DynamicImage::new_rgb8(width, height);
+    let mut jpeg = std::io::Cursor::new(vec![]);
+    image.write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+    let jpeg = jpeg.into_inner();
+
+    let mut exif = b"Exif\0\0MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+    exif.extend_from_slice(&orientation.to_be_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+    let mut bytes = jpeg[0..2].to_vec();
+    bytes.extend_from_slice(&[0xff, 0xe1]);
+    bytes.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    bytes.extend_from_slice(&exif);
+    bytes.extend_from_slice(&jpeg[2..]);
+
+    bytes
+}
+
+pub(crate) async fn get_bytes(app: &Router, uri: &str) -> (StatusCode, Vec<u8>) {
+    let res = app
+        .clone()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    let status = res.status();
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn test_get_autorotate() {
+    let state = test_state("get-autorotate");
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "r.jpg"),
+        &jpeg,
+    )
+    .unwrap();
+
+    let (status, original) = get_bytes(&app, "/picup/asset/pic/r.jpg").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(original, jpeg);
+
+    let (status, rotated) = get_bytes(&app, "/picup/asset/pic/r.jpg?autorotate=1").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let rotated = image::load_from_memory(&rotated).unwrap();
+    assert_eq!((rotated.width(), rotated.height()), (2, 4));
+
+    // not an image, served as is
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "files", "a.txt"),
+        "hello",
+    )
+    .unwrap();
+
+    let (status, text) = get_bytes(&app, "/picup/asset/files/a.txt?autorotate=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(text, b"hello");
+}
+
+#[tokio::test]
+async fn test_get_resized() {
+    let state = test_state("get-resized");
+    let app = test_app(&state);
+
+    let png = png_of_size(40, 20);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "a.png"),
+        &png,
+    )
+    .unwrap();
+
+    for (query, dimensions) in [("w=10", (10, 5)), ("h=5", (10, 5)), ("w=10&h=2", (4, 2))] {
+        let (status, resized) = get_bytes(&app, &format!("/picup/asset/pic/a.png?{}", query)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let resized = image::load_from_memory(&resized).unwrap();
+        assert_eq!((resized.width(), resized.height()), dimensions, "{}", query);
+    }
+
+    // cached by dimensions
+    assert!(std::path::Path::new(&uri_concat!(
+        &state.pic_directory,
+        "cache",
+        "pic",
+        "resize-10x2",
+        "a.png"
+    ))
+    .exists());
+
+    // never enlarged
+    let (status, original) = get_bytes(&app, "/picup/asset/pic/a.png?w=100&h=100").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(original, png);
+
+    let (status, _) = get_bytes(&app, "/picup/asset/pic/a.png?w=100000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+fn has_exif(bytes: &[u8]) -> bool {
+    use image::ImageDecoder;
+
+    let mut decoder = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .unwrap()
+        .into_decoder()
+        .unwrap();
+
+    decoder.exif_metadata().unwrap().is_some()
+}
+
+#[tokio::test]
+async fn test_upload_autorotates_and_strips_by_default() {
+    let state = test_state("upload-autorotates");
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+    assert!(has_exif(&jpeg));
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("r.jpg", "image/jpeg", &jpeg)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let stored = std::fs::read(uri_concat!(&state.pic_directory, "asset", "pic", "r.jpg")).unwrap();
+    assert!(!has_exif(&stored));
+
+    let stored = image::load_from_memory(&stored).unwrap();
+    assert_eq!((stored.width(), stored.height()), (2, 4));
+
+    // nothing to do for an upright image without metadata
+    let (status, _) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let stored = std::fs::read(uri_concat!(&state.pic_directory, "asset", "pic", "a.png")).unwrap();
+    assert_eq!(stored, PNG_BYTES);
+}
+
+#[tokio::test]
+async fn test_upload_processing_overridden_by_category() {
+    let state = test_state_with("upload-processing-overridden", |state| {
+        let pic = state.categories.get_mut("pic").unwrap();
+        pic.autorotate = Some(false);
+        pic.strip_metadata = Some(false);
+    });
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("r.jpg", "image/jpeg", &jpeg)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let stored = std::fs::read(uri_concat!(&state.pic_directory, "asset", "pic", "r.jpg")).unwrap();
+    assert_eq!(stored, jpeg);
+}
+
+fn png_of_size(width: u32, height: u32) -> Vec<u8> {
+    let mut png = std::io::Cursor::new(vec![]);
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    png.into_inner()
+}
+
+#[tokio::test]
+async fn test_get_montage() {
+    let state = test_state("get-montage");
+    let app = test_app(&state);
+
+    for (file_name, bytes) in [
+        ("a.png", png_of_size(8, 4)),
+        ("b.png", png_of_size(4, 8)),
+        ("c.png", png_of_size(8, 8)),
+    ] {
+        std::fs::write(
+            uri_concat!(&state.pic_directory, "asset", "pic", file_name),
+            bytes,
+        )
+        .unwrap();
+    }
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "d.png"),
+        PNG_BYTES,
+    )
+    .unwrap();
+
+    let (status, montage) = get_bytes(
+        &app,
+        "/picup/category/pic/montage?files=c.png,a.png,b.png&columns=2&cell=16",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let montage = image::load_from_memory(&montage).unwrap();
+    assert_eq!((montage.width(), montage.height()), (32, 32));
+
+    // served from the cache regardless of the order
+    let (status, cached) = get_bytes(
+        &app,
+        "/picup/category/pic/montage?files=a.png,b.png,c.png&columns=2&cell=16",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(image::load_from_memory(&cached).unwrap(), montage);
+
+    let (_, json) = send(
+        &app,
+        Request::get("/picup/category/pic/montage?files=a.png,missing.png")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(json["code"], 1008, "{}", json);
+
+    let (_, json) = send(
+        &app,
+        Request::get("/picup/category/pic/montage?files=a.png,d.png")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(json["code"], 1003, "{}", json);
+}
+
+#[tokio::test]
+async fn test_presigned_upload() {
+    let state = test_state("presigned-upload");
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        Request::post("/picup/upload/presign?access_token=baka&category=pic&max_size=20")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let url = json["data"]["url"].as_str().unwrap();
+    let query = url.split_once('?').unwrap().1;
+
+    let (status, json) = send(
+        &app,
+        upload_request(query, &[("a.png", "image/png", PNG_BYTES)]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    // the constraints are signed
+    let (_, json) = send(
+        &app,
+        upload_request(
+            &query.replace("category=pic", "category=files"),
+            &[("b.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(json["code"], 1001, "{}", json);
+
+    let (_, json) = send(
+        &app,
+        upload_request(
+            &query.replace("override=false", "override=true"),
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(json["code"], 1001, "{}", json);
+
+    let (_, json) = send(
+        &app,
+        upload_request(
+            query,
+            &[
+                ("c.png", "image/png", PNG_BYTES),
+                ("d.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(json["code"], 1011, "{}", json);
+
+    // nor is the category, whatever the name
+    let (_, json) = send(
+        &app,
+        upload_request(query, &[("../files/f.png", "image/png", PNG_BYTES)]),
+    )
+    .await;
+    assert_eq!(json["code"], 1002, "{}", json);
+    assert!(!std::path::Path::new(&state.asset_path("files", "f.png")).exists());
+
+    let expired = crate::presign::Grant {
+        category: "pic",
+        r#override: false,
+        max_size: 0,
+        expires: 1,
+    };
+
+    let (_, json) = send(
+        &app,
+        upload_request(
+            &format!("category=pic&expires=1&signature={}", expired.sign("baka")),
+            &[("e.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(json["code"], 1001, "{}", json);
+}
+
+/// A jpeg of noise, which gets larger when converted to a lossless format.
+fn noise_jpeg(width: u32, height: u32) -> Vec<u8> {
+    let mut seed = 1u32;
+    let image = image::RgbImage::from_fn(width, height, |_, _| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let [r, g, b, _] = seed.to_be_bytes();
+        image::Rgb([r, g, b])
+    });
+
+    let mut jpeg = std::io::Cursor::new(vec![]);
+    image::DynamicImage::ImageRgb8(image)
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .unwrap();
+    jpeg.into_inner()
+}
+
+#[tokio::test]
+async fn test_convert_only_if_smaller() {
+    let state = test_state_with("convert-only-if-smaller", |state| {
+        state.image.convert_to = Some(image::ImageFormat::WebP);
+    });
+    let app = test_app(&state);
+
+    let png = png_of_size(64, 64);
+    let jpeg = noise_jpeg(32, 32);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("flat.png", "image/png", &png),
+                ("noise.jpg", "image/jpeg", &jpeg),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    // the flat one benefits from conversion
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/flat.webp"
+    );
+    let stored = std::fs::read(uri_concat!(
+        &state.pic_directory,
+        "asset",
+        "pic",
+        "flat.webp"
+    ))
+    .unwrap();
+    assert!(stored.len() < png.len());
+    assert_eq!(
+        image::guess_format(&stored).unwrap(),
+        image::ImageFormat::WebP
+    );
+
+    // the noise doesn't, and is kept as is
+    assert_eq!(
+        json["data"][1]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/noise.jpg"
+    );
+    let stored = std::fs::read(uri_concat!(
+        &state.pic_directory,
+        "asset",
+        "pic",
+        "noise.jpg"
+    ))
+    .unwrap();
+    assert_eq!(stored, jpeg);
+}
+
+#[tokio::test]
+async fn test_upload_watermark() {
+    let state = test_state_with("upload-watermark", |state| {
+        state.categories.get_mut("pic").unwrap().watermark = Some(Arc::new(Watermark {
+            logo: image::RgbaImage::from_pixel(8, 8, image::Rgba([255, 0, 0, 255])),
+            position: WatermarkPosition::BottomRight,
+            opacity: 1.0,
+        }));
+    });
+    let app = test_app(&state);
+
+    let mut png = std::io::Cursor::new(vec![]);
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+        64,
+        64,
+        image::Rgb([255, 255, 255]),
+    ))
+    .write_to(&mut png, image::ImageFormat::Png)
+    .unwrap();
+    let png = png.into_inner();
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("w.png", "image/png", &png)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let stored = std::fs::read(uri_concat!(&state.pic_directory, "asset", "pic", "w.png")).unwrap();
+    let stored = image::load_from_memory(&stored).unwrap().to_rgba8();
+
+    // inside the logo, 8 pixels off the corner
+    assert_eq!(stored.get_pixel(52, 52), &image::Rgba([255, 0, 0, 255]));
+    assert_eq!(stored.get_pixel(0, 0), &image::Rgba([255, 255, 255, 255]));
+    assert_eq!(stored.get_pixel(60, 60), &image::Rgba([255, 255, 255, 255]));
+
+    // not images are stored as is
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=files",
+            &[("a.txt", "text/plain", b"hello")],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+}
+
+#[tokio::test]
+async fn test_errors_are_json() {
+    let state = test_state("errors-are-json");
+    let app = test_app(&state);
+
+    let (status, json) = send(&app, Request::get("/nowhere").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json["code"], 991, "{}", json);
+
+    // a mistyped route tells the right ones
+    let (status, json) = send(
+        &app,
+        Request::post("/picup/uplaod").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json["code"], 991, "{}", json);
+    assert!(json["data"]
+        .as_array()
+        .unwrap()
+        .contains(&Value::from("POST /picup/upload")));
+
+    let (status, json) = send(
+        &app,
+        Request::delete("/picup/version")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(json["code"], 992, "{}", json);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/asset/pic/missing.png")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json["code"], 1008, "{}", json);
+
+    // a rejection of the query extractor, with its explanation
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/asset/pic/missing.png?compress=lots")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["code"], 990, "{}", json);
+    assert!(
+        json["msg"].as_str().unwrap().contains("query string"),
+        "{}",
+        json
+    );
+}
+
+#[tokio::test]
+async fn test_keep_original() {
+    let state = test_state_with("keep-original", |state| {
+        state.categories.get_mut("pic").unwrap().keep_original = true;
+    });
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("r.jpg", "image/jpeg", &jpeg),
+                ("a.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (_, processed) = get_bytes(&app, "/picup/asset/pic/r.jpg").await;
+    assert_ne!(processed, jpeg);
+
+    let (status, original) =
+        get_bytes(&app, "/picup/asset/pic/r.jpg?original=1&access_token=baka").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(original, jpeg);
+
+    let (status, _) = get_bytes(&app, "/picup/asset/pic/r.jpg?original=1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // unprocessed ones are their own originals
+    assert!(!std::path::Path::new(&uri_concat!(
+        &state.pic_directory,
+        "original",
+        "pic",
+        "a.png"
+    ))
+    .exists());
+    let (status, original) =
+        get_bytes(&app, "/picup/asset/pic/a.png?original=1&access_token=baka").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(original, PNG_BYTES);
+}
+
+#[tokio::test]
+async fn test_filename_template() {
+    let state = test_state_with("filename-template", |state| {
+        state.categories.get_mut("pic").unwrap().filename_template =
+            Some(FilenameTemplate::parse("{category}-{uuid}{ext}").unwrap());
+        state.categories.get_mut("files").unwrap().filename_template =
+            Some(FilenameTemplate::parse("x_{name}{ext}").unwrap());
+    });
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("a.png", "image/png", PNG_BYTES),
+                ("a.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    // the same upload name doesn't collide
+    let first = json["data"][0]["url"].as_str().unwrap();
+    let second = json["data"][1]["url"].as_str().unwrap();
+    assert_ne!(first, second);
+    assert!(first.starts_with("http://127.0.0.1:19190/picup/asset/pic/pic-"));
+    assert!(first.ends_with(".png"));
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=files",
+            &[("../my file?.txt", "text/plain", b"hello")],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/files/x_.._my_file_.txt"
+    );
+
+    assert!(FilenameTemplate::parse("{nope}").is_err());
+    assert!(FilenameTemplate::parse("{name").is_err());
+    assert!(FilenameTemplate::parse("a/{name}").is_err());
+}
+
+#[tokio::test]
+async fn test_correct_extension() {
+    let state = test_state_with("correct-extension", |state| {
+        state.categories.get_mut("pic").unwrap().correct_extension = true;
+    });
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 1);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("mislabeled.png", "image/png", &jpeg),
+                ("a.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/mislabeled.jpg"
+    );
+    assert_eq!(json["data"][0]["submitted_name"], "mislabeled.png");
+    assert_eq!(json["data"][0]["stored_name"], "mislabeled.jpg");
+    assert_eq!(
+        json["data"][1]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/a.png"
+    );
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/asset/pic/mislabeled.jpg")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[CONTENT_TYPE], "image/jpeg");
+
+    let (status, _) = get_bytes(&app, "/picup/asset/pic/mislabeled.png").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // left as is unless turned on
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=files",
+            &[("mislabeled.png", "image/png", &jpeg)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/files/mislabeled.png"
+    );
+}
+
+/// Category, name, path and url of each upload the hook is called for.
+type Events = Arc<std::sync::Mutex<Vec<(String, String, String, String)>>>;
+
+struct Recorder(Events);
+
+impl UploadHook for Recorder {
+    fn on_uploaded<'a>(
+        &'a self,
+        category: &'a str,
+        name: &'a str,
+        path: &'a str,
+        url: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.0.lock().unwrap().push((
+                category.to_string(),
+                name.to_string(),
+                path.to_string(),
+                url.to_string(),
+            ));
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_upload_hook() {
+    let events = Events::default();
+
+    let state = test_state_with("upload-hook", |state| {
+        state.upload_hook = Some(Box::new(Recorder(events.clone())));
+    });
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+
+    let (category, name, path, url) = &events[0];
+    assert_eq!(category, "pic");
+    assert_eq!(name, "a.png");
+    assert_eq!(std::fs::read(path).unwrap(), PNG_BYTES);
+    assert_eq!(url, &json["data"][0]["url"]);
+}
+
+#[tokio::test]
+async fn test_embedded_server() {
+    let dir = temp_dir().join("picup-test-embedded-server");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let config = dir.join("picup-srv.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[server]\ntoken = \"baka\"\ndirectory = \"{}\"\n[server.categories]\npic = {{}}\n",
+            dir.join("data").display()
+        ),
+    )
+    .unwrap();
+
+    let events = Events::default();
+
+    let app = crate::Server::load(&config)
+        .await
+        .unwrap()
+        .upload_hook(Recorder(events.clone()))
+        .router();
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/a.png"
+    );
+
+    let path = {
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        events[0].2.clone()
+    };
+    assert_eq!(std::fs::read(path).unwrap(), PNG_BYTES);
+
+    let e = crate::Server::load(&dir.join("missing.toml"))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn test_hotlink() {
+    let state = test_state_with("hotlink", |state| {
+        state.categories.get_mut("pic").unwrap().hotlink =
+            Some(Hotlink::new(vec!["Example.com".to_string()], false));
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "a.png"),
+        PNG_BYTES,
+    )
+    .unwrap();
+
+    for (referer, status) in [
+        (Some("https://example.com/post/1"), StatusCode::OK),
+        (Some("http://img.example.com:8080/"), StatusCode::OK),
+        (Some("https://notexample.com/"), StatusCode::FORBIDDEN),
+        (Some("https://example.com.evil.org/"), StatusCode::FORBIDDEN),
+        (Some("garbage"), StatusCode::FORBIDDEN),
+        (None, StatusCode::FORBIDDEN),
+    ] {
+        let mut req = Request::get("/picup/asset/pic/a.png");
+
+        if let Some(referer) = referer {
+            req = req.header(REFERER, referer);
+        }
+
+        let res = app
+            .clone()
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status, "{:?}", referer);
+    }
+
+    // other categories are unprotected
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "files", "a.png"),
+        PNG_BYTES,
+    )
+    .unwrap();
+
+    let (status, _) = get_bytes(&app, "/picup/asset/files/a.png").await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(Hotlink::new(vec![], true).allows(None));
+}
+
+#[tokio::test]
+async fn test_cache_control() {
+    let (state, memory) = test_state_in_memory(|state| {
+        state.categories.get_mut("pic").unwrap().immutable = true;
+        state.categories.get_mut("files").unwrap().cache_control =
+            Some(HeaderValue::from_static("no-cache"));
+    });
+    let app = test_app(&state);
+
+    for category in ["pic", "files"] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                &format!("access_token=baka&category={}", category),
+                &[("a.png", "image/png", PNG_BYTES)],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", json);
+    }
+
+    for (uri, cache_control) in [
+        (
+            "/picup/asset/pic/a.png",
+            "public, max-age=31536000, immutable",
+        ),
+        ("/picup/asset/files/a.png", "no-cache"),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CACHE_CONTROL], cache_control, "{}", uri);
+    }
+
+    // assets of immutable categories are never replaced
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["code"], 1004, "{}", json);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=files&override=true",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    assert_eq!(
+        memory
+            .read(&state.asset_path("files", "a.png"))
+            .await
+            .unwrap(),
+        PNG_BYTES
+    );
+}
+
+#[tokio::test]
+async fn test_unchanged_upload() {
+    let (state, memory) = test_state_in_memory(|_| {});
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let hash = hash_index::sha256_hex(PNG_BYTES);
+    let etag = format!("\"{}\"", hash);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/asset/pic/a.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[ETAG], etag.as_str());
+
+    for (if_none_match, status) in [
+        (etag.as_str(), StatusCode::NOT_MODIFIED),
+        ("\"other\", W/\"x\"", StatusCode::OK),
+        ("*", StatusCode::NOT_MODIFIED),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/picup/asset/pic/a.png")
+                    .header(IF_NONE_MATCH, if_none_match)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status, "{}", if_none_match);
+    }
+
+    // the part header goes right after the content type
+    for (if_none_match, bytes, code) in [
+        (etag.as_str(), PNG_BYTES, 0),
+        // not what it claims, so it's a different file
+        (etag.as_str(), &png_of_size(2, 2)[..], 1004),
+        ("\"other\"", PNG_BYTES, 1004),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(upload_request(
+                "access_token=baka&category=pic",
+                &[(
+                    "a.png",
+                    &format!("image/png\r\nIf-None-Match: {}", if_none_match),
+                    bytes,
+                )],
+            ))
+            .await
+            .unwrap();
+        let unchanged = res.headers().get("x-picup-unchanged").cloned();
+        let json: Value =
+            serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(json["code"], code, "{}", json);
+
+        if code == 0 {
+            assert_eq!(unchanged.unwrap(), "a.png");
+            assert_eq!(
+                json["data"],
+                serde_json::json!([{
+                    "submitted_name": "a.png",
+                    "stored_name": "a.png",
+                    "url": "http://127.0.0.1:19190/picup/asset/pic/a.png",
+                    "action": "skipped",
+                }])
+            );
+        }
+    }
+
+    // hashed on demand if stored without one
+    memory
+        .write(&state.asset_path("files", "b.txt"), b"b".to_vec())
+        .await
+        .unwrap();
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/asset/files/b.txt")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers()[ETAG],
+        format!("\"{}\"", hash_index::sha256_hex(b"b")).as_str()
+    );
+}
+
+#[tokio::test]
+async fn test_override_policy() {
+    let (state, memory) = test_state_in_memory(|_| {});
+    let app = test_app(&state);
+
+    let other_png = png_of_size(2, 2);
+
+    for (policy, bytes, code, action, name) in [
+        ("never", PNG_BYTES, 0, "created", "a.png"),
+        ("never", PNG_BYTES, 1004, "", ""),
+        ("if_different", PNG_BYTES, 0, "deduplicated", "a.png"),
+        ("if_different", &other_png[..], 0, "overwritten", "a.png"),
+        ("always", &other_png[..], 0, "overwritten", "a.png"),
+        ("rename", PNG_BYTES, 0, "renamed", "a-1.png"),
+        ("rename", PNG_BYTES, 0, "renamed", "a-2.png"),
+        ("whatever", PNG_BYTES, 990, "", ""),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(upload_request(
+                &format!("access_token=baka&category=pic&override={}", policy),
+                &[("a.png", "image/png", bytes)],
+            ))
+            .await
+            .unwrap();
+        let actions = res.headers().get("x-picup-actions").cloned();
+        let json: Value =
+            serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(json["code"], code, "{}: {}", policy, json);
+
+        if code == 0 {
+            assert_eq!(actions.unwrap(), action, "{}", policy);
+            assert_eq!(
+                json["data"],
+                serde_json::json!([{
+                    "submitted_name": "a.png",
+                    "stored_name": name,
+                    "url": format!("http://127.0.0.1:19190/picup/asset/pic/{}", name),
+                    "action": action,
+                }])
+            );
+        }
+    }
+
+    assert_eq!(
+        memory
+            .read(&state.asset_path("pic", "a.png"))
+            .await
+            .unwrap(),
+        other_png
+    );
+}
+
+#[tokio::test]
+async fn test_upload_index() {
+    let state = test_state("upload-index");
+    let app = test_app(&state);
+
+    // the part header goes right after the content type
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=pic",
+            &[
+                ("b.png", "image/png\r\nX-Picup-Index: 1", PNG_BYTES),
+                ("a.png", "image/png\r\nX-Picup-Index: 0", PNG_BYTES),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-picup-index"], "1, 0");
+
+    // not listed unless every file has one
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[
+                ("a.png", "image/png\r\nX-Picup-Index: 0", PNG_BYTES),
+                ("b.png", "image/png", PNG_BYTES),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-picup-index").is_none());
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[("a.png", "image/png\r\nX-Picup-Index: first", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["code"], 1009, "{}", json);
+}
+
+#[tokio::test]
+async fn test_get_batch() {
+    let state = test_state("get-batch");
+    let app = test_app(&state);
+
+    for (name, bytes) in [("a.png", PNG_BYTES), ("b.txt", &b"bee"[..])] {
+        std::fs::write(
+            uri_concat!(&state.pic_directory, "asset", "pic", name),
+            bytes,
+        )
+        .unwrap();
+    }
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/category/pic/batch?files=b.txt,gone.png,a.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-picup-missing"], "gone.png");
+
+    let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/mixed; boundary=")
+        .unwrap();
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+    let mut expected = format!(
+        "--{0}\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"b.txt\"\r\n\r\nbee\r\n\
+         --{0}\r\nContent-Type: image/png\r\nContent-Disposition: attachment; filename=\"a.png\"\r\n\r\n",
+        boundary
+    )
+    .into_bytes();
+    expected.extend_from_slice(PNG_BYTES);
+    expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    assert_eq!(body, expected);
+
+    for (uri, status) in [
+        ("/picup/category/pic/batch?files=", StatusCode::BAD_REQUEST),
+        (
+            "/picup/category/pic/batch?files=..",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/picup/category/nope/batch?files=a.png",
+            StatusCode::BAD_REQUEST,
+        ),
+    ] {
+        let (actual, _) = get_bytes(&app, uri).await;
+        assert_eq!(actual, status, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_get_by_hash() {
+    let state = test_state("get-by-hash");
+    let app = test_app(&state);
+
+    for (category, name, bytes) in [
+        ("pic", "a.png", PNG_BYTES),
+        ("files", "b.txt", b"b".as_slice()),
+    ] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                &format!("access_token=baka&category={}", category),
+                &[(name, "image/png", bytes)],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", json);
+    }
+
+    let hash = hash_index::sha256_hex(PNG_BYTES);
+    let other_hash = hash_index::sha256_hex(b"b");
+
+    for uri in [
+        format!("/picup/hash/pic/{}", hash),
+        format!("/picup/hash/pic/{}", &hash[0..8]),
+        format!("/picup/hash/pic/{}", hash.to_uppercase()),
+    ] {
+        let (status, body) = get_bytes(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(body, PNG_BYTES, "{}", uri);
+    }
+
+    let (status, body) = get_bytes(
+        &app,
+        &format!("/picup/hash/pic/{}?any_category=1", other_hash),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"b");
+
+    for (uri, status, code) in [
+        (
+            format!("/picup/hash/pic/{}", other_hash),
+            StatusCode::NOT_FOUND,
+            1008,
+        ),
+        (
+            format!("/picup/hash/pic/{}", &hash[0..7]),
+            StatusCode::BAD_REQUEST,
+            1009,
+        ),
+        (
+            "/picup/hash/pic/not-a-hash".to_string(),
+            StatusCode::BAD_REQUEST,
+            1009,
+        ),
+        (
+            format!("/picup/hash/nope/{}", hash),
+            StatusCode::NOT_FOUND,
+            1006,
+        ),
+    ] {
+        let (actual, json) = send(&app, Request::get(&uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(actual, status, "{}: {}", uri, json);
+        assert_eq!(json["code"], code, "{}: {}", uri, json);
+    }
+
+    // replaced by another content, which is what the hash finds then
+    let other_png = png_of_size(2, 2);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[("a.png", "image/png", &other_png)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (status, _) = get_bytes(&app, &format!("/picup/hash/pic/{}", hash)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = get_bytes(
+        &app,
+        &format!("/picup/hash/pic/{}", hash_index::sha256_hex(&other_png)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, other_png);
+
+    // found by the hash of the upload, not by that of the autorotated asset
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("r.jpg", "image/jpeg", &jpeg)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (_, stored) = get_bytes(&app, "/picup/asset/pic/r.jpg").await;
+    assert_ne!(stored, jpeg);
+
+    let (status, body) = get_bytes(
+        &app,
+        &format!("/picup/hash/pic/{}", hash_index::sha256_hex(&jpeg)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, stored);
+
+    let (status, _) = get_bytes(
+        &app,
+        &format!("/picup/hash/pic/{}", hash_index::sha256_hex(&stored)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_get_thumb() {
+    let state = test_state("get-thumb");
+    let app = test_app(&state);
+
+    for (name, bytes) in [("a.png", png_of_size(600, 300)), ("b.txt", b"bee".to_vec())] {
+        std::fs::write(
+            uri_concat!(&state.pic_directory, "asset", "pic", name),
+            bytes,
+        )
+        .unwrap();
+    }
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/thumb/pic/a.png")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[CONTENT_TYPE], "image/webp");
+    assert_eq!(res.headers()[CACHE_CONTROL], "public, max-age=1919810");
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        image::guess_format(&body).unwrap(),
+        image::ImageFormat::WebP
+    );
+
+    let thumb = image::load_from_memory(&body).unwrap();
+    assert_eq!((thumb.width(), thumb.height()), (256, 128));
+
+    for uri in [
+        "/picup/thumb/pic/gone.png",
+        "/picup/thumb/pic/b.txt",
+        "/picup/thumb/nope/a.png",
+    ] {
+        let (status, _) = get_bytes(&app, uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    // lossless unless the category sets a quality
+    assert_eq!(&body[12..16], b"VP8L");
+}
+
+#[tokio::test]
+async fn test_thumbnail_quality() {
+    let state = test_state_with("thumbnail-quality", |state| {
+        state.categories.get_mut("pic").unwrap().thumbnail_quality = Some(50);
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "a.png"),
+        png_of_size(600, 300),
+    )
+    .unwrap();
+
+    let (status, body) = get_bytes(&app, "/picup/thumb/pic/a.png").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[12..16], b"VP8 ");
+
+    let thumb = image::load_from_memory(&body).unwrap();
+    assert_eq!((thumb.width(), thumb.height()), (256, 128));
+}
+
+#[tokio::test]
+async fn test_file_names_stay_in_category() {
+    let state = test_state_with("file-names-stay-in-category", |state| {
+        state.scoped_tokens.push(ScopedToken {
+            name: "pic-writer".to_string(),
+            value: "pw".to_string(),
+            categories: Some(vec!["pic".to_string()]),
+            scopes: vec![Scope::Write],
+        });
+    });
+    let app = test_app(&state);
+
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "secret.png"),
+        png_of_size(2, 2),
+    )
+    .unwrap();
+
+    for uri in [
+        "/picup/asset/pic/..%2F..%2Fsecret.png",
+        "/picup/thumb/pic/..%2F..%2Fsecret.png",
+        "/picup/asset/pic/..",
+        "/picup/thumb/pic/..%5C..%5Csecret.png",
+        "/picup/meta/pic/..%2F..%2Fsecret.png",
+    ] {
+        let (status, json) = send(&app, Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}: {}", uri, json);
+        assert_eq!(json["code"], 1002, "{}: {}", uri, json);
+    }
+
+    // a token of `pic` can't write to another category through the name
+    for name in ["../files/x.png", "..\\files\\x.png", "..", "../../x.png"] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                "access_token=pw&category=pic",
+                &[(name, "image/png", PNG_BYTES)],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}: {}", name, json);
+        assert_eq!(json["code"], 1002, "{}: {}", name, json);
+    }
+
+    assert!(!std::path::Path::new(&state.asset_path("files", "x.png")).exists());
+    assert!(!std::path::Path::new(&uri_concat!(&state.pic_directory, "x.png")).exists());
+}
+
+const UNSAFE_SVG: &[u8] = br##"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY x "boom">]>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" onload="alert(1)">
+  <script>alert(2)</script>
+  <style>@import url(https://evil.org/a.css);</style>
+  <defs><rect id="r" width="4" height="4" fill="red"/></defs>
+  <use xlink:href="#r"/>
+  <use href="https://evil.org/b.svg#r"/>
+  <a href="javascript:alert(3)"><set attributeName="href" to="javascript:alert(4)"/><text>hi</text></a>
+  <foreignObject><div xmlns="http://www.w3.org/1999/xhtml">x</div></foreignObject>
+  <rect width="2" height="2" style="fill: url(#g)" onclick="alert(5)"/>
+</svg>"##;
+
+#[tokio::test]
+async fn test_svg() {
+    let state = test_state("svg");
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("evil.svg", "image/svg+xml", UNSAFE_SVG)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+    assert_eq!(json["code"], 1003, "{}", json);
+
+    let state = test_state_with("svg-allowed", |state| {
+        state.categories.get_mut("pic").unwrap().allow_svg = true;
+    });
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[("evil.svg", "image/svg+xml", UNSAFE_SVG)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::get("/picup/asset/pic/evil.svg")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[CONTENT_TYPE], "image/svg+xml");
+    assert!(res.headers()["content-security-policy"]
+        .to_str()
+        .unwrap()
+        .contains("default-src 'none'"));
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let sanitized = String::from_utf8(body.to_vec()).unwrap();
+
+    for gone in [
+        "onload",
+        "onclick",
+        "script",
+        "alert",
+        "evil.org",
+        "ENTITY",
+        "foreignObject",
+    ] {
+        assert!(!sanitized.contains(gone), "{} in {}", gone, sanitized);
+    }
+    for kept in [
+        r##"<use xlink:href="#r"/>"##,
+        "<text>hi</text>",
+        "url(#g)",
+        r#"<rect id="r""#,
+    ] {
+        assert!(sanitized.contains(kept), "{} not in {}", kept, sanitized);
+    }
+
+    // just a file where anything goes
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=files",
+            &[("evil.svg", "image/svg+xml", UNSAFE_SVG)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (_, stored) = get_bytes(&app, "/picup/asset/files/evil.svg").await;
+    assert_eq!(stored, UNSAFE_SVG);
+}
+
+#[tokio::test]
+async fn test_quality_clamped() {
+    let state = test_state_with("quality-clamped", |state| {
+        let pic = state.categories.get_mut("pic").unwrap();
+        pic.min_quality = Some(30);
+        pic.max_quality = Some(40);
+    });
+    let app = test_app(&state);
+
+    let jpeg = noise_jpeg(64, 64);
+
+    for (query, quality) in [
+        ("category=pic&compress=95", Some("40")),
+        ("category=pic&compress=10", Some("30")),
+        ("category=pic", Some("40")),
+        ("category=files&compress=95", Some("95")),
+        ("category=files", None),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(upload_request(
+                &format!("access_token=baka&override=true&{}", query),
+                &[("a.jpg", "image/jpeg", &jpeg)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK, "{}", query);
+        assert_eq!(
+            res.headers()
+                .get("x-picup-quality")
+                .map(|v| v.to_str().unwrap()),
+            quality,
+            "{}",
+            query
+        );
+    }
+
+    let (_, clamped) = get_bytes(&app, "/picup/asset/pic/a.jpg").await;
+    let (_, unclamped) = get_bytes(&app, "/picup/asset/files/a.jpg").await;
+    assert_eq!(unclamped, jpeg);
+    assert!(clamped.len() < jpeg.len());
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&compress=101",
+            &[("b.jpg", "image/jpeg", &jpeg)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["code"], 1009, "{}", json);
+}
+
+#[tokio::test]
+async fn test_default_compress() {
+    let state = test_state_with("default-compress", |state| {
+        let pic = state.categories.get_mut("pic").unwrap();
+        pic.default_compress = Some(50);
+        pic.max_quality = Some(40);
+        state.categories.get_mut("files").unwrap().default_compress = Some(60);
+    });
+    let app = test_app(&state);
+
+    let jpeg = noise_jpeg(64, 64);
+
+    for (query, quality) in [
+        ("category=files", "60"),
+        ("category=files&compress=90", "90"),
+        // the default is clamped as well
+        ("category=pic", "40"),
+    ] {
+        let res = app
+            .clone()
+            .oneshot(upload_request(
+                &format!("access_token=baka&override=true&{}", query),
+                &[("a.jpg", "image/jpeg", &jpeg)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK, "{}", query);
+        assert_eq!(res.headers()["x-picup-quality"], quality, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_blurhash() {
+    let state = test_state_with("blurhash", |state| {
+        for config in state.categories.values_mut() {
+            config.blurhash = true;
+        }
+    });
+    let app = test_app(&state);
+
+    let mut red = std::io::Cursor::new(vec![]);
+    image::RgbImage::from_pixel(8, 6, image::Rgb([255, 0, 0]))
+        .write_to(&mut red, image::ImageFormat::Png)
+        .unwrap();
+    let red = red.into_inner();
+
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=pic",
+            &[("red.png", "image/png", &red)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let blurhash = urlencoding::decode(res.headers()["x-picup-blurhash"].to_str().unwrap())
+        .unwrap()
+        .into_owned();
+
+    // 4x3 components, their maximum, the average color and the other 11 components
+    assert_eq!(blurhash.len(), 28, "{}", blurhash);
+    assert!(blurhash.starts_with('L'), "{}", blurhash);
+
+    // the average color, which the crate rounds channels of 0 up to 1 in
+    let dc = blurhash[2..6].bytes().fold(0, |value, digit| {
+        value * 83
+            + b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~"
+                .iter()
+                .position(|&b| b == digit)
+                .unwrap() as u32
+    });
+    assert_eq!(dc >> 16, 255, "{}", blurhash);
+    assert!((dc >> 8 & 0xff) <= 1 && (dc & 0xff) <= 1, "{}", blurhash);
+
+    // none for what isn't an image
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=files",
+            &[("a.txt", "text/plain", b"a"), ("b.png", "image/png", &red)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers()["x-picup-blurhash"],
+        format!(", {}", urlencoding::encode(&blurhash)).as_str()
+    );
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let blurhashes = json["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|upload| (upload["name"].as_str().unwrap(), upload["blurhash"].clone()))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(blurhashes["red.png"], blurhash.as_str());
+    assert_eq!(blurhashes["a.txt"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_list_recent() {
+    let state = test_state("list-recent");
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["data"], serde_json::json!([]));
+
+    for (category, name, uploaded) in [
+        ("pic", "a.png", 100),
+        ("files", "b.txt", 300),
+        ("pic", "c.png", 200),
+    ] {
+        let path = uri_concat!(&state.pic_directory, "asset", category, name);
+        std::fs::write(&path, name).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(uploaded))
+            .unwrap();
+    }
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=baka&limit=2")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            {
+                "category": "files",
+                "name": "b.txt",
+                "url": "http://127.0.0.1:19190/picup/asset/files/b.txt",
+                "size": 5,
+                "uploaded": 300,
+            },
+            {
+                "category": "pic",
+                "name": "c.png",
+                "url": "http://127.0.0.1:19190/picup/asset/pic/c.png",
+                "size": 5,
+                "uploaded": 200,
+            },
+        ])
+    );
+
+    for query in ["access_token=nope", "access_token=baka&limit=0"] {
+        let (status, _) = send(
+            &app,
+            Request::get(format!("/picup/recent?{}", query))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_decode_cache() {
+    // fits one 40x20 rgb image of 2400 bytes
+    let state = test_state_with("decode-cache", |state| {
+        state.decode_cache = Some(DecodeCache::new(3000));
+    });
+    let app = test_app(&state);
+
+    for name in ["a.png", "b.png"] {
+        std::fs::write(
+            uri_concat!(&state.pic_directory, "asset", "pic", name),
+            png_of_size(40, 20),
+        )
+        .unwrap();
+    }
+    std::fs::write(
+        uri_concat!(&state.pic_directory, "asset", "pic", "large.png"),
+        png_of_size(100, 100),
+    )
+    .unwrap();
+
+    let cache = state.decode_cache.as_ref().unwrap();
+
+    // later sizes reuse the decode
+    for query in ["w=10", "w=20", "h=4"] {
+        let (status, resized) = get_bytes(&app, &format!("/picup/asset/pic/a.png?{}", query)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(image::load_from_memory(&resized).unwrap().width() <= 20);
+        assert_eq!(cache.len(), 1);
+    }
+
+    // a.png is dropped for b.png
+    let (status, _) = get_bytes(&app, "/picup/asset/pic/b.png?w=10").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cache.len(), 1);
+
+    // too large to be cached at all
+    let (status, resized) = get_bytes(&app, "/picup/asset/pic/large.png?w=10").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(image::load_from_memory(&resized).unwrap().width(), 10);
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test]
+async fn test_auto_create_categories() {
+    let state = test_state_with("auto-create-categories", |state| {
+        state.default_category = state.categories.remove("files");
+        state.categories.get_mut("pic").unwrap().max_file_size = Some(PNG_BYTES.len() as u64);
+    });
+    let app = test_app(&state);
+
+    // not found until something is uploaded to it
+    let (status, _) = send(
+        &app,
+        Request::get("/picup/asset/alice/a.txt")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=alice",
+            &[("a.txt", "text/plain", b"hello")],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/alice/a.txt"
+    );
+
+    let (status, body) = get_bytes(&app, "/picup/asset/alice/a.txt").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"hello");
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/categories?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            {
+                "name": "alice",
+                "allow_all_files": true,
+                "max_size": 0,
+                "allowed_extensions": null,
+            },
+            {
+                "name": "pic",
+                "allow_all_files": false,
+                "max_size": PNG_BYTES.len(),
+                "allowed_extensions": ["bmp", "gif", "jpeg", "jpg", "png", "webp"],
+            },
+        ])
+    );
+
+    // told as it's enforced
+    for (bytes, code) in [(PNG_BYTES.to_vec(), 0), (png_of_size(2, 2), 1011)] {
+        let (_, json) = send(
+            &app,
+            upload_request(
+                "access_token=baka&category=pic&override=true",
+                &[("a.png", "image/png", &bytes)],
+            ),
+        )
+        .await;
+        assert_eq!(json["code"], code, "{}", json);
+    }
+
+    for category in ["..", ".hidden", "a%2Fb", ""] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                &format!("access_token=baka&category={}", category),
+                &[("a.txt", "text/plain", b"hello")],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", category);
+        assert_eq!(json["code"], 1006, "{}", category);
+    }
+}
+
+#[test]
+fn test_config_formats() {
+    use std::path::Path;
+
+    use crate::config::{self, Format};
+
+    let toml = include_str!("../picup-srv.toml");
+    let from_toml = config::parse(toml, Format::detect(Path::new("picup-srv.toml"), toml)).unwrap();
+    assert_eq!(from_toml.port, 19190);
+    assert_eq!(from_toml.read_timeout(), 30);
+    assert!(from_toml.image.autorotate);
+    assert!(from_toml.categories["pic"].allow_empty_referer);
+
+    // the same config written as json
+    let json = serde_json::to_string_pretty(&toml.parse::<toml::Table>().unwrap()).unwrap();
+    let from_json =
+        config::parse(&json, Format::detect(Path::new("picup-srv.json"), &json)).unwrap();
+    assert_eq!(from_json, from_toml);
+
+    // by content without a known extension
+    assert_eq!(
+        Format::detect(Path::new("picup-srv.conf"), &json),
+        Format::Json
+    );
+    assert_eq!(
+        Format::detect(Path::new("picup-srv.conf"), toml),
+        Format::Toml
+    );
+    assert_eq!(
+        Format::detect(Path::new("picup-srv.toml"), "{"),
+        Format::Toml
+    );
+
+    // the former `timeout` of all requests is still that of those not set
+    let timeouts = |keys: &str| {
+        let toml = format!(
+            "[server]\ntoken = \"baka\"\n{}\n[server.categories]\npic = {{}}\n",
+            keys
+        );
+        let config = config::parse(&toml, Format::Toml).unwrap();
+        (config.read_timeout(), config.upload_timeout())
+    };
+    assert_eq!(timeouts(""), (30, 300));
+    assert_eq!(timeouts("timeout = 3000"), (3000, 3000));
+    assert_eq!(
+        timeouts("timeout = 3000\nread_timeout = 10\nupload_timeout = 600"),
+        (10, 600)
+    );
+    assert_eq!(from_toml.upload_timeout(), 3000);
+
+    // misspelled keys aren't left to their defaults
+    for keys in [
+        "upload_timout = 600\n[server.categories]\npic = {}",
+        "[server.categories]\npic = { allow_all_file = true }",
+        "[server.image]\nautorotat = false\n[server.categories]\npic = {}",
+    ] {
+        let toml = format!("[server]\ntoken = \"baka\"\n{}\n", keys);
+        let e = config::parse(&toml, Format::Toml).unwrap_err();
+        assert!(e.contains("unknown field"), "{}", e);
+    }
+
+    // other tables of the file are someone else's
+    assert!(config::parse(&format!("{}\n[client]\nurl = \"a\"\n", toml), Format::Toml).is_ok());
+
+    assert!(config::parse("{\"server\": ", Format::Json).is_err());
+    assert!(config::parse("[server", Format::Toml).is_err());
+}
+
+#[test]
+fn test_client_ip() {
+    let trust = ProxyTrust::new(&["127.0.0.1".to_string(), "10.0.0.0/8".to_string()]).unwrap();
+
+    let client_ip = |peer: &str, headers: &[(&str, &str)]| {
+        let mut map = axum::http::HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        trust.client_ip(peer.parse().unwrap(), &map).to_string()
+    };
+
+    // whoever isn't a proxy may claim anything
+    assert_eq!(
+        client_ip("203.0.113.9", &[("x-forwarded-for", "1.1.1.1")]),
+        "203.0.113.9"
+    );
+
+    assert_eq!(client_ip("127.0.0.1", &[]), "127.0.0.1");
+    assert_eq!(
+        client_ip("127.0.0.1", &[("x-real-ip", "198.51.100.7")]),
+        "198.51.100.7"
+    );
+
+    // the client put the first one there itself
+    assert_eq!(
+        client_ip(
+            "127.0.0.1",
+            &[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.1.2.3")]
+        ),
+        "198.51.100.7"
+    );
+    assert_eq!(
+        client_ip(
+            "::ffff:127.0.0.1",
+            &[
+                ("x-forwarded-for", "198.51.100.7"),
+                ("x-forwarded-for", "10.1.2.3")
+            ]
+        ),
+        "198.51.100.7"
+    );
+    assert_eq!(
+        client_ip("127.0.0.1", &[("x-forwarded-for", "10.9.9.9, 10.1.2.3")]),
+        "10.9.9.9"
+    );
+    assert_eq!(
+        client_ip("127.0.0.1", &[("x-forwarded-for", "garbage, 10.1.2.3")]),
+        "127.0.0.1"
+    );
+
+    assert!(ProxyTrust::new(&["10.0.0.0/33".to_string()]).is_err());
+    assert!(ProxyTrust::new(&["localhost".to_string()]).is_err());
+}
+
+/// Serves `/a.png`, `/large.png` past the size limit of [`test_state`], and `/a.txt` on loopback,
+/// along with `/moved` redirecting to `/a.png` and `/elsewhere` to it on `127.0.0.2`.
+async fn remote_images() -> String {
+    use axum::{response::Redirect, routing::get};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let remote = Router::new()
+        .route("/a.png", get(|| async { PNG_BYTES }))
+        .route("/large.png", get(|| async { vec![0u8; 2 << 20] }))
+        .route("/a.txt", get(|| async { "hello" }))
+        .route("/moved", get(|| async { Redirect::temporary("/a.png") }))
+        .route(
+            "/elsewhere",
+            get(move || async move {
+                Redirect::temporary(&format!("http://127.0.0.2:{}/a.png", addr.port()))
+            }),
+        );
+
+    tokio::spawn(async move { axum::serve(listener, remote).await.unwrap() });
+
+    format!("http://{}", addr)
+}
+
+fn url_upload_request(token: &str, body: Value) -> Request<Body> {
+    Request::post(format!("/picup/upload/url?access_token={}", token))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_upload_urls() {
+    let remote = remote_images().await;
+
+    let state = test_state("upload-urls");
+    let app = test_app(&state);
+
+    for r#override in [false, true] {
+        let (status, json) = send(
+            &app,
+            url_upload_request(
+                "baka",
+                serde_json::json!({
+                    "category": "pic",
+                    "urls": [format!("{}/a.png?v=1", remote)],
+                    "override": r#override,
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", json);
+        assert_eq!(json["data"][0]["submitted_name"], "a.png");
+        assert_eq!(
+            json["data"][0]["url"],
+            "http://127.0.0.1:19190/picup/asset/pic/a.png"
+        );
+    }
+
+    let (status, body) = get_bytes(&app, "/picup/asset/pic/a.png").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, PNG_BYTES);
+
+    let cases = [
+        ("baka!", format!("{}/a.png", remote), 1001),
+        ("baka", format!("{}/a.png", remote), 1004),
+        ("baka", "not a url".to_string(), 1009),
+        ("baka", format!("{}/large.png", remote), 1011),
+        ("baka", format!("{}/missing.png", remote), 1005),
+        ("baka", format!("{}/a.txt", remote), 1003),
+    ];
+
+    for (token, url, code) in cases {
+        let (status, json) = send(
+            &app,
+            url_upload_request(
+                token,
+                serde_json::json!({ "category": "pic", "urls": [url] }),
+            ),
+        )
+        .await;
+        assert!(!status.is_success(), "{}", url);
+        assert_eq!(json["code"], code, "{}: {}", url, json);
+    }
+
+    // nothing but the first upload is stored
+    let (status, _) = get_bytes(&app, "/picup/asset/pic/large.png").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for (url, name) in [
+        ("http://a.org/b/c.png?d=1", "c.png"),
+        ("http://a.org/b/", "b"),
+        ("http://a.org/", "image"),
+        ("http://a.org/b/%2E%2E", "image"),
+        ("http://a.org/b/%2e.", "image"),
+        ("http://a.org/b/..%2F..%5Cc.png", ".._.._c.png"),
+        ("http://a.org/b/%ff", "%ff"),
+    ] {
+        let parsed = reqwest::Url::parse(url).unwrap();
+        assert_eq!(crate::url_fetch::file_name(&parsed), name, "{}", url);
+    }
+}
+
+#[tokio::test]
+async fn test_url_fetch_blocked() {
+    let remote = remote_images().await;
+    let port = remote.rsplit(':').next().unwrap().to_string();
+
+    let state = test_state_with("url-fetch-blocked", |state| {
+        state.url_fetcher = UrlFetcher::new(
+            Duration::from_secs(5),
+            1 << 20,
+            Allowlist::new(&["127.0.0.1".to_string()]).unwrap(),
+        );
+    });
+    let app = test_app(&state);
+
+    let upload = |url: String| {
+        url_upload_request(
+            "baka",
+            serde_json::json!({ "category": "pic", "urls": [url] }),
+        )
+    };
+
+    // allowed, redirects within it as well
+    for path in ["a.png", "moved"] {
+        let (status, json) = send(&app, upload(format!("{}/{}", remote, path))).await;
+        assert_eq!(status, StatusCode::OK, "{}: {}", path, json);
+    }
+
+    let blocked = [
+        "file:///etc/passwd".to_string(),
+        "ftp://127.0.0.1/a.png".to_string(),
+        format!("http://127.0.0.2:{}/a.png", port),
+        format!("http://[::1]:{}/a.png", port),
+        format!("http://[::ffff:127.0.0.2]:{}/a.png", port),
+        "http://169.254.169.254/latest/meta-data".to_string(),
+        "http://10.0.0.1/a.png".to_string(),
+        format!("http://0.0.0.0:{}/a.png", port),
+        format!("http://[64:ff9b::127.0.0.2]:{}/a.png", port),
+        format!("http://[2002:7f00:2::]:{}/a.png", port),
+        "http://192.0.0.170/a.png".to_string(),
+        format!("{}/elsewhere", remote),
+    ];
+
+    for url in blocked {
+        let (status, json) = send(&app, upload(url.clone())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}: {}", url, json);
+        assert_eq!(json["code"], 1016, "{}: {}", url, json);
+    }
+
+    // resolved before it's connected to
+    let (status, json) = send(
+        &test_app(&test_state_with("url-fetch-blocked-names", |state| {
+            state.url_fetcher = UrlFetcher::new(
+                Duration::from_secs(5),
+                1 << 20,
+                Allowlist::new(&[]).unwrap(),
+            );
+        })),
+        upload(format!("http://localhost:{}/a.png", port)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", json);
+    assert_eq!(json["code"], 1016, "{}", json);
+
+    assert!(Allowlist::new(&["10.0.0.0/33".to_string()]).is_err());
+
+    for (ip, internal) in [
+        ("192.0.0.8", true),
+        ("198.18.0.1", true),
+        ("198.19.255.255", true),
+        ("198.20.0.1", false),
+        ("240.0.0.1", true),
+        ("255.255.255.255", true),
+        ("8.8.8.8", false),
+        ("64:ff9b::10.0.0.1", true),
+        ("64:ff9b::8.8.8.8", false),
+        ("64:ff9b:1::8.8.8.8", true),
+        ("2002:c0a8:101::1", true),
+        ("2002:808:808::1", false),
+        ("::ffff:169.254.169.254", true),
+        ("2606:4700::1111", false),
+    ] {
+        assert_eq!(
+            crate::url_fetch::is_internal(ip.parse().unwrap()),
+            internal,
+            "{}",
+            ip
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_metadata() {
+    let state = test_state("metadata");
+    let app = test_app(&state);
+
+    let (status, json) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic",
+            &[
+                (
+                    "a.png",
+                    "image/png\r\nX-Picup-Metadata: alt=A%20cat&caption=%E7%8C%AB",
+                    PNG_BYTES,
+                ),
+                ("b.png", "image/png", PNG_BYTES),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/meta/pic/a.png")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/a.png"
+    );
+    assert_eq!(json["data"]["size"], PNG_BYTES.len());
+    assert_eq!(
+        json["data"]["metadata"],
+        serde_json::json!({ "alt": "A cat", "caption": "猫" })
+    );
+
+    let (_, json) = send(
+        &app,
+        Request::get("/picup/recent?access_token=baka")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    let recent = json["data"].as_array().unwrap();
+    assert_eq!(recent.len(), 2);
+    for upload in recent {
+        match upload["name"].as_str().unwrap() {
+            "a.png" => assert_eq!(upload["metadata"]["alt"], "A cat"),
+            _ => assert!(upload.get("metadata").is_none(), "{}", upload),
+        }
+    }
+
+    // kept for the same content unless given again
+    let (status, _) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=if_different",
+            &[("a.png", "image/png", PNG_BYTES)],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        metadata::stored(&state, "pic", "a.png").await["alt"],
+        "A cat"
+    );
+
+    let (status, _) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=if_different",
+            &[(
+                "a.png",
+                "image/png\r\nX-Picup-Metadata: alt=A%20dog",
+                PNG_BYTES,
+            )],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        metadata::stored(&state, "pic", "a.png").await,
+        picup_lib::Metadata::from([("alt".to_string(), "A dog".to_string())])
+    );
+
+    // dropped along with the file it was given with
+    let (status, _) = send(
+        &app,
+        upload_request(
+            "access_token=baka&category=pic&override=true",
+            &[("a.png", "image/png", b"\x89PNG\r\n\x1a\nother")],
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(metadata::stored(&state, "pic", "a.png").await.is_empty());
+
+    let long = format!("alt={}", "a".repeat(metadata::MAX_SIZE));
+
+    for header in ["al%20t=x", "alt=x&alt=y", "=x", "alt=%FF", long.as_str()] {
+        let (status, json) = send(
+            &app,
+            upload_request(
+                "access_token=baka&category=pic",
+                &[(
+                    "c.png",
+                    &format!("image/png\r\nX-Picup-Metadata: {}", header),
+                    PNG_BYTES,
+                )],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", header);
+        assert_eq!(json["code"], 1009, "{}: {}", header, json);
+    }
+
+    // as the client sends it
+    let given = picup_lib::Metadata::from([
+        ("caption".to_string(), "a=b&c, 100%".to_string()),
+        ("author".to_string(), String::new()),
+    ]);
+    assert_eq!(
+        metadata::parse(&picup_lib::encode_metadata(&given)).unwrap(),
+        given
+    );
+
+    for path in ["/picup/meta/pic/c.png", "/picup/meta/nope/a.png"] {
+        let (status, _) = send(&app, Request::get(path).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_commit_across_devices() {
+    #[cfg(unix)]
+    assert!(crate::is_cross_device(&std::io::Error::from_raw_os_error(
+        libc::EXDEV
+    )));
+    assert!(!crate::is_cross_device(&std::io::Error::from(
+        std::io::ErrorKind::NotFound
+    )));
+
+    let dir = temp_dir().join("picup-test-commit-across-devices");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let from = dir.join("temp.png").to_str().unwrap().to_string();
+    let to = dir.join("a.png").to_str().unwrap().to_string();
+
+    // what a rename failing with EXDEV falls back to
+    std::fs::write(&from, PNG_BYTES).unwrap();
+    std::fs::write(&to, b"replaced").unwrap();
+    crate::copy_then_remove(&from, &to).await.unwrap();
+    assert_eq!(std::fs::read(&to).unwrap(), PNG_BYTES);
+    assert!(!std::path::Path::new(&from).exists());
+    assert!(!std::path::Path::new(&format!("{}.part", to)).exists());
+
+    // nothing is touched if it can't be copied
+    assert!(crate::copy_then_remove(&from, &to).await.is_err());
+    assert_eq!(std::fs::read(&to).unwrap(), PNG_BYTES);
+
+    // for real where /dev/shm is a file system of its own, as in most containers
+    let shm = std::path::Path::new("/dev/shm");
+    if shm.is_dir() {
+        let from = shm.join("picup-test-commit-across-devices.png");
+        std::fs::write(&from, b"from shm").unwrap();
+
+        crate::commit_file(from.to_str().unwrap(), &to)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&to).unwrap(), b"from shm");
+        assert!(!from.exists());
+    }
+}
+
+#[tokio::test]
+async fn test_async_processing() {
+    let state = test_state_with("async-processing", |state| {
+        let pic = state.categories.get_mut("pic").unwrap();
+        pic.async_processing = true;
+        pic.keep_original = true;
+        pic.blurhash = true;
+    });
+    let app = test_app(&state);
+
+    let jpeg = jpeg_with_orientation(4, 2, 6);
+
+    let res = app
+        .clone()
+        .oneshot(upload_request(
+            "access_token=baka&category=pic",
+            &[("r.jpg", "image/jpeg", &jpeg)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    let job = res.headers()[picup_lib::JOB_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        json["data"][0]["url"],
+        "http://127.0.0.1:19190/picup/asset/pic/r.jpg"
+    );
+
+    let mut status = Value::Null;
+
+    for _ in 0..100 {
+        let (code, json) = send(
+            &app,
+            Request::get(format!("/picup/job/{}", job))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK, "{}", json);
+        assert_eq!(json["data"]["category"], "pic");
+
+        status = json["data"]["status"].clone();
+
+        if status != "processing" {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(status, "done");
+
+    let (_, stored) = get_bytes(&app, "/picup/asset/pic/r.jpg").await;
+    let stored = image::load_from_memory(&stored).unwrap();
+    assert_eq!((stored.width(), stored.height()), (2, 4));
+
+    let (_, original) =
+        get_bytes(&app, "/picup/asset/pic/r.jpg?original=1&access_token=baka").await;
+    assert_eq!(original, jpeg);
+
+    let (_, json) = send(
+        &app,
+        Request::get("/picup/meta/pic/r.jpg")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert!(json["data"]["blurhash"].is_string(), "{}", json);
+
+    let (status, json) = send(
+        &app,
+        Request::get("/picup/job/unknown")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json["code"], 991);
+}
+
+#[tokio::test]
+async fn test_reprocess_category() {
+    let state = test_state_with("reprocess-category", |state| {
+        let pic = state.categories.get_mut("pic").unwrap();
+        pic.thumbnail_size = 8;
+        pic.blurhash = true;
+    });
+    let app = test_app(&state);
+
+    // stored before the category made thumbnails of that size or computed placeholders
+    std::fs::write(state.asset_path("pic", "a.png"), png_of_size(32, 16)).unwrap();
+    std::fs::write(state.asset_path("pic", "b.txt"), b"not an image").unwrap();
+
+    let reprocess = |query: &str| {
+        Request::post(format!("/picup/category/{}", query))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (_, json) = send(&app, reprocess("pic/reprocess")).await;
+    assert_eq!(json["code"], 1001);
+
+    let (_, json) = send(&app, reprocess("nope/reprocess?access_token=baka")).await;
+    assert_eq!(json["code"], 1006);
+
+    let (status, json) = send(&app, reprocess("pic/reprocess?access_token=baka")).await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", json);
+    assert_eq!(
+        json["data"]["urls"],
+        serde_json::json!([
+            "http://127.0.0.1:19190/picup/asset/pic/a.png",
+            "http://127.0.0.1:19190/picup/asset/pic/b.txt"
+        ])
+    );
+
+    let job = json["data"]["id"].as_str().unwrap().to_string();
+
+    let mut info = Value::Null;
+
+    for _ in 0..100 {
+        let (_, json) = send(
+            &app,
+            Request::get(format!("/picup/job/{}", job))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        info = json["data"].clone();
+
+        if info["status"] != "processing" {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(info["status"], "done", "{}", info);
+    assert_eq!(info["done"], 2);
+
+    // only the asset being reprocessed is served uncached meanwhile
+    let jobs = Jobs::default();
+    let job = jobs.start_unmarked("pic", &[("a.png".to_string(), String::new())]);
+    assert!(!jobs.is_processing("pic", "a.png"));
+    jobs.mark("pic", "a.png");
+    assert!(jobs.is_processing("pic", "a.png"));
+    jobs.unmark("pic", "a.png");
+    assert!(!jobs.is_processing("pic", "a.png"));
+    jobs.finish(&job, None);
+
+    let thumb = uri_concat!(&state.pic_directory, "cache", "pic", "thumb-8", "a.png");
+    let thumb = image::load_from_memory(&std::fs::read(thumb).unwrap()).unwrap();
+    assert_eq!((thumb.width(), thumb.height()), (8, 4));
+
+    let (_, json) = send(
+        &app,
+        Request::get("/picup/meta/pic/a.png")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert!(json["data"]["blurhash"].is_string(), "{}", json);
+}
+
+#[tokio::test]
+async fn test_list_category() {
+    let state = test_state("list-category");
+    let app = test_app(&state);
+
+    let list = |query: &str| Request::get(format!("/picup/category/{}", query)).body(Body::empty());
+
+    let (status, json) = send(&app, list("pic?access_token=baka").unwrap()).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["data"], serde_json::json!([]));
+
+    // nothing uploaded yet, or removed by hand
+    std::fs::remove_dir(uri_concat!(&state.pic_directory, "asset", "files")).unwrap();
+    let (status, json) = send(&app, list("files?access_token=baka").unwrap()).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(json["data"], serde_json::json!([]));
+
+    for name in ["c.png", "a b.png", "b.png"] {
+        std::fs::write(
+            uri_concat!(&state.pic_directory, "asset", "pic", name),
+            name,
+        )
+        .unwrap();
+    }
+
+    let (status, json) = send(&app, list("pic?access_token=baka").unwrap()).await;
+    assert_eq!(status, StatusCode::OK, "{}", json);
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            "http://127.0.0.1:19190/picup/asset/pic/a%20b.png",
+            "http://127.0.0.1:19190/picup/asset/pic/b.png",
+            "http://127.0.0.1:19190/picup/asset/pic/c.png",
+        ])
+    );
+
+    let (_, json) = send(&app, list("pic?access_token=baka&page=2&limit=2").unwrap()).await;
+    assert_eq!(
+        json["data"],
+        serde_json::json!(["http://127.0.0.1:19190/picup/asset/pic/c.png"])
+    );
+
+    let (_, json) = send(&app, list("pic?access_token=baka&page=3&limit=2").unwrap()).await;
+    assert_eq!(json["data"], serde_json::json!([]));
+
+    let (status, json) = send(&app, list("nope?access_token=baka").unwrap()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json["code"], 1006);
+
+    for query in [
+        "pic",
+        "pic?access_token=nope",
+        "pic?access_token=baka&limit=0",
+        "pic?access_token=baka&limit=1001",
+        "pic?access_token=baka&page=0",
+    ] {
+        let (status, _) = send(&app, list(query).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_throttle() {
+    let chunks =
+        futures_util::stream::iter((0..5).map(|_| Ok::<_, std::io::Error>(vec![0u8; 1000])));
+
+    let started = tokio::time::Instant::now();
+    let mut throttled = Box::pin(throttle::throttle(chunks, 1000));
+
+    let mut received = vec![];
+
+    while let Some(chunk) = throttled.next().await {
+        received.push((started.elapsed(), chunk.unwrap().len()));
+    }
+
+    // each chunk waits for the bytes before it to have been sent at 1000 bytes per second, the
+    // first one not at all
+    assert_eq!(received.len(), 5);
+
+    for (i, (elapsed, len)) in received.into_iter().enumerate() {
+        assert_eq!(len, 1000);
+        assert!(
+            elapsed >= Duration::from_secs(i as u64),
+            "chunk {} after {:?}",
+            i,
+            elapsed
+        );
+        assert!(
+            elapsed < Duration::from_secs(i as u64) + Duration::from_millis(100),
+            "chunk {} after {:?}",
+            i,
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use futures_util::{stream::unfold, Stream, StreamExt};
+use tokio::{
+    io,
+    time::{sleep_until, Instant},
+};
+
+/// Paces the chunks of `stream` so that it doesn't exceed `rate` bytes per second on average.
+///
+/// A chunk is held back until the bytes before it would have been sent at the rate, so the first
+/// chunk is never delayed.
+pub fn throttle<S, B>(stream: S, rate: u64) -> impl Stream<Item = io::Result<B>>
+where
+    S: Stream<Item = io::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    let started = Instant::now();
+
+    unfold((stream, 0u64), move |(mut stream, sent)| async move {
+        let chunk = stream.next().await?;
+
+        // due once the bytes before it have been sent at the rate
+        sleep_until(started + Duration::from_secs_f64(sent as f64 / rate as f64)).await;
+
+        let sent = match &chunk {
+            Ok(bytes) => sent + bytes.as_ref().len() as u64,
+            Err(_) => sent,
+        };
+
+        Some((chunk, (stream, sent)))
+    })
+}
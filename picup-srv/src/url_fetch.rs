@@ -0,0 +1,322 @@
+//! Remote images fetched by the server for uploads by url, limited in size and time, and kept
+//! away from internal addresses so that the server can't be used to probe its network.
+//!
+//! Hosts are resolved by the client through [`CheckedResolver`], so the addresses checked are the
+//! ones connected to, and each redirect is checked like the first url.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::body::Bytes;
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect, Client, Url,
+};
+use tokio::net::lookup_host;
+
+use crate::client_ip;
+
+/// Redirects followed at most, as browsers do.
+const MAX_REDIRECTS: usize = 10;
+
+pub enum FetchError {
+    /// not a url
+    Invalid(String),
+
+    /// not http(s), or of an internal address that isn't allowed
+    Blocked(String),
+
+    TooLarge,
+
+    Failed(String),
+}
+
+pub struct Fetched {
+    /// last segment of the path of the url
+    pub file_name: String,
+
+    /// sniffed from the content, or as the remote server tells
+    pub content_type: Option<String>,
+
+    pub bytes: Bytes,
+}
+
+pub struct UrlFetcher {
+    client: Client,
+
+    /// of each image
+    max_size: u64,
+
+    allowlist: Arc<Allowlist>,
+}
+
+/// Internal networks which may be fetched from, such as those of an intranet the server is meant
+/// to re-host images of.
+pub struct Allowlist {
+    /// networks as their first address and prefix length
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl Allowlist {
+    /// Fails with the first entry that is neither an address nor a network.
+    pub fn new(networks: &[String]) -> Result<Self, String> {
+        let networks = networks
+            .iter()
+            .map(|network| client_ip::network(network.trim()).ok_or_else(|| network.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Allowlist { networks })
+    }
+
+    fn blocks(&self, ip: IpAddr) -> bool {
+        is_internal(ip)
+            && !self
+                .networks
+                .iter()
+                .any(|network| client_ip::contains(*network, ip))
+    }
+
+    /// Whether the url may be fetched before its host is resolved, which is checked then.
+    fn check(&self, url: &Url) -> Result<(), Blocked> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Blocked(format!("{} is not http(s)", url)));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| Blocked(format!("no host in {}", url)))?;
+
+        // addresses aren't resolved, so they are checked here
+        let ip = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => ip,
+            Err(_) => return Ok(()),
+        };
+
+        if self.blocks(ip) {
+            return Err(Blocked(format!("{} is an internal address", url)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a url isn't fetched, passed through the errors of the client.
+#[derive(Debug)]
+struct Blocked(String);
+
+impl Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Blocked {}
+
+/// Resolves hosts for the client, failing for those with an address that is blocked.
+struct CheckedResolver {
+    allowlist: Arc<Allowlist>,
+}
+
+impl Resolve for CheckedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowlist = self.allowlist.clone();
+
+        Box::pin(async move {
+            // the client puts the port of the url in
+            let addrs = lookup_host((name.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+
+            if let Some(addr) = addrs.iter().find(|addr| allowlist.blocks(addr.ip())) {
+                return Err(Box::new(Blocked(format!(
+                    "{} resolves to the internal address {}",
+                    name.as_str(),
+                    addr.ip()
+                ))) as Box<dyn Error + Send + Sync>);
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The reason in the chain of errors of the client, if it failed because a url is blocked.
+fn blocked_by(e: &reqwest::Error) -> Option<String> {
+    let mut source = e.source();
+
+    while let Some(e) = source {
+        if let Some(blocked) = e.downcast_ref::<Blocked>() {
+            return Some(blocked.0.clone());
+        }
+
+        source = e.source();
+    }
+
+    None
+}
+
+/// Whether the address is of the host itself or its networks, rather than of the internet.
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+
+            v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                // "this network", which linux connects to the host itself
+                || octets[0] == 0
+                || v4.is_multicast()
+                // shared address space of carrier-grade nat
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+                // protocol assignments, such as the dns64 discovery and nat64 addresses
+                || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+                // benchmarking, routed within labs
+                || (octets[0] == 198 && octets[1] & 0xfe == 18)
+                // reserved, along with the broadcast address
+                || octets[0] & 0xf0 == 240
+        }
+        IpAddr::V6(v6) => match embedded_ipv4(v6) {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => {
+                v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    // unique local and link-local
+                    || v6.segments()[0] & 0xfe00 == 0xfc00
+                    || v6.segments()[0] & 0xffc0 == 0xfe80
+                    // nat64 of local use, whose gateways may reach anything
+                    || v6.segments()[..3] == [0x64, 0xff9b, 1]
+            }
+        },
+    }
+}
+
+/// The ipv4 address an ipv6 one reaches, mapped or through a nat64 gateway or a 6to4 relay.
+fn embedded_ipv4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = v6.segments();
+    let octets = v6.octets();
+
+    if let Some(v4) = v6.to_ipv4_mapped() {
+        return Some(v4);
+    }
+
+    // the well-known prefix of nat64, 64:ff9b::/96
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        ));
+    }
+
+    // 6to4, 2002::/16
+    if segments[0] == 0x2002 {
+        return Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]));
+    }
+
+    None
+}
+
+/// Name an image fetched from the url is stored under, before the templates of its category.
+pub fn file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|segments| segments.rev().find(|segment| !segment.is_empty()))
+        .map(|segment| {
+            urlencoding::decode(segment)
+                .map(|name| name.into_owned())
+                .unwrap_or_else(|_| segment.to_string())
+                // which were escaped in the segment
+                .replace(['/', '\\'], "_")
+        })
+        // such as `..` escaped, which would be a parent directory
+        .filter(|name| crate::is_plain_file_name(name))
+        .unwrap_or_else(|| "image".to_string())
+}
+
+impl UrlFetcher {
+    pub fn new(timeout: Duration, max_size: u64, allowlist: Allowlist) -> Self {
+        let allowlist = Arc::new(allowlist);
+
+        let redirects = allowlist.clone();
+
+        UrlFetcher {
+            client: Client::builder()
+                .timeout(timeout)
+                .redirect(redirect::Policy::custom(move |attempt| {
+                    if attempt.previous().len() >= MAX_REDIRECTS {
+                        return attempt.error(Blocked("too many redirects".to_string()));
+                    }
+
+                    match redirects.check(attempt.url()) {
+                        Ok(()) => attempt.follow(),
+                        Err(blocked) => attempt.error(blocked),
+                    }
+                }))
+                .dns_resolver(Arc::new(CheckedResolver {
+                    allowlist: allowlist.clone(),
+                }))
+                // the proxy would be resolved instead of the host
+                .no_proxy()
+                .build()
+                .unwrap(),
+            max_size,
+            allowlist,
+        }
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Fetched, FetchError> {
+        let url = Url::parse(url).map_err(|e| FetchError::Invalid(format!("{}: {}", url, e)))?;
+
+        self.allowlist
+            .check(&url)
+            .map_err(|blocked| FetchError::Blocked(blocked.0))?;
+
+        let failed = |e: reqwest::Error| match blocked_by(&e) {
+            Some(blocked) => FetchError::Blocked(blocked),
+            None => FetchError::Failed(format!("{}: {}", url, e)),
+        };
+
+        let mut res = self.client.get(url.clone()).send().await.map_err(failed)?;
+
+        if !res.status().is_success() {
+            return Err(FetchError::Failed(format!("{}: {}", url, res.status())));
+        }
+        if res.content_length().is_some_and(|len| len > self.max_size) {
+            return Err(FetchError::TooLarge);
+        }
+
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+
+        // the length may be missing or a lie
+        let mut bytes = vec![];
+
+        while let Some(chunk) = res.chunk().await.map_err(failed)? {
+            if bytes.len() as u64 + chunk.len() as u64 > self.max_size {
+                return Err(FetchError::TooLarge);
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Fetched {
+            file_name: file_name(&url),
+            content_type: picup_lib::sniff_mime(&bytes)
+                .map(str::to_owned)
+                .or(content_type),
+            bytes: Bytes::from(bytes),
+        })
+    }
+}